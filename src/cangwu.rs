@@ -3,69 +3,145 @@
 extern crate nalgebra as na;
 use na::DMatrix;
 
+use super::ratio::parse_as_vector;
 use super::temperament_class::{TemperamentClass, key_to_mapping};
-use super::uv::only_unison_vector;
+use super::uv::{
+    join_mappings, mapping_from_unison_vectors, meet_mappings,
+    only_unison_vector,
+};
+use super::weighting::Weighting;
 use super::{
-    Cents, ETMap, ETSlice, Exponent, Mapping, PrimeLimit, PriorityQueue, map,
-    mapping_from_name, normalize_positive, prime_mapping,
+    Cents, ETMap, ETSlice, Error, Exponent, Mapping, PrimeLimit,
+    PriorityQueue, map, mapping_from_name, normalize_positive, prime_mapping,
+    warted_et_name,
 };
+use std::borrow::Cow;
 use std::collections::HashSet;
 
-/// Temperament class with a prime limit but no tuning
+/// Temperament class with a prime limit but no tuning.
+/// CangwuTemperament<'static> owns its prime limit.
+#[derive(Clone, Debug)]
 pub struct CangwuTemperament<'a> {
-    plimit: &'a [Cents],
+    plimit: Cow<'a, [Cents]>,
     pub melody: Mapping,
 }
 
+/// Weighted by Tenney unless the weighting says otherwise.
+/// Cangwu badness, and so the temperament search, is always Tenney weighted.
 pub trait TenneyWeighted {
     fn mapping(&self) -> &Mapping;
     fn plimit(&self) -> &[Cents];
 
-    fn weighted_mapping(&self) -> DMatrix<f64> {
-        let melody = self.mapping();
-        let plimit = self.plimit();
-        weight_mapping(melody, plimit)
+    fn weighting(&self) -> &Weighting {
+        &TENNEY
     }
-}
 
-fn weight_mapping(mapping: &[ETMap], plimit: &[Cents]) -> DMatrix<f64> {
-    let rank = mapping.len();
-    let dimension = plimit.len();
-    let flattened = mapping.iter().flat_map(|m| m.iter()).cloned();
-    let mapping = DMatrix::from_iterator(dimension, rank, flattened);
-    let weighting_vec = map(|x| 1200.0 / x, plimit);
-    let mut weighting =
-        DMatrix::from_vec(dimension, 1, weighting_vec.clone());
-    debug_assert!(rank > 0);
-    for _ in 1..rank {
-        weighting.extend(weighting_vec.clone());
+    fn weighted_mapping(&self) -> DMatrix<f64> {
+        self.weighting().weight_mapping(self.mapping(), self.plimit())
     }
-    mapping.map(f64::from).component_mul(&weighting)
 }
 
+static TENNEY: Weighting = Weighting::Tenney;
+
 impl<'a> CangwuTemperament<'a> {
-    pub fn new(plimit: &'a [Cents], melody: &[ETMap]) -> Self {
+    pub fn new(
+        plimit: impl Into<Cow<'a, [Cents]>>,
+        melody: &[ETMap],
+    ) -> Self {
         let melody = melody.to_vec();
-        CangwuTemperament { plimit, melody }
+        CangwuTemperament { plimit: plimit.into(), melody }
+    }
+
+    /// Copy that borrows the prime limit from this one
+    pub fn borrowed(&self) -> CangwuTemperament<'_> {
+        CangwuTemperament::new(&*self.plimit, &self.melody)
+    }
+
+    /// Take ownership of the prime limit to lose the lifetime
+    pub fn into_owned(self) -> CangwuTemperament<'static> {
+        CangwuTemperament {
+            plimit: Cow::Owned(self.plimit.into_owned()),
+            melody: self.melody,
+        }
     }
 
     /// Turn an ET name like "12 & 19" into a temperament object
-    pub fn from_name(plimit: &'a PrimeLimit, name: &str) -> Option<Self> {
+    pub fn from_name(
+        plimit: &'a PrimeLimit,
+        name: &str,
+    ) -> Result<Self, Error> {
         mapping_from_name(plimit, name).map(|melody| CangwuTemperament {
-            plimit: &plimit.pitches,
+            plimit: Cow::Borrowed(&plimit.pitches),
             melody,
         })
     }
 
+    /// The temperament class that tempers out the unison vectors
+    /// (as prime exponents), with the mapping in Hermite normal form
+    pub fn from_unison_vectors(
+        plimit: impl Into<Cow<'a, [Cents]>>,
+        uvs: &[ETMap],
+    ) -> Result<Self, Error> {
+        let plimit = plimit.into();
+        let melody = mapping_from_unison_vectors(plimit.len(), uvs)?;
+        Ok(CangwuTemperament { plimit, melody })
+    }
+
+    /// The temperament class that tempers out ratios like "81/80"
+    pub fn from_ratios(
+        plimit: &'a PrimeLimit,
+        ratios: &[&str],
+    ) -> Result<Self, Error> {
+        let uvs = ratios
+            .iter()
+            .map(|ratio| parse_as_vector(plimit, ratio))
+            .collect::<Result<Mapping, _>>()?;
+        Self::from_unison_vectors(&plimit.pitches, &uvs)
+    }
+
+    /// Equal temperaments from the list, taken in order,
+    /// that together give exactly this temperament class
+    pub fn spanning_ets(&self, ets: &[ETMap]) -> Option<Mapping> {
+        let mut result = CangwuTemperament::new(&*self.plimit, &[]);
+        for et in ets {
+            if self.et_belongs(et) && !result.et_belongs(et) {
+                result.melody.push(et.clone());
+            }
+        }
+        (result.key().ok()? == self.key().ok()?).then_some(result.melody)
+    }
+
+    /// The temperament class that tempers out the unison vectors
+    /// of both classes, as a reduced mapping
+    pub fn join(&self, other: &CangwuTemperament) -> Result<Self, Error> {
+        if self.plimit != other.plimit {
+            return Err(Error::LimitMismatch);
+        }
+        let melody =
+            join_mappings(self.plimit.len(), &self.melody, &other.melody)?;
+        Ok(CangwuTemperament { plimit: self.plimit.clone(), melody })
+    }
+
+    /// The temperament class spanned by the vals of both classes,
+    /// as a reduced mapping
+    pub fn meet(&self, other: &CangwuTemperament) -> Result<Self, Error> {
+        if self.plimit != other.plimit {
+            return Err(Error::LimitMismatch);
+        }
+        let melody =
+            meet_mappings(self.plimit.len(), &self.melody, &other.melody)?;
+        Ok(CangwuTemperament { plimit: self.plimit.clone(), melody })
+    }
+
     /// Strange legacy method because the Python web app used to
     /// define things like this
     pub fn from_ets_and_key(
         plimit: &'a [Cents],
         ets: &ETSlice,
         key: &ETSlice,
-    ) -> Option<Self> {
+    ) -> Result<Self, Error> {
         let melody = vec![];
-        let mut result = CangwuTemperament { plimit, melody };
+        let mut result = CangwuTemperament { plimit: plimit.into(), melody };
         let tclass = Self::new(plimit, &key_to_mapping(plimit.len(), key)?);
         for &et in ets.iter() {
             for etmap in tclass.ets_of_size(et) {
@@ -77,7 +153,12 @@ impl<'a> CangwuTemperament<'a> {
                 }
             }
         }
-        if result.rank() == tclass.rank() { Some(result) } else { None }
+        let (expected, found) = (tclass.rank()?, result.rank()?);
+        if expected == found {
+            Ok(result)
+        } else {
+            Err(Error::RankMismatch { expected, found })
+        }
     }
 
     pub fn badness(&self, ek: Cents) -> Cents {
@@ -100,11 +181,12 @@ impl<'a> CangwuTemperament<'a> {
     /// Get equal temperaments of a specific size belonging to the class.
     /// If more than one match is legal, they might not all be found.
     pub fn ets_of_size(&self, size: Exponent) -> Mapping {
-        let pet = prime_mapping(self.plimit, size);
+        let pet = prime_mapping(&self.plimit, size);
         let ek = self.badness(0.0);
-        let mut bmax = Self::new(self.plimit, &[pet]).badness(ek);
+        let mut bmax =
+            CangwuTemperament::new(&*self.plimit, &[pet]).badness(ek);
         for _ in 0..100 {
-            let ets = limited_mappings(size, ek, bmax, self.plimit);
+            let ets = limited_mappings(size, ek, bmax, &self.plimit);
             if !ets.is_empty() {
                 return ets;
             }
@@ -122,7 +204,7 @@ impl<'a> CangwuTemperament<'a> {
         let dimension = self.plimit.len();
         let n_ets = n_results + 10;
         let seed_ets: Vec<ETMap> = filtered_equal_temperaments(
-            self.plimit,
+            &self.plimit,
             |et| !self.et_belongs(et),
             ek,
             n_ets,
@@ -130,7 +212,7 @@ impl<'a> CangwuTemperament<'a> {
         let mut rts = vec![self.melody.clone()];
         for _ in (rank + 1)..dimension {
             rts = higher_rank_search(
-                self.plimit,
+                &self.plimit,
                 &seed_ets,
                 &rts,
                 ek,
@@ -139,7 +221,7 @@ impl<'a> CangwuTemperament<'a> {
         }
         rts.iter()
             .filter_map(only_unison_vector)
-            .map(|uv| normalize_positive(self.plimit, uv))
+            .map(|uv| normalize_positive(&self.plimit, uv))
             .collect()
     }
 
@@ -151,7 +233,7 @@ impl<'a> CangwuTemperament<'a> {
     /// n_results: How many to return
     pub fn get_belonging_ets(&self, ek: Cents, n_results: usize) -> Mapping {
         filtered_equal_temperaments(
-            self.plimit,
+            &self.plimit,
             |mapping| self.et_belongs(mapping),
             ek,
             n_results,
@@ -171,10 +253,15 @@ impl TenneyWeighted for CangwuTemperament<'_> {
     }
 
     fn plimit(&self) -> &[Cents] {
-        self.plimit
+        &self.plimit
     }
 }
 
+/// The best temperament classes that come from adding
+/// one of the equal temperaments to one of the lower rank classes
+///
+/// With the "rayon" feature, candidates are calculated in parallel
+/// but the results are the same.
 pub fn higher_rank_search(
     plimit: &[Cents],
     ets: &[ETMap],
@@ -182,27 +269,117 @@ pub fn higher_rank_search(
     ek: Cents,
     n_results: usize,
 ) -> Vec<Mapping> {
+    watched_higher_rank_search(plimit, ets, rts, ek, n_results, |_, _| true)
+        .expect("Search stopped without being asked to")
+}
+
+/// higher_rank_search that calls watch(cap, candidates) as it goes,
+/// with the current badness cap and the number of candidates examined.
+/// If watch returns false, the search stops and returns None.
+pub(crate) fn watched_higher_rank_search(
+    plimit: &[Cents],
+    ets: &[ETMap],
+    rts: &[Mapping],
+    ek: Cents,
+    n_results: usize,
+    watch: impl FnMut(Cents, usize) -> bool,
+) -> Option<Vec<Mapping>> {
+    #[cfg(feature = "rayon")]
+    {
+        parallel_higher_rank_search(plimit, ets, rts, ek, n_results, watch)
+    }
+    #[cfg(not(feature = "rayon"))]
+    {
+        serial_higher_rank_search(plimit, ets, rts, ek, n_results, watch)
+    }
+}
+
+#[cfg_attr(all(feature = "rayon", not(test)), allow(dead_code))]
+fn serial_higher_rank_search(
+    plimit: &[Cents],
+    ets: &[ETMap],
+    rts: &[Mapping],
+    ek: Cents,
+    n_results: usize,
+    mut watch: impl FnMut(Cents, usize) -> bool,
+) -> Option<Vec<Mapping>> {
     let mut results = PriorityQueue::new(n_results);
     let mut cache = HashSet::new();
+    let mut candidates = 0;
     for rt in rts {
         let rank = rt.len() + 1;
         for et in ets {
             let mut new_rt = rt.clone();
             new_rt.push(et.clone());
             let rt_obj = CangwuTemperament::new(plimit, &new_rt);
-            if rt_obj.rank() == rank {
+            if rt_obj.rank() == Ok(rank) {
                 let badness = rt_obj.badness(ek);
-                if badness < results.cap {
-                    let key = rt_obj.key();
-                    if !cache.contains(&key) {
-                        cache.insert(key);
-                        results.push(badness, new_rt);
-                    }
+                // Anything too big for a key is too big to return
+                if badness < results.cap
+                    && let Ok(key) = rt_obj.key()
+                    && !cache.contains(&key)
+                {
+                    cache.insert(key);
+                    results.push(badness, new_rt);
                 }
             }
         }
+        candidates += ets.len();
+        if !watch(results.cap, candidates) {
+            return None;
+        }
     }
-    results.extract().collect()
+    Some(results.extract().collect())
+}
+
+/// Every candidate is scored in parallel, including the key,
+/// and then they go into the queue in the same order as
+/// the serial search so that ties and duplicates resolve the same way.
+/// Lower rank classes are taken a few at a time
+/// so that watch gets called now and then.
+#[cfg(feature = "rayon")]
+fn parallel_higher_rank_search(
+    plimit: &[Cents],
+    ets: &[ETMap],
+    rts: &[Mapping],
+    ek: Cents,
+    n_results: usize,
+    mut watch: impl FnMut(Cents, usize) -> bool,
+) -> Option<Vec<Mapping>> {
+    use rayon::prelude::*;
+    let mut results = PriorityQueue::new(n_results);
+    let mut cache = HashSet::new();
+    let mut candidates = 0;
+    for chunk in rts.chunks(rayon::current_num_threads().max(1)) {
+        let scored: Vec<(Cents, ETMap, Mapping)> = chunk
+            .par_iter()
+            .flat_map_iter(|rt| {
+                ets.iter().filter_map(move |et| {
+                    let mut new_rt = rt.clone();
+                    new_rt.push(et.clone());
+                    let rt_obj = CangwuTemperament::new(plimit, &new_rt);
+                    if rt_obj.rank() == Ok(rt.len() + 1) {
+                        let badness = rt_obj.badness(ek);
+                        let key = rt_obj.key().ok()?;
+                        Some((badness, key, new_rt))
+                    } else {
+                        None
+                    }
+                })
+            })
+            .collect();
+        for (badness, key, new_rt) in scored {
+            if badness < results.cap && !cache.contains(&key) {
+                cache.insert(key);
+                results.push(badness, new_rt);
+            }
+        }
+        candidates += chunk.len() * ets.len();
+        if !watch(results.cap, candidates) {
+            return None;
+        }
+    }
+    Some(results.extract().collect())
 }
 
 pub fn rms_of_matrix(a: &DMatrix<f64>) -> f64 {
@@ -226,6 +403,20 @@ pub fn get_equal_temperaments(
     ek: Cents,
     n_results: usize,
 ) -> Mapping {
+    watched_equal_temperaments(plimit, ek, n_results, |_, _, _| true)
+        .expect("Search stopped without being asked to")
+}
+
+/// get_equal_temperaments that calls watch(n_notes, cap, candidates)
+/// after each size of equal temperament, with the badness cap
+/// and the number of candidates examined so far.
+/// If watch returns false, the search stops and returns None.
+pub(crate) fn watched_equal_temperaments(
+    plimit: &[Cents],
+    ek: Cents,
+    n_results: usize,
+    mut watch: impl FnMut(Exponent, Cents, usize) -> bool,
+) -> Option<Mapping> {
     // Stop weird things happening for non-standard units
     let plimit = map(|p| 12e2 * (p / plimit[0]), plimit);
 
@@ -233,17 +424,22 @@ pub fn get_equal_temperaments(
     let bmax = preliminary_badness(&plimit, ek, n_results);
     let mut n_notes = 1;
     let mut cap = bmax;
+    let mut candidates = 0;
     while (f64::from(n_notes)) < cap / ek {
         for mapping in limited_mappings(n_notes, ek, cap, &plimit) {
             let bad = equal_temperament_badness(&plimit, ek, &mapping);
             results.push(bad, mapping.clone());
+            candidates += 1;
+        }
+        if !watch(n_notes, cap, candidates) {
+            return None;
         }
         n_notes += 1;
         cap = cap.min(results.cap);
     }
 
     debug_assert!(results.len() == n_results);
-    results.extract().collect()
+    Some(results.extract().collect())
 }
 
 /// Get the best equal temperament mappings for the given prime limit
@@ -295,6 +491,25 @@ pub fn filtered_equal_temperaments(
     }
 }
 
+/// The lowest badness of a val that maps the first prime
+/// to nothing, if there is one below bmax.
+/// These vals aren't found by get_equal_temperaments.
+pub(crate) fn best_zero_first_badness(
+    plimit: &[Cents],
+    ek: Cents,
+    bmax: Cents,
+) -> Option<Cents> {
+    // Use the same units as get_equal_temperaments
+    let scale = plimit[0] / 12e2;
+    let plimit = map(|p| p / scale, plimit);
+    limited_mappings(0, ek, bmax / scale, &plimit)
+        .iter()
+        .filter(|mapping| mapping.iter().any(|&m| m != 0))
+        .map(|mapping| equal_temperament_badness(&plimit, ek, mapping))
+        .reduce(f64::min)
+        .map(|badness| badness * scale)
+}
+
 pub fn equal_temperament_badness(
     plimit: &[Cents],
     ek: Cents,
@@ -330,14 +545,14 @@ pub fn equal_temperament_badness(
 /// Decide if this is unambiguously the best mapping of
 /// this number of notes in the prime limit.
 /// Really a TE error function, but here because we have the search.
-pub fn ambiguous_et(plimit: &[Cents], et: &ETMap) -> bool {
+pub fn ambiguous_et(plimit: &[Cents], et: &ETSlice) -> bool {
     let n_notes = if let Some(&n) = et.first() {
         n
     } else {
         // Say an equal temperament with no mappings is unambiguous
         return false;
     };
-    if et != &prime_mapping(plimit, et[0]) {
+    if et != prime_mapping(plimit, et[0]) {
         return true;
     }
     // As this is the prime mapping, check that there are
@@ -347,6 +562,17 @@ pub fn ambiguous_et(plimit: &[Cents], et: &ETMap) -> bool {
     others.len() > 1
 }
 
+/// Name for an equal temperament, with warts only
+/// when the number of steps alone would be ambiguous
+pub fn et_name(plimit: &PrimeLimit, et: &ETSlice) -> String {
+    assert!(!et.is_empty());
+    if ambiguous_et(&plimit.pitches, et) {
+        warted_et_name(plimit, et)
+    } else {
+        et[0].to_string()
+    }
+}
+
 /// High guess for the worst badness of a search.
 /// Must be a reasonable cap, and at least as high
 /// as the worst result we want to keep in the real search.
@@ -388,6 +614,9 @@ fn limited_mappings(
 ) -> Mapping {
     let cap = square(bmax / 12e2) * (plimit.len() as f64) / square(plimit[0]);
     let mut searcher = MoreMappings::new(n_notes, cap, ek / 12e2, plimit);
+    #[cfg(feature = "rayon")]
+    searcher.parallel_search();
+    #[cfg(not(feature = "rayon"))]
     searcher.search(1, 0.0, 0.0);
     searcher.results
 }
@@ -417,6 +646,49 @@ impl<'a> MoreMappings<'a> {
     ///
     /// tot2: running total of w squared
     fn search(&mut self, i: usize, tot: f64, tot2: f64) {
+        let (tot, tot2) = self.running_totals(i, tot, tot2);
+        if i == self.plimit.len() {
+            // Recursion stops here.
+            // Clone the object to save as the one being worked on
+            // keeps changing
+            self.results.push(self.mapping.clone());
+        } else {
+            for guess in self.guesses(i, tot, tot2) {
+                self.mapping[i] = guess;
+                self.search(i + 1, tot, tot2);
+            }
+        }
+    }
+
+    /// The same as search(1, 0.0, 0.0) but with each choice
+    /// for the second element searched in parallel
+    #[cfg(feature = "rayon")]
+    fn parallel_search(&mut self) {
+        use rayon::prelude::*;
+        let (tot, tot2) = self.running_totals(1, 0.0, 0.0);
+        if self.plimit.len() == 1 {
+            self.results.push(self.mapping.clone());
+            return;
+        }
+        let branches: Vec<Mapping> = self
+            .guesses(1, tot, tot2)
+            .into_par_iter()
+            .map(|guess| {
+                let mut branch = MoreMappings {
+                    mapping: self.mapping.clone(),
+                    results: Vec::new(),
+                    ..*self
+                };
+                branch.mapping[1] = guess;
+                branch.search(2, tot, tot2);
+                branch.results
+            })
+            .collect();
+        self.results.extend(branches.into_iter().flatten());
+    }
+
+    /// Add the element before i to the running totals
+    fn running_totals(&self, i: usize, tot: f64, tot2: f64) -> (f64, f64) {
         debug_assert!(self.mapping.len() == self.plimit.len());
         let weighted_size =
             f64::from(self.mapping[i - 1]) / self.plimit[i - 1];
@@ -426,26 +698,29 @@ impl<'a> MoreMappings<'a> {
             tot2 * (1.0 - 1e-10)
                 <= self.lambda * square(tot) / (i as f64) + self.cap
         );
-        if i == self.plimit.len() {
-            // Recursion stops here.
-            // Clone the object to save as the one being worked on
-            // keeps changing
-            self.results.push(self.mapping.clone());
+        (tot, tot2)
+    }
+
+    /// Values for element i that might stay within the cap
+    fn guesses(
+        &self,
+        i: usize,
+        tot: f64,
+        tot2: f64,
+    ) -> std::ops::RangeInclusive<Exponent> {
+        let toti = tot * self.lambda / ((i as f64) + self.epsilon2);
+        let error2 = tot2 - tot * toti;
+        if error2 < self.cap {
+            let target = self.plimit[i];
+            let deficit = ((i + 1) as f64 * (self.cap - error2)
+                / (i as f64 + self.epsilon2))
+                .sqrt();
+            let xmin = target * (toti - deficit);
+            let xmax = target * (toti + deficit);
+            intrange(xmin, xmax)
         } else {
-            let toti = tot * self.lambda / ((i as f64) + self.epsilon2);
-            let error2 = tot2 - tot * toti;
-            if error2 < self.cap {
-                let target = self.plimit[i];
-                let deficit = ((i + 1) as f64 * (self.cap - error2)
-                    / (i as f64 + self.epsilon2))
-                    .sqrt();
-                let xmin = target * (toti - deficit);
-                let xmax = target * (toti + deficit);
-                for guess in intrange(xmin, xmax) {
-                    self.mapping[i] = guess;
-                    self.search(i + 1, tot, tot2);
-                }
-            }
+            // Empty range
+            intrange(1.0, 0.0)
         }
     }
 }
@@ -544,9 +819,9 @@ fn mystery() {
     let limit13 = super::PrimeLimit::new(13);
     let mystery =
         CangwuTemperament::new(&limit13.pitches, &mystery_vector);
-    assert_eq!(mystery.key(), vec![0, 1, 1, 1, 1,
+    assert_eq!(mystery.key().unwrap(), vec![0, 1, 1, 1, 1,
                                    29, 46, 0, 14, 33, 40]);
-    assert_eq!(mystery.rank(), 2);
+    assert_eq!(mystery.rank(), Ok(2));
     super::assert_between!(5.43717, mystery.badness(1.0), 5.43718);
     super::assert_between!(2.52619, mystery.badness(0.1), 2.52620);
 }
@@ -562,12 +837,12 @@ fn ragismic() {
     let limit7 = super::PrimeLimit::new(7);
     let ragismic =
         CangwuTemperament::new(&limit7.pitches, &ragismic_vector);
-    assert_eq!(ragismic.key(), vec![
+    assert_eq!(ragismic.key().unwrap(), vec![
         1, -4,
         1, 0, 7,
         1, 0, 0, 1,
     ]);
-    assert_eq!(ragismic.rank(), 3);
+    assert_eq!(ragismic.rank(), Ok(3));
     assert_between!(0.17, ragismic.badness(1.0), 0.18);
     assert_between!(0.01, ragismic.badness(0.1), 0.02);
     let ragismic =
@@ -651,7 +926,7 @@ fn porcupine_unison_vectors() {
 #[test]
 fn test_ambiguous_et() {
     let limit = super::PrimeLimit::new(11).pitches;
-    assert!(ambiguous_et(&limit, &vec![1, 2, 3, 4, 5]));
+    assert!(ambiguous_et(&limit, &[1, 2, 3, 4, 5]));
 }
 
 #[test]
@@ -712,8 +987,8 @@ fn meantone_from_name() {
     let expected =
         vec![vec![31, 49, 72, 87, 107, 115], vec![12, 19, 28, 34, 42, 45]];
     let named = CangwuTemperament::from_name(&limit, "31 & 12f");
-    assert!(named.is_some());
-    if let Some(rt) = named {
+    assert!(named.is_ok());
+    if let Ok(rt) = named {
         assert_eq!(rt.melody, expected);
     }
 }
@@ -725,10 +1000,10 @@ fn marvel_from_key() {
     let clone = CangwuTemperament::from_ets_and_key(
         &limit11.pitches,
         &[22, 31, 41],
-        &original.key(),
+        &original.key().unwrap(),
     );
-    assert!(clone.is_some());
-    if let Some(clone) = clone {
+    assert!(clone.is_ok());
+    if let Ok(clone) = clone {
         assert_eq!(original.melody, clone.melody);
     }
 }
@@ -740,10 +1015,10 @@ fn jove_from_key() {
     let clone = CangwuTemperament::from_ets_and_key(
         &limit11.pitches,
         &[27, 31, 41],
-        &original.key(),
+        &original.key().unwrap(),
     );
-    assert!(clone.is_some());
-    if let Some(clone) = clone {
+    assert!(clone.is_ok());
+    if let Ok(clone) = clone {
         assert_eq!(original.melody, clone.melody);
     }
 }
@@ -754,20 +1029,168 @@ fn porcupine_from_key() {
     let limit11 = super::PrimeLimit::new(11);
     let key = vec![3, 5, -6, 4, 1, 2, 3, 2, 4];
     let mapping = key_to_mapping(limit11.pitches.len(), &key);
-    assert_eq!(
-        Some(vec![vec![1, 2, 3, 2, 4], vec![0, 3, 5, -6, 4]]),
-        mapping,
-    );
+    assert_eq!(Ok(vec![vec![1, 2, 3, 2, 4], vec![0, 3, 5, -6, 4]]), mapping,);
     match CangwuTemperament::from_ets_and_key(
         &limit11.pitches,
         &[15, 22],
         &key,
     ) {
-        Some(_) => (),
-        None => assert!(false),
+        Ok(_) => (),
+        Err(_) => assert!(false),
     }
 }
 
+#[test]
+fn marvel_from_too_few_ets() {
+    let limit11 = super::PrimeLimit::new(11);
+    let original = make_marvel(&limit11);
+    let clone = CangwuTemperament::from_ets_and_key(
+        &limit11.pitches,
+        &[22, 31],
+        &original.key().unwrap(),
+    );
+    assert_eq!(
+        clone.err(),
+        Some(Error::RankMismatch { expected: 3, found: 2 }),
+    );
+}
+
+#[test]
+fn bad_wart_from_name() {
+    let limit = super::PrimeLimit::new(5);
+    let named = CangwuTemperament::from_name(&limit, "12f & 19q");
+    assert_eq!(named.err(), Some(Error::UnknownWart('f')));
+}
+
+#[test]
+fn owned_marvel() {
+    let limit11 = super::PrimeLimit::new(11);
+    let marvel = make_marvel(&limit11);
+    let badness = marvel.badness(1.0);
+    let owned = marvel.clone().into_owned();
+    drop(limit11);
+    assert_eq!(owned.badness(1.0), badness);
+    assert_eq!(owned.borrowed().badness(1.0), badness);
+}
+
+#[test]
+fn marvel_from_ratios() {
+    let limit11 = super::PrimeLimit::new(11);
+    let marvel = make_marvel(&limit11);
+    let rt =
+        CangwuTemperament::from_ratios(&limit11, &["225:224", "385:384"])
+            .expect("no temperament from ratios");
+    assert_eq!(rt.melody, marvel.reduced_mapping().unwrap());
+    assert_eq!(rt.key().unwrap(), marvel.key().unwrap());
+    let uvs = vec![vec![-5, 2, 2, -1, 0], vec![-7, -1, 1, 1, 1]];
+    let rt = CangwuTemperament::from_unison_vectors(&limit11.pitches, &uvs)
+        .expect("no temperament from unison vectors");
+    assert_eq!(rt.melody, marvel.reduced_mapping().unwrap());
+    assert_eq!(rt.spanning_ets(&marvel.melody), Some(marvel.melody));
+}
+
+#[test]
+fn meantone_spanning_ets() {
+    let limit5 = super::PrimeLimit::new(5);
+    let meantone = CangwuTemperament::from_ratios(&limit5, &["81:80"])
+        .expect("no meantone");
+    let ets = vec![vec![12, 19, 28], vec![24, 38, 56], vec![19, 30, 44]];
+    assert_eq!(
+        meantone.spanning_ets(&ets),
+        Some(vec![vec![12, 19, 28], vec![19, 30, 44]])
+    );
+    // Doubled ETs give contorted meantone
+    let ets = vec![vec![24, 38, 56], vec![38, 60, 88]];
+    assert_eq!(meantone.spanning_ets(&ets), None);
+}
+
+#[test]
+fn bad_unison_vectors() {
+    let limit5 = super::PrimeLimit::new(5);
+    assert_eq!(
+        CangwuTemperament::from_ratios(&limit5, &["81:80", "2:1", "3:2"])
+            .map(|rt| rt.melody),
+        Err(Error::TooManyUnisonVectors)
+    );
+    assert_eq!(
+        CangwuTemperament::from_ratios(&limit5, &["81:80", "126:125"])
+            .map(|rt| rt.melody),
+        Err(Error::PrimeOutsideLimit("126:125".to_string()))
+    );
+    assert_eq!(
+        CangwuTemperament::from_unison_vectors(&limit5.pitches, &[vec![1]])
+            .map(|rt| rt.melody),
+        Err(Error::DimensionMismatch { expected: 3, found: 1 })
+    );
+    // Dependent unison vectors don't lower the rank
+    let rt = CangwuTemperament::from_ratios(&limit5, &["81:80", "6561:6400"])
+        .expect("no meantone");
+    assert_eq!(rt.rank(), Ok(2));
+    // No unison vectors is just intonation
+    let rt = CangwuTemperament::from_unison_vectors(&limit5.pitches, &[])
+        .expect("no JI");
+    assert_eq!(rt.melody, vec![vec![1, 0, 0], vec![0, 1, 0], vec![0, 0, 1]]);
+}
+
+#[test]
+fn meantone_meet_and_join() {
+    let limit7 = super::PrimeLimit::new(7);
+    let meantone =
+        CangwuTemperament::from_ratios(&limit7, &["81:80"]).expect("bad");
+    let marvel =
+        CangwuTemperament::from_ratios(&limit7, &["225:224"]).expect("bad");
+    let join = meantone.join(&marvel).expect("no join");
+    assert_eq!(join.rank(), Ok(2));
+    assert_eq!(
+        join.key().unwrap(),
+        CangwuTemperament::from_ratios(&limit7, &["81:80", "225:224"])
+            .expect("no septimal meantone")
+            .key()
+            .unwrap()
+    );
+    assert_eq!(join.name(&limit7), Some("Meantone"));
+    // Any val of 5-limit meantone or 7-limit marvel fits
+    let meet = meantone.meet(&marvel).expect("no meet");
+    assert_eq!(meet.rank(), Ok(4));
+    assert_eq!(meet.melody, join.meet(&meet).expect("no meet").melody);
+
+    // Septimal and dominant meantone only share the syntonic comma
+    let septimal =
+        CangwuTemperament::from_ratios(&limit7, &["81:80", "126:125"])
+            .expect("no septimal meantone");
+    let dominant =
+        CangwuTemperament::from_ratios(&limit7, &["81:80", "64:63"])
+            .expect("no dominant meantone");
+    let meet = septimal.meet(&dominant).expect("no meet");
+    assert_eq!(meet.rank(), Ok(3));
+    assert_eq!(meet.melody, meantone.melody);
+    assert_eq!(meet.key().unwrap(), meantone.key().unwrap());
+
+    // Rank 2 temperaments sharing a val
+    let limit5 = super::PrimeLimit::new(5);
+    let meantone =
+        CangwuTemperament::from_name(&limit5, "12 & 19").expect("bad");
+    let augmented =
+        CangwuTemperament::from_name(&limit5, "12 & 15").expect("bad");
+    let meet = meantone.meet(&augmented).expect("no meet");
+    assert_eq!(meet.rank(), Ok(3));
+    let join = meantone.join(&augmented).expect("no join");
+    assert_eq!(join.rank(), Ok(1));
+    assert_eq!(join.melody, vec![vec![12, 19, 28]]);
+}
+
+#[test]
+fn meet_in_different_limits() {
+    let limit5 = super::PrimeLimit::new(5);
+    let limit7 = super::PrimeLimit::new(7);
+    let meantone =
+        CangwuTemperament::from_name(&limit5, "12 & 19").expect("bad");
+    let septimal =
+        CangwuTemperament::from_name(&limit7, "12 & 19").expect("bad");
+    assert!(matches!(meantone.meet(&septimal), Err(Error::LimitMismatch)));
+    assert!(matches!(meantone.join(&septimal), Err(Error::LimitMismatch)));
+}
+
 #[test]
 fn marvel_ets() {
     let limit11 = super::PrimeLimit::new(11);
@@ -889,3 +1312,62 @@ fn na_naa_ets() {
 fn octaves(mappings: &Vec<super::ETMap>) -> super::ETMap {
     mappings.iter().map(|m| m[0]).collect()
 }
+
+#[test]
+fn zero_first_badness() {
+    let limit7 = super::PrimeLimit::new(7).pitches;
+    let mut best = f64::INFINITY;
+    for a in -3..=3 {
+        for b in -3..=3 {
+            for c in -3..=3 {
+                if (a, b, c) != (0, 0, 0) {
+                    let val = [0, a, b, c];
+                    let badness =
+                        equal_temperament_badness(&limit7, 1.0, &val);
+                    best = best.min(badness);
+                }
+            }
+        }
+    }
+    assert_eq!(best_zero_first_badness(&limit7, 1.0, best * 0.99), None);
+    let found = best_zero_first_badness(&limit7, 1.0, best * 1.01);
+    super::assert_between!(
+        best * 0.999999,
+        found.unwrap_or(0.0),
+        best * 1.000001
+    );
+}
+
+#[cfg(feature = "rayon")]
+#[test]
+fn parallel_limited_mappings() {
+    let limit13 = super::PrimeLimit::new(13).pitches;
+    // Parameters as limited_mappings(n_notes, 1.0, 1e2, &limit13)
+    let cap = square(1e2 / 12e2) * 6.0 / square(limit13[0]);
+    for n_notes in [1, 12, 31, 41, 72] {
+        let mut serial =
+            MoreMappings::new(n_notes, cap, 1.0 / 12e2, &limit13);
+        serial.search(1, 0.0, 0.0);
+        let mut parallel =
+            MoreMappings::new(n_notes, cap, 1.0 / 12e2, &limit13);
+        parallel.parallel_search();
+        assert_eq!(parallel.results, serial.results);
+    }
+}
+
+#[cfg(feature = "rayon")]
+#[test]
+fn parallel_rank_search_matches() {
+    let limit11 = super::PrimeLimit::new(11).pitches;
+    let ets = get_equal_temperaments(&limit11, 1.0, 20);
+    let rts: Vec<Mapping> = ets.iter().map(|et| vec![et.clone()]).collect();
+    let serial = |rts: &[Mapping]| {
+        serial_higher_rank_search(&limit11, &ets, rts, 1.0, 20, |_, _| true)
+    };
+    let parallel = |rts: &[Mapping]| {
+        parallel_higher_rank_search(&limit11, &ets, rts, 1.0, 20, |_, _| true)
+    };
+    let rank2 = serial(&rts).expect("serial search failed");
+    assert_eq!(parallel(&rts), Some(rank2.clone()));
+    assert_eq!(parallel(&rank2), serial(&rank2));
+}
@@ -0,0 +1,288 @@
+//! Least squares tunings with some intervals held just
+//!
+//! CTE (Constrained Tenney-Euclidean) is the TE tuning
+//! with pure octaves, unlike POTE which stretches the TE tuning
+//! and so only comes close to the optimum.
+//! Any other set of intervals can be held just in the same way:
+//! find a tuning that fits them and then the best tuning
+//! from the ones that only differ by steps that don't change them.
+
+extern crate nalgebra as na;
+use na::{DMatrix, DVector};
+
+use super::cangwu::TenneyWeighted;
+use super::temperament_class::TemperamentClass;
+use super::tuned_temperament::TunedTemperament;
+use super::weighting::Weighting;
+use super::{Cents, ETMap, Error, Mapping, Tuning};
+use std::borrow::Cow;
+
+/// Singular values smaller than this are treated as zero
+const EPSILON: f64 = 1e-9;
+
+/// Optimal tuning with some intervals just.
+/// ConstrainedTemperament<'static> owns its prime limit.
+#[derive(Clone, Debug)]
+pub struct ConstrainedTemperament<'a> {
+    plimit: Cow<'a, [Cents]>,
+    pub melody: Mapping,
+    pub tuning: Tuning,
+    pub weighting: Weighting,
+    /// Intervals, as prime exponents, that are tuned just
+    pub just_intervals: Mapping,
+}
+
+impl TemperamentClass for ConstrainedTemperament<'_> {
+    fn mapping(&self) -> &Mapping {
+        &self.melody
+    }
+}
+
+impl TunedTemperament for ConstrainedTemperament<'_> {
+    fn plimit(&self) -> &[Cents] {
+        &self.plimit
+    }
+
+    fn tuning(&self) -> &Tuning {
+        &self.tuning
+    }
+}
+
+impl TenneyWeighted for ConstrainedTemperament<'_> {
+    fn mapping(&self) -> &Mapping {
+        &self.melody
+    }
+
+    fn plimit(&self) -> &[Cents] {
+        &self.plimit
+    }
+
+    fn weighting(&self) -> &Weighting {
+        &self.weighting
+    }
+}
+
+impl<'a> ConstrainedTemperament<'a> {
+    /// TE tuning with the just intervals (as prime exponents)
+    /// held just
+    pub fn new(
+        plimit: impl Into<Cow<'a, [Cents]>>,
+        melody: &[ETMap],
+        just_intervals: &[ETMap],
+    ) -> Result<Self, Error> {
+        Self::weighted(plimit, melody, just_intervals, Weighting::Tenney)
+    }
+
+    /// CTE: TE tuning with the first prime (usually the octave) just
+    pub fn cte(
+        plimit: impl Into<Cow<'a, [Cents]>>,
+        melody: &[ETMap],
+    ) -> Result<Self, Error> {
+        let plimit = plimit.into();
+        if plimit.is_empty() {
+            // There's no room for the octave
+            return Err(Error::DimensionMismatch { expected: 0, found: 1 });
+        }
+        let mut octave = vec![0; plimit.len()];
+        octave[0] = 1;
+        Self::new(plimit, melody, &[octave])
+    }
+
+    pub fn weighted(
+        plimit: impl Into<Cow<'a, [Cents]>>,
+        melody: &[ETMap],
+        just_intervals: &[ETMap],
+        weighting: Weighting,
+    ) -> Result<Self, Error> {
+        let plimit = plimit.into();
+        weighting.check(&plimit)?;
+        for vector in melody.iter().chain(just_intervals) {
+            if vector.len() != plimit.len() {
+                return Err(Error::DimensionMismatch {
+                    expected: plimit.len(),
+                    found: vector.len(),
+                });
+            }
+        }
+        let mut rt = ConstrainedTemperament {
+            plimit,
+            melody: melody.to_vec(),
+            tuning: vec![],
+            weighting,
+            just_intervals: just_intervals.to_vec(),
+        };
+        rt.optimize()?;
+        Ok(rt)
+    }
+
+    fn optimize(&mut self) -> Result<(), Error> {
+        let rank = self.melody.len();
+        let wmap = self.weighted_mapping();
+        let ji = self.weighting.weighted_ji(&self.plimit);
+        let generators =
+            DMatrix::from_fn(self.just_intervals.len(), rank, |i, j| {
+                dot(&self.melody[j], &self.just_intervals[i])
+            });
+        let sizes = DVector::from_iterator(
+            self.just_intervals.len(),
+            self.just_intervals.iter().map(|interval| {
+                self.plimit
+                    .iter()
+                    .zip(interval)
+                    .map(|(&p, &x)| p * x as Cents)
+                    .sum::<Cents>()
+            }),
+        );
+        let tuning =
+            constrained_least_squares(&wmap, &ji, &generators, &sizes)?;
+        self.tuning = tuning.iter().cloned().collect();
+        Ok(())
+    }
+
+    /// Optimal weighted error of the constrained tuning
+    pub fn error(&self) -> Cents {
+        let tuning = DVector::from_column_slice(&self.tuning);
+        let ji = self.weighting.weighted_ji(&self.plimit);
+        let weighted = self.weighted_mapping() * tuning - ji;
+        (weighted.norm_squared() / weighted.len() as f64).sqrt()
+    }
+
+    /// Take ownership of the prime limit to lose the lifetime
+    pub fn into_owned(self) -> ConstrainedTemperament<'static> {
+        ConstrainedTemperament {
+            plimit: Cow::Owned(self.plimit.into_owned()),
+            melody: self.melody,
+            tuning: self.tuning,
+            weighting: self.weighting,
+            just_intervals: self.just_intervals,
+        }
+    }
+}
+
+/// The x that makes a·x closest to b with c·x = d exactly,
+/// or ImpossibleJustIntervals if the constraints can't be met.
+/// Any x that meets the constraints is a particular solution
+/// plus something from the null space of c.
+pub fn constrained_least_squares(
+    a: &DMatrix<f64>,
+    b: &DVector<f64>,
+    c: &DMatrix<f64>,
+    d: &DVector<f64>,
+) -> Result<DVector<f64>, Error> {
+    let n = a.ncols();
+    let (particular, projection) = if c.nrows() == 0 {
+        // nalgebra can't take the SVD of an empty matrix
+        (DVector::zeros(n), DMatrix::identity(n, n))
+    } else {
+        let c_pinv =
+            c.clone().pseudo_inverse(EPSILON).expect("no pseudoinverse");
+        (&c_pinv * d, DMatrix::identity(n, n) - &c_pinv * c)
+    };
+    let free_a = a * &projection;
+    let free_pinv = free_a.pseudo_inverse(EPSILON).expect("no pseudoinverse");
+    let free = free_pinv * (b - a * &particular);
+    let x = particular + projection * free;
+    let residual = c * &x - d;
+    if residual.iter().any(|r| r.abs() > 1e-6) {
+        return Err(Error::ImpossibleJustIntervals);
+    }
+    Ok(x)
+}
+
+pub(crate) fn dot(mapping: &[i32], interval: &[i32]) -> Cents {
+    mapping.iter().zip(interval).map(|(&m, &x)| m as Cents * x as Cents).sum()
+}
+
+#[test]
+fn meantone_cte() {
+    let limit5 = super::PrimeLimit::new(5);
+    let mapping = vec![vec![1, 0, -4], vec![0, 1, 4]];
+    let rt = ConstrainedTemperament::cte(&limit5.pitches, &mapping)
+        .expect("no CTE");
+    assert_eq!(format!("{:.6}", rt.tuning[0]), "1200.000000");
+    assert_eq!(format!("{:.3}", rt.tuning[1]), "1897.214");
+    assert!(rt.error() > 0.0);
+}
+
+#[test]
+fn quarter_comma() {
+    let limit5 = super::PrimeLimit::new(5);
+    let meantone = super::te::TETemperament::new(
+        &limit5.pitches,
+        &[vec![12, 19, 28], vec![7, 11, 16]],
+    );
+    let just = [vec![1, 0, 0], vec![-2, 0, 1]];
+    let rt =
+        ConstrainedTemperament::new(&limit5.pitches, &meantone.melody, &just)
+            .expect("no quarter comma");
+    let tuning_map = rt.tuning_map();
+    assert_eq!(format!("{:.3}", tuning_map[0]), "1200.000");
+    assert_eq!(format!("{:.3}", tuning_map[1]), "1896.578");
+    assert_eq!(format!("{:.3}", tuning_map[2]), "2786.314");
+    // With nothing just it's the TE tuning
+    let te =
+        ConstrainedTemperament::new(&limit5.pitches, &meantone.melody, &[])
+            .expect("no TE");
+    for (x, y) in te.tuning.iter().zip(&meantone.tuning) {
+        super::assert_between!(y - 1e-6, *x, y + 1e-6);
+    }
+    super::assert_between!(
+        te.error() - 1e-6,
+        meantone.error(),
+        te.error() + 1e-6
+    );
+    // The weighted error agrees with TE for any weighting
+    for weighting in Weighting::NAMED {
+        let weighted_te = super::te::TETemperament::weighted(
+            &limit5.pitches,
+            &meantone.melody,
+            weighting.clone(),
+        )
+        .unwrap();
+        let rt = ConstrainedTemperament::weighted(
+            &limit5.pitches,
+            &meantone.melody,
+            &[],
+            weighting,
+        )
+        .unwrap();
+        super::assert_between!(
+            rt.error() - 1e-6,
+            weighted_te.error(),
+            rt.error() + 1e-6
+        );
+    }
+}
+
+#[test]
+fn impossible_just_intervals() {
+    let limit5 = super::PrimeLimit::new(5);
+    let mapping = vec![vec![1, 0, -4], vec![0, 1, 4]];
+    let syntonic_comma = vec![-4, 4, -1];
+    assert_eq!(
+        ConstrainedTemperament::new(
+            &limit5.pitches,
+            &mapping,
+            &[syntonic_comma]
+        )
+        .map(|rt| rt.tuning),
+        Err(Error::ImpossibleJustIntervals)
+    );
+    assert_eq!(
+        ConstrainedTemperament::new(&limit5.pitches, &mapping, &[vec![1, 0]])
+            .map(|rt| rt.tuning),
+        Err(Error::DimensionMismatch { expected: 3, found: 2 })
+    );
+    assert_eq!(
+        ConstrainedTemperament::cte(
+            &limit5.pitches,
+            &[vec![1, 0], vec![0, 1]]
+        )
+        .map(|rt| rt.tuning),
+        Err(Error::DimensionMismatch { expected: 3, found: 2 })
+    );
+    assert_eq!(
+        ConstrainedTemperament::cte(vec![], &mapping).map(|rt| rt.tuning),
+        Err(Error::DimensionMismatch { expected: 0, found: 1 })
+    );
+}
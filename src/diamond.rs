@@ -0,0 +1,492 @@
+//! Tunings optimized over an odd limit tonality diamond
+//!
+//! The diamond has every ratio of odd numbers up to the odd limit,
+//! reduced to within an octave.  The 2/1 stands in for the 1/1
+//! so that tempered octaves count.  Minimax tunings come from
+//! the same kind of nested linear program as TOP and RMS tunings from
+//! the constrained least squares used for CTE.
+
+extern crate nalgebra as na;
+use microlp::{ComparisonOp, OptimizationDirection, Problem};
+use na::{DMatrix, DVector};
+use num_integer::Integer;
+
+use super::constrained::{constrained_least_squares, dot};
+use super::ratio::{factorize_ratio, stringify};
+use super::temperament_class::TemperamentClass;
+use super::top::{SOLVER_EPSILON, TOLERANCE, stuck_primes};
+use super::tuned_temperament::TunedTemperament;
+use super::{Cents, ETMap, Error, Mapping, PrimeLimit, Tuning};
+use std::borrow::Cow;
+
+/// Intervals of a tonality diamond
+#[derive(Clone, Debug, PartialEq)]
+pub struct Diamond {
+    pub odd_limit: u128,
+    /// Numerators and denominators, smallest interval first
+    pub ratios: Vec<(u128, u128)>,
+    /// The same intervals as prime exponents
+    pub intervals: Mapping,
+}
+
+impl Diamond {
+    /// All the intervals of the diamond,
+    /// which must fit in the prime limit
+    pub fn new(limit: &PrimeLimit, odd_limit: u128) -> Result<Self, Error> {
+        let odds: Vec<u128> = (1..=odd_limit).step_by(2).collect();
+        let mut ratios = vec![(2, 1)];
+        for &n in &odds {
+            for &d in &odds {
+                if n == d {
+                    continue;
+                }
+                let (mut n, mut d) = (n, d);
+                while n > 2 * d {
+                    d *= 2;
+                }
+                while n < d {
+                    n *= 2;
+                }
+                let gcd = n.gcd(&d);
+                ratios.push((n / gcd, d / gcd));
+            }
+        }
+        ratios.sort_by(|&(n1, d1), &(n2, d2)| (n1 * d2).cmp(&(n2 * d1)));
+        ratios.dedup();
+        let intervals = ratios
+            .iter()
+            .map(|&ratio| factorize_ratio(limit, ratio))
+            .collect::<Result<Mapping, _>>()?;
+        Ok(Diamond { odd_limit, ratios, intervals })
+    }
+
+    /// Just sizes in cents
+    pub fn sizes(&self, plimit: &[Cents]) -> Tuning {
+        self.intervals
+            .iter()
+            .map(|interval| {
+                plimit
+                    .iter()
+                    .zip(interval)
+                    .map(|(&p, &x)| p * x as Cents)
+                    .sum()
+            })
+            .collect()
+    }
+}
+
+/// How to fit the diamond
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub enum DiamondFit {
+    /// Smallest worst error
+    Minimax,
+    /// Smallest root mean square error
+    Rms,
+}
+
+/// Temperament tuned to an odd limit tonality diamond.
+/// DiamondTemperament<'static> owns its prime limit.
+#[derive(Clone, Debug)]
+pub struct DiamondTemperament<'a> {
+    plimit: Cow<'a, [Cents]>,
+    pub melody: Mapping,
+    pub tuning: Tuning,
+    pub diamond: Diamond,
+    pub fit: DiamondFit,
+    pub pure_octaves: bool,
+}
+
+impl TemperamentClass for DiamondTemperament<'_> {
+    fn mapping(&self) -> &Mapping {
+        &self.melody
+    }
+}
+
+impl TunedTemperament for DiamondTemperament<'_> {
+    fn plimit(&self) -> &[Cents] {
+        &self.plimit
+    }
+
+    fn tuning(&self) -> &Tuning {
+        &self.tuning
+    }
+}
+
+impl<'a> DiamondTemperament<'a> {
+    /// Tuning for the diamond (which must be in the same prime limit),
+    /// with the first prime just if pure_octaves is set
+    pub fn new(
+        plimit: impl Into<Cow<'a, [Cents]>>,
+        melody: &[ETMap],
+        diamond: Diamond,
+        fit: DiamondFit,
+        pure_octaves: bool,
+    ) -> Result<Self, Error> {
+        let plimit = plimit.into();
+        for interval in &diamond.intervals {
+            if interval.len() != plimit.len() {
+                return Err(Error::DimensionMismatch {
+                    expected: plimit.len(),
+                    found: interval.len(),
+                });
+            }
+        }
+        let mut rt = DiamondTemperament {
+            plimit,
+            melody: melody.to_vec(),
+            tuning: vec![],
+            diamond,
+            fit,
+            pure_octaves,
+        };
+        match fit {
+            DiamondFit::Minimax => rt.optimize_minimax()?,
+            DiamondFit::Rms => rt.optimize_rms()?,
+        }
+        Ok(rt)
+    }
+
+    /// Take ownership of the prime limit to lose the lifetime
+    pub fn into_owned(self) -> DiamondTemperament<'static> {
+        DiamondTemperament {
+            plimit: Cow::Owned(self.plimit.into_owned()),
+            melody: self.melody,
+            tuning: self.tuning,
+            diamond: self.diamond,
+            fit: self.fit,
+            pure_octaves: self.pure_octaves,
+        }
+    }
+
+    /// Each diamond interval's size in generator steps
+    fn interval_steps(&self) -> Vec<Vec<f64>> {
+        self.diamond
+            .intervals
+            .iter()
+            .map(|interval| {
+                self.melody.iter().map(|m| dot(m, interval)).collect()
+            })
+            .collect()
+    }
+
+    /// Octave (first prime) in generator steps
+    fn octave_steps(&self) -> Vec<f64> {
+        self.melody.iter().map(|m| m[0] as f64).collect()
+    }
+
+    /// The minimax error, then the minimax error of the intervals
+    /// that aren't stuck at that error, and so on
+    /// until the tuning is unique, like TIPTOP
+    fn optimize_minimax(&mut self) -> Result<(), Error> {
+        let rank = self.melody.len();
+        let steps = self.interval_steps();
+        let sizes = self.diamond.sizes(&self.plimit);
+        // Intervals with a fixed tuning, as (index, tuned size)
+        let mut fixed: Vec<(usize, Cents)> = Vec::new();
+        if self.pure_octaves
+            && let Some(octave) =
+                self.diamond.ratios.iter().position(|&r| r == (2, 1))
+        {
+            fixed.push((octave, sizes[octave]));
+        }
+        // A subset of the fixed intervals that are independent
+        let mut constraints = fixed.clone();
+        while constraints.len() < rank {
+            let free: Vec<usize> = (0..sizes.len())
+                .filter(|i| fixed.iter().all(|(j, _)| i != j))
+                .collect();
+            if free.is_empty() {
+                // The mapping must be rank deficient
+                return Ok(());
+            }
+            let (error, tuning) =
+                solve_level(&steps, &sizes, &constraints, &free, None)?;
+            // Ranges of tunings at this error, as (index, lowest, highest)
+            let mut ranges = Vec::new();
+            for &i in &free {
+                // Only intervals at the worst error can be stuck there
+                let tuned = dot_tuning(&steps[i], &tuning);
+                if ((tuned - sizes[i]).abs() - error).abs() > TOLERANCE {
+                    continue;
+                }
+                let (_, lowest) = solve_level(
+                    &steps,
+                    &sizes,
+                    &constraints,
+                    &free,
+                    Some((i, OptimizationDirection::Minimize, error)),
+                )?;
+                let (_, highest) = solve_level(
+                    &steps,
+                    &sizes,
+                    &constraints,
+                    &free,
+                    Some((i, OptimizationDirection::Maximize, error)),
+                )?;
+                ranges.push((
+                    i,
+                    dot_tuning(&steps[i], &lowest),
+                    dot_tuning(&steps[i], &highest),
+                ));
+            }
+            self.tuning = tuning;
+            for interval in stuck_primes(&sizes, &ranges)? {
+                fixed.push(interval);
+                let mut extended = constraints.clone();
+                extended.push(interval);
+                if fixed_rank(&steps, &extended) > constraints.len() {
+                    constraints = extended;
+                }
+            }
+        }
+        let intervals =
+            DMatrix::from_fn(rank, rank, |i, j| steps[constraints[i].0][j]);
+        let sizes = DVector::from_iterator(
+            rank,
+            constraints.iter().map(|&(_, size)| size),
+        );
+        let tuning = intervals.lu().solve(&sizes).expect("no unique tuning");
+        self.tuning = tuning.iter().cloned().collect();
+        Ok(())
+    }
+
+    fn optimize_rms(&mut self) -> Result<(), Error> {
+        let steps = self.interval_steps();
+        let rank = self.melody.len();
+        let a = DMatrix::from_fn(steps.len(), rank, |i, j| steps[i][j]);
+        let b = DVector::from_vec(self.diamond.sizes(&self.plimit));
+        let (c, d) = if self.pure_octaves {
+            (
+                DMatrix::from_row_slice(1, rank, &self.octave_steps()),
+                DVector::from_element(1, self.plimit[0]),
+            )
+        } else {
+            (DMatrix::zeros(0, rank), DVector::zeros(0))
+        };
+        let tuning = constrained_least_squares(&a, &b, &c, &d)?;
+        self.tuning = tuning.iter().cloned().collect();
+        Ok(())
+    }
+
+    /// Tempered minus just size of each diamond interval
+    pub fn diamond_errors(&self) -> Tuning {
+        self.diamond
+            .intervals
+            .iter()
+            .zip(self.diamond.sizes(&self.plimit))
+            .map(|(interval, size)| self.pitch_from_primes(interval) - size)
+            .collect()
+    }
+
+    /// Worst error of any diamond interval
+    pub fn max_error(&self) -> Cents {
+        self.diamond_errors().iter().map(|x| x.abs()).fold(0.0, f64::max)
+    }
+
+    pub fn rms_error(&self) -> Cents {
+        let errors = self.diamond_errors();
+        let sum_squares: f64 = errors.iter().map(|x| x * x).sum();
+        (sum_squares / errors.len() as f64).sqrt()
+    }
+
+    /// A line for each diamond interval with its ratio,
+    /// tempered size, and error
+    pub fn report(&self) -> String {
+        self.diamond
+            .ratios
+            .iter()
+            .zip(&self.diamond.intervals)
+            .zip(self.diamond_errors())
+            .map(|((&ratio, interval), error)| {
+                // Don't show rounding errors as -0.000
+                let error = if error.abs() < 5e-4 { 0.0 } else { error };
+                format!(
+                    "{:>7} {:9.3} {:7.3}",
+                    stringify(ratio),
+                    self.pitch_from_primes(interval),
+                    error
+                )
+            })
+            .collect::<Vec<_>>()
+            .join("\n")
+    }
+}
+
+/// Solve the linear program for one level of the nested minimax
+/// with the constrained intervals fixed and the free intervals
+/// counting towards the error.  With a target,
+/// the error is held to the given value and the target interval
+/// tuned as flat or sharp as possible.
+fn solve_level(
+    steps: &[Vec<f64>],
+    sizes: &[Cents],
+    constraints: &[(usize, Cents)],
+    free: &[usize],
+    target: Option<(usize, OptimizationDirection, f64)>,
+) -> Result<(f64, Tuning), Error> {
+    let (direction, error_bounds) = match target {
+        Some((_, direction, max_error)) => {
+            (direction, (0.0, max_error + 10.0 * SOLVER_EPSILON))
+        }
+        None => (OptimizationDirection::Minimize, (0.0, f64::INFINITY)),
+    };
+    let mut problem = Problem::new(direction);
+    let error_cost = if target.is_some() { 0.0 } else { 1.0 };
+    let error = problem.add_var(error_cost, error_bounds);
+    let rank = steps.first().map_or(0, Vec::len);
+    let vars: Vec<_> = (0..rank)
+        .map(|j| {
+            let cost = match target {
+                Some((i, _, _)) => steps[i][j],
+                None => 0.0,
+            };
+            problem.add_var(cost, (f64::NEG_INFINITY, f64::INFINITY))
+        })
+        .collect();
+    let tuned_interval = |i: usize| -> Vec<_> {
+        vars.iter().copied().zip(steps[i].iter().copied()).collect()
+    };
+    for &i in free {
+        // tuned - error <= size and tuned + error >= size
+        let mut constraint = tuned_interval(i);
+        constraint.push((error, -1.0));
+        problem.add_constraint(&constraint, ComparisonOp::Le, sizes[i]);
+        constraint.pop();
+        constraint.push((error, 1.0));
+        problem.add_constraint(&constraint, ComparisonOp::Ge, sizes[i]);
+    }
+    for &(i, size) in constraints {
+        problem.add_constraint(tuned_interval(i), ComparisonOp::Eq, size);
+    }
+    let solution = problem.solve()?;
+    let tuning = vars.iter().map(|&v| solution[v]).collect();
+    Ok((solution[error], tuning))
+}
+
+fn dot_tuning(steps: &[f64], tuning: &[Cents]) -> Cents {
+    steps.iter().zip(tuning).map(|(x, y)| x * y).sum()
+}
+
+/// How many independent intervals are in this list
+fn fixed_rank(steps: &[Vec<f64>], intervals: &[(usize, Cents)]) -> usize {
+    let rank = steps.first().map_or(0, Vec::len);
+    let matrix = DMatrix::from_fn(intervals.len(), rank, |i, j| {
+        steps[intervals[i].0][j]
+    });
+    matrix.rank(1e-9)
+}
+
+#[test]
+fn five_limit_diamond() {
+    let limit5 = PrimeLimit::new(5);
+    let diamond = Diamond::new(&limit5, 5).expect("no diamond");
+    assert_eq!(
+        diamond.ratios,
+        vec![(6, 5), (5, 4), (4, 3), (3, 2), (8, 5), (5, 3), (2, 1)]
+    );
+    assert_eq!(diamond.intervals[1], vec![-2, 0, 1]);
+    let nine = Diamond::new(&PrimeLimit::new(7), 9).expect("no diamond");
+    assert_eq!(nine.ratios.len(), 19);
+    assert!(nine.ratios.contains(&(9, 7)));
+    assert_eq!(
+        Diamond::new(&limit5, 7),
+        Err(Error::PrimeOutsideLimit("8:7".to_string()))
+    );
+}
+
+#[test]
+fn minimax_meantone() {
+    let limit5 = PrimeLimit::new(5);
+    let meantone = vec![vec![1, 0, -4], vec![0, 1, 4]];
+    let diamond = Diamond::new(&limit5, 5).expect("no diamond");
+    // Quarter comma
+    let rt = DiamondTemperament::new(
+        &limit5.pitches,
+        &meantone,
+        diamond.clone(),
+        DiamondFit::Minimax,
+        true,
+    )
+    .expect("no tuning");
+    super::assert_between!(1199.999, rt.tuning[0], 1200.001);
+    super::assert_between!(1896.57, rt.tuning[1], 1896.59);
+    super::assert_between!(5.37, rt.max_error(), 5.38);
+    // Tempering the octave doesn't help here
+    let tempered = DiamondTemperament::new(
+        &limit5.pitches,
+        &meantone,
+        diamond.clone(),
+        DiamondFit::Minimax,
+        false,
+    )
+    .expect("no tuning");
+    super::assert_between!(5.37, tempered.max_error(), 5.38);
+    // The next level of the nested minimax
+    // makes the octave and 5:4 pure again
+    for (&x, &y) in tempered.tuning.iter().zip(&rt.tuning) {
+        super::assert_between!(y - 1e-6, x, y + 1e-6);
+    }
+    let report = rt.report();
+    let lines: Vec<&str> = report.lines().collect();
+    assert_eq!(lines.len(), 7);
+    assert_eq!(lines[1], "    5:4   386.314   0.000");
+    // The diamond has to match the prime limit
+    let nine = Diamond::new(&PrimeLimit::new(7), 9).expect("no diamond");
+    assert_eq!(
+        DiamondTemperament::new(
+            &limit5.pitches,
+            &meantone,
+            nine,
+            DiamondFit::Minimax,
+            true
+        )
+        .map(|rt| rt.tuning),
+        Err(Error::DimensionMismatch { expected: 3, found: 4 })
+    );
+}
+
+#[test]
+fn owned_diamond_temperament() {
+    let rt = {
+        let limit5 = PrimeLimit::new(5);
+        let diamond = Diamond::new(&limit5, 5).expect("no diamond");
+        DiamondTemperament::new(
+            limit5.pitches.clone(),
+            &[vec![1, 0, -4], vec![0, 1, 4]],
+            diamond,
+            DiamondFit::Minimax,
+            true,
+        )
+        .expect("no tuning")
+        .into_owned()
+    };
+    super::assert_between!(1896.57, rt.tuning[1], 1896.59);
+}
+
+#[test]
+fn rms_meantone() {
+    let limit5 = PrimeLimit::new(5);
+    let meantone = vec![vec![1, 0, -4], vec![0, 1, 4]];
+    let diamond = Diamond::new(&limit5, 5).expect("no diamond");
+    let rt = DiamondTemperament::new(
+        &limit5.pitches,
+        &meantone,
+        diamond.clone(),
+        DiamondFit::Rms,
+        true,
+    )
+    .expect("no tuning");
+    super::assert_between!(1199.999, rt.tuning[0], 1200.001);
+    // 7/26 comma
+    super::assert_between!(1896.15, rt.tuning[1], 1896.17);
+    let minimax = DiamondTemperament::new(
+        &limit5.pitches,
+        &meantone,
+        diamond.clone(),
+        DiamondFit::Minimax,
+        true,
+    )
+    .expect("no tuning");
+    assert!(rt.rms_error() < minimax.rms_error());
+    assert!(rt.max_error() > minimax.max_error());
+}
@@ -0,0 +1,133 @@
+//! What can go wrong when parsing names or calculating temperaments
+
+use std::fmt;
+
+#[derive(Debug, Clone, PartialEq)]
+pub enum Error {
+    /// Something that couldn't be read as a prime limit
+    BadLimit(String),
+    /// Something that couldn't be read as an equal temperament name
+    BadETName(String),
+    /// A letter in an ET name that isn't a wart for this limit
+    UnknownWart(char),
+    /// Something that couldn't be read as a ratio
+    BadRatio(String),
+    /// A Scala scale file that couldn't be read
+    BadScalaFile(String),
+    /// A ratio with a prime factor not in the limit
+    PrimeOutsideLimit(String),
+    /// Numbers too big for the integer types used by ratios
+    RatioOverflow,
+    /// Numbers too big for the integer types used by
+    /// mappings and unison vectors
+    Overflow,
+    /// A temperament class key that doesn't fit the prime limit
+    BadKey { dimension: usize, length: usize },
+    /// The temperament ended up with a different rank
+    /// to the one asked for
+    RankMismatch { expected: usize, found: usize },
+    /// A member of the prime limit that isn't an integer
+    /// so can't be part of a ratio
+    NonPrimeSubgroup(String),
+    /// A wedgie that doesn't fit the prime limit and rank
+    BadWedgie { dimension: usize, rank: usize, length: usize },
+    /// A multivector that isn't the wedge product of anything
+    NotDecomposable,
+    /// A vector with the wrong number of elements for the prime limit
+    DimensionMismatch { expected: usize, found: usize },
+    /// Temperament classes in different prime limits
+    LimitMismatch,
+    /// Intervals that can't all be just in the temperament class
+    ImpossibleJustIntervals,
+    /// An Lp norm with p less than 1 or not finite
+    BadNorm(f64),
+    /// Unison vectors that temper out everything
+    TooManyUnisonVectors,
+    /// The linear programming solver failed
+    Optimization(microlp::Error),
+    /// A nested minimax level with nothing at the worst error
+    NestedMinimax,
+}
+
+impl fmt::Display for Error {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            Error::BadLimit(limit) => {
+                write!(f, "Unrecognized prime limit {}", limit)
+            }
+            Error::BadETName(name) => {
+                write!(f, "Unrecognized equal temperament name {}", name)
+            }
+            Error::UnknownWart(wart) => {
+                write!(f, "Wart {} doesn't belong to this prime limit", wart)
+            }
+            Error::BadRatio(ratio) => {
+                write!(f, "Unrecognized ratio {}", ratio)
+            }
+            Error::PrimeOutsideLimit(ratio) => {
+                write!(f, "Ratio {} has a prime outside the limit", ratio)
+            }
+            Error::BadScalaFile(reason) => {
+                write!(f, "Unreadable Scala file: {}", reason)
+            }
+            Error::RatioOverflow => "Ratio too big to calculate".fmt(f),
+            Error::Overflow => {
+                "Integer overflow: numbers too big for this calculation"
+                    .fmt(f)
+            }
+            Error::BadKey { dimension, length } => write!(
+                f,
+                "Key of length {} doesn't fit a {}-dimensional limit",
+                length, dimension
+            ),
+            Error::RankMismatch { expected, found } => write!(
+                f,
+                "Expected a rank {} temperament but found rank {}",
+                expected, found
+            ),
+            Error::NonPrimeSubgroup(partial) => write!(
+                f,
+                "Subgroup element {} isn't an integer harmonic",
+                partial
+            ),
+            Error::BadWedgie { dimension, rank, length } => write!(
+                f,
+                "Wedgie of length {} doesn't fit rank {} \
+                 in a {}-dimensional limit",
+                length, rank, dimension
+            ),
+            Error::NotDecomposable => {
+                "Multivector isn't the wedgie of a temperament class".fmt(f)
+            }
+            Error::DimensionMismatch { expected, found } => write!(
+                f,
+                "Vector of length {} doesn't fit a {}-dimensional limit",
+                found, expected
+            ),
+            Error::LimitMismatch => {
+                "Temperament classes have different prime limits".fmt(f)
+            }
+            Error::ImpossibleJustIntervals => {
+                "These intervals can't all be just in this temperament".fmt(f)
+            }
+            Error::BadNorm(p) => {
+                write!(f, "Norm {} isn't a finite number of at least 1", p)
+            }
+            Error::TooManyUnisonVectors => {
+                "Too many unison vectors: whole space matches".fmt(f)
+            }
+            Error::Optimization(e) => write!(f, "Optimization failed: {}", e),
+            Error::NestedMinimax => {
+                "Nested minimax found nothing at the worst error".fmt(f)
+            }
+        }
+    }
+}
+
+impl std::error::Error for Error {}
+
+impl From<microlp::Error> for Error {
+    fn from(e: microlp::Error) -> Self {
+        Error::Optimization(e)
+    }
+}
@@ -0,0 +1,711 @@
+//! Exact integer linear algebra
+//!
+//! Everything works on i128 matrices so that intermediate results
+//! don't overflow for the sizes of mapping the rest of the crate uses.
+//! The arithmetic is checked, so anything that still overflows
+//! gives Error::Overflow instead of a wrong answer.
+//! Nothing gets rounded from floating point, so big vals are safe.
+//! Hermite normal forms can also use arbitrary precision integers
+//! with the "bigint" feature.
+//! Vectors are rows, like mappings and lists of unison vectors.
+
+use super::{ETMap, Error, Exponent, Mapping};
+
+pub type IntMatrix = Vec<Vec<i128>>;
+
+/// The Smith normal form of a matrix with the transformations
+/// that make it: left × original × right = smith.
+/// Both transformations are unimodular.
+#[derive(Clone, Debug, PartialEq)]
+pub struct SmithForm {
+    pub left: IntMatrix,
+    pub smith: IntMatrix,
+    pub right: IntMatrix,
+}
+
+impl SmithForm {
+    /// The non-zero diagonal entries, each dividing the next
+    pub fn invariant_factors(&self) -> Vec<i128> {
+        self.smith
+            .iter()
+            .enumerate()
+            .filter_map(|(i, row)| row.get(i).copied())
+            .filter(|&x| x != 0)
+            .collect()
+    }
+
+    pub fn rank(&self) -> usize {
+        self.invariant_factors().len()
+    }
+}
+
+pub fn from_mapping(mapping: &[ETMap]) -> IntMatrix {
+    mapping
+        .iter()
+        .map(|row| row.iter().map(|&x| i128::from(x)).collect())
+        .collect()
+}
+
+/// Convert back to a mapping, if it fits in Exponents
+pub fn to_mapping(matrix: &[Vec<i128>]) -> Result<Mapping, Error> {
+    matrix
+        .iter()
+        .map(|row| {
+            row.iter()
+                .map(|&x| Exponent::try_from(x).map_err(|_| Error::Overflow))
+                .collect()
+        })
+        .collect()
+}
+
+pub fn identity(n: usize) -> IntMatrix {
+    (0..n)
+        .map(|i| (0..n).map(|j| if i == j { 1 } else { 0 }).collect())
+        .collect()
+}
+
+pub fn transpose(matrix: &[Vec<i128>], n_cols: usize) -> IntMatrix {
+    (0..n_cols).map(|j| matrix.iter().map(|row| row[j]).collect()).collect()
+}
+
+pub fn multiply(
+    a: &[Vec<i128>],
+    b: &[Vec<i128>],
+) -> Result<IntMatrix, Error> {
+    let n_cols = b.first().map_or(0, Vec::len);
+    a.iter()
+        .map(|row| {
+            (0..n_cols)
+                .map(|j| {
+                    row.iter().zip(b).try_fold(0, |total: i128, (x, brow)| {
+                        x.checked_mul(brow[j])
+                            .and_then(|product| total.checked_add(product))
+                            .ok_or(Error::Overflow)
+                    })
+                })
+                .collect()
+        })
+        .collect()
+}
+
+/// Smith normal form of a matrix with any number of rows and columns
+pub fn smith_normal_form(mapping: &[ETMap]) -> Result<SmithForm, Error> {
+    let n_rows = mapping.len();
+    let n_cols = mapping.first().map_or(0, Vec::len);
+    debug_assert!(mapping.iter().all(|row| row.len() == n_cols));
+    let mut form = SmithForm {
+        left: identity(n_rows),
+        smith: from_mapping(mapping),
+        right: identity(n_cols),
+    };
+    for t in 0..n_rows.min(n_cols) {
+        if !form.move_pivot(t) {
+            break;
+        }
+        form.clear_pivot(t)?;
+        if form.smith[t][t] < 0 {
+            form.smith[t] = negate_row(&form.smith[t])?;
+            form.left[t] = negate_row(&form.left[t])?;
+        }
+    }
+    Ok(form)
+}
+
+fn negate_row(row: &[i128]) -> Result<Vec<i128>, Error> {
+    row.iter().map(|x| x.checked_neg().ok_or(Error::Overflow)).collect()
+}
+
+impl SmithForm {
+    /// Bring the smallest non-zero entry at or beyond (t, t) to (t, t).
+    /// Returns false if there's nothing left.
+    fn move_pivot(&mut self, t: usize) -> bool {
+        let n_cols = self.right.len();
+        let smallest = (t..self.smith.len())
+            .flat_map(|i| (t..n_cols).map(move |j| (i, j)))
+            .filter(|&(i, j)| self.smith[i][j] != 0)
+            .min_by_key(|&(i, j)| self.smith[i][j].unsigned_abs());
+        let Some((i, j)) = smallest else {
+            return false;
+        };
+        self.swap_rows(t, i);
+        self.swap_cols(t, j);
+        true
+    }
+
+    /// Make everything in row t and column t zero apart from the pivot,
+    /// with the pivot dividing everything after it
+    fn clear_pivot(&mut self, t: usize) -> Result<(), Error> {
+        let n_rows = self.smith.len();
+        let n_cols = self.right.len();
+        loop {
+            let mut clean = true;
+            for i in (t + 1)..n_rows {
+                let q = euclid_quotient(self.smith[i][t], self.smith[t][t])?;
+                self.add_row(i, t, q)?;
+                if self.smith[i][t] != 0 {
+                    // The remainder is a smaller pivot
+                    self.swap_rows(t, i);
+                    clean = false;
+                }
+            }
+            for j in (t + 1)..n_cols {
+                let q = euclid_quotient(self.smith[t][j], self.smith[t][t])?;
+                self.add_col(j, t, q)?;
+                if self.smith[t][j] != 0 {
+                    self.swap_cols(t, j);
+                    clean = false;
+                }
+            }
+            if !clean {
+                continue;
+            }
+            let pivot = self.smith[t][t];
+            let indivisible = ((t + 1)..n_rows).find(|&i| {
+                self.smith[i][(t + 1)..]
+                    .iter()
+                    .any(|x| x.checked_rem(pivot).is_some_and(|r| r != 0))
+            });
+            match indivisible {
+                // Bring the entry into row t to get a smaller pivot
+                Some(i) => self.add_row(t, i, 1)?,
+                None => return Ok(()),
+            }
+        }
+    }
+
+    /// Row i += multiple × row k
+    fn add_row(
+        &mut self,
+        i: usize,
+        k: usize,
+        multiple: i128,
+    ) -> Result<(), Error> {
+        if multiple == 0 {
+            return Ok(());
+        }
+        for matrix in [&mut self.smith, &mut self.left] {
+            let source = matrix[k].clone();
+            for (x, y) in matrix[i].iter_mut().zip(source) {
+                *x = add_product(*x, multiple, y)?;
+            }
+        }
+        Ok(())
+    }
+
+    /// Column j += multiple × column k
+    fn add_col(
+        &mut self,
+        j: usize,
+        k: usize,
+        multiple: i128,
+    ) -> Result<(), Error> {
+        if multiple == 0 {
+            return Ok(());
+        }
+        for matrix in [&mut self.smith, &mut self.right] {
+            for row in matrix.iter_mut() {
+                row[j] = add_product(row[j], multiple, row[k])?;
+            }
+        }
+        Ok(())
+    }
+
+    fn swap_rows(&mut self, i: usize, k: usize) {
+        self.smith.swap(i, k);
+        self.left.swap(i, k);
+    }
+
+    fn swap_cols(&mut self, j: usize, k: usize) {
+        for matrix in [&mut self.smith, &mut self.right] {
+            for row in matrix.iter_mut() {
+                row.swap(j, k);
+            }
+        }
+    }
+}
+
+/// x + multiple × y
+fn add_product(x: i128, multiple: i128, y: i128) -> Result<i128, Error> {
+    multiple
+        .checked_mul(y)
+        .and_then(|product| x.checked_add(product))
+        .ok_or(Error::Overflow)
+}
+
+/// The negative of the Euclidean quotient:
+/// the multiple to add that leaves the remainder
+fn euclid_quotient(x: i128, divisor: i128) -> Result<i128, Error> {
+    x.checked_div_euclid(divisor)
+        .and_then(i128::checked_neg)
+        .ok_or(Error::Overflow)
+}
+
+/// One step of fraction-free elimination:
+/// (x × pivot - left × above) / previous, which is always exact
+fn bareiss_step(
+    x: i128,
+    pivot: i128,
+    left: i128,
+    above: i128,
+    previous: i128,
+) -> Result<i128, Error> {
+    let scaled = x.checked_mul(pivot).ok_or(Error::Overflow)?;
+    let product = left.checked_mul(above).ok_or(Error::Overflow)?;
+    scaled
+        .checked_sub(product)
+        .and_then(|difference| difference.checked_div(previous))
+        .ok_or(Error::Overflow)
+}
+
+/// Exact determinant by fraction-free (Bareiss) elimination
+pub fn determinant(matrix: &[Vec<i128>]) -> Result<i128, Error> {
+    let mut matrix = matrix.to_vec();
+    let n = matrix.len();
+    debug_assert!(matrix.iter().all(|row| row.len() == n));
+    let mut sign = 1;
+    let mut previous = 1;
+    for k in 0..n {
+        if matrix[k][k] == 0 {
+            match ((k + 1)..n).find(|&i| matrix[i][k] != 0) {
+                Some(i) => {
+                    matrix.swap(i, k);
+                    sign = -sign;
+                }
+                None => return Ok(0),
+            }
+        }
+        for i in (k + 1)..n {
+            for j in (k + 1)..n {
+                matrix[i][j] = bareiss_step(
+                    matrix[i][j],
+                    matrix[k][k],
+                    matrix[i][k],
+                    matrix[k][j],
+                    previous,
+                )?;
+            }
+        }
+        previous = matrix[k][k];
+    }
+    if n == 0 {
+        Ok(1)
+    } else {
+        matrix[n - 1][n - 1].checked_mul(sign).ok_or(Error::Overflow)
+    }
+}
+
+/// The adjugate (classical adjoint) of a square matrix:
+/// the inverse times the determinant, but it always exists
+pub fn adjugate(matrix: &[Vec<i128>]) -> Result<IntMatrix, Error> {
+    let n = matrix.len();
+    debug_assert!(matrix.iter().all(|row| row.len() == n));
+    // Fraction-free Gauss-Jordan elimination next to an identity
+    // leaves the determinant times the identity on the left
+    // and the transformation that got there on the right,
+    // which is the adjugate (up to the sign of the row swaps)
+    let mut augmented: IntMatrix = matrix
+        .iter()
+        .zip(identity(n))
+        .map(|(row, unit)| [row.clone(), unit].concat())
+        .collect();
+    let mut sign = 1;
+    let mut previous = 1;
+    for k in 0..n {
+        if augmented[k][k] == 0 {
+            match ((k + 1)..n).find(|&i| augmented[i][k] != 0) {
+                Some(i) => {
+                    augmented.swap(i, k);
+                    sign = -sign;
+                }
+                // Singular, so there's no inverse to scale
+                None => {
+                    return (0..n).map(|i| adjugate_row(matrix, i)).collect();
+                }
+            }
+        }
+        for i in (0..n).filter(|&i| i != k) {
+            for j in (0..(2 * n)).filter(|&j| j != k) {
+                augmented[i][j] = bareiss_step(
+                    augmented[i][j],
+                    augmented[k][k],
+                    augmented[i][k],
+                    augmented[k][j],
+                    previous,
+                )?;
+            }
+            augmented[i][k] = 0;
+        }
+        previous = augmented[k][k];
+    }
+    augmented
+        .into_iter()
+        .map(|row| {
+            row[n..]
+                .iter()
+                .map(|x| x.checked_mul(sign).ok_or(Error::Overflow))
+                .collect()
+        })
+        .collect()
+}
+
+/// Row i of the adjugate of a square matrix, from the cofactors
+/// of column i, without calculating the whole adjugate
+pub fn adjugate_row(
+    matrix: &[Vec<i128>],
+    i: usize,
+) -> Result<Vec<i128>, Error> {
+    let n = matrix.len();
+    debug_assert!(matrix.iter().all(|row| row.len() == n));
+    if n == 1 {
+        return Ok(vec![1]);
+    }
+    (0..n)
+        .map(|j| {
+            // Cofactor of (j, i) for the transpose
+            let minor: IntMatrix = matrix
+                .iter()
+                .enumerate()
+                .filter(|&(row, _)| row != j)
+                .map(|(_, row)| {
+                    row.iter()
+                        .enumerate()
+                        .filter(|&(col, _)| col != i)
+                        .map(|(_, &x)| x)
+                        .collect()
+                })
+                .collect();
+            let cofactor = determinant(&minor)?;
+            if (i + j).is_multiple_of(2) {
+                Ok(cofactor)
+            } else {
+                cofactor.checked_neg().ok_or(Error::Overflow)
+            }
+        })
+        .collect()
+}
+
+/// Integers wide enough for Hermite normal forms
+/// that overflow Exponents.
+/// With the "bigint" feature, they can be any size.
+#[cfg(not(feature = "bigint"))]
+pub type WideInt = i128;
+#[cfg(feature = "bigint")]
+pub type WideInt = num_bigint::BigInt;
+
+/// Integer arithmetic for a Hermite normal form
+/// that says when it overflows
+pub trait HermiteInt: Clone + Ord + From<Exponent> {
+    /// self - q × y
+    fn sub_product(&self, q: &Self, y: &Self) -> Option<Self>;
+    /// Division rounding down
+    fn quotient(&self, divisor: &Self) -> Option<Self>;
+    fn negate(&self) -> Option<Self>;
+    /// Absolute value, or something huge if it doesn't fit
+    fn magnitude(&self) -> Self;
+    fn to_exponent(&self) -> Option<Exponent>;
+}
+
+impl HermiteInt for i128 {
+    fn sub_product(&self, q: &Self, y: &Self) -> Option<Self> {
+        q.checked_mul(*y).and_then(|qy| self.checked_sub(qy))
+    }
+
+    fn quotient(&self, divisor: &Self) -> Option<Self> {
+        let q = self.checked_div(*divisor)?;
+        // Round towards negative infinity
+        if q * divisor != *self && (*self < 0) != (*divisor < 0) {
+            Some(q - 1)
+        } else {
+            Some(q)
+        }
+    }
+
+    fn negate(&self) -> Option<Self> {
+        self.checked_neg()
+    }
+
+    fn magnitude(&self) -> Self {
+        self.checked_abs().unwrap_or(i128::MAX)
+    }
+
+    fn to_exponent(&self) -> Option<Exponent> {
+        Exponent::try_from(*self).ok()
+    }
+}
+
+#[cfg(feature = "bigint")]
+impl HermiteInt for num_bigint::BigInt {
+    fn sub_product(&self, q: &Self, y: &Self) -> Option<Self> {
+        Some(self - q * y)
+    }
+
+    fn quotient(&self, divisor: &Self) -> Option<Self> {
+        use num_integer::Integer;
+        Some(self.div_floor(divisor))
+    }
+
+    fn negate(&self) -> Option<Self> {
+        Some(-self)
+    }
+
+    fn magnitude(&self) -> Self {
+        num_bigint::BigInt::from(self.magnitude().clone())
+    }
+
+    fn to_exponent(&self) -> Option<Exponent> {
+        Exponent::try_from(self).ok()
+    }
+}
+
+/// Row Hermite normal form with i128 entries.
+/// Zero rows go at the bottom.
+pub fn hermite_form(matrix: &[Vec<i128>]) -> Result<IntMatrix, Error> {
+    checked_hermite_form(matrix).ok_or(Error::Overflow)
+}
+
+/// Row Hermite normal form, or None if anything overflows.
+/// Zero rows go at the bottom.
+pub fn checked_hermite_form<T: HermiteInt>(
+    matrix: &[Vec<T>],
+) -> Option<Vec<Vec<T>>> {
+    let zero = T::from(0);
+    let mut result = matrix.to_vec();
+    let n_cols = result.first().map_or(0, Vec::len);
+    let mut pivot_row = 0;
+    for col in 0..n_cols {
+        // Euclid's algorithm down the column
+        loop {
+            let smallest = (pivot_row..result.len())
+                .filter(|&i| result[i][col] != zero)
+                .min_by_key(|&i| result[i][col].magnitude());
+            let Some(smallest) = smallest else { break };
+            result.swap(pivot_row, smallest);
+            let pivot = result[pivot_row].clone();
+            let mut cleared = true;
+            for row in result[(pivot_row + 1)..].iter_mut() {
+                subtract_multiple(row, &pivot, col)?;
+                cleared &= row[col] == zero;
+            }
+            if cleared {
+                break;
+            }
+        }
+        let Some(row) = result.get_mut(pivot_row) else { break };
+        if row[col] == zero {
+            continue;
+        }
+        if row[col] < zero {
+            *row = row.iter().map(T::negate).collect::<Option<_>>()?;
+        }
+        let pivot = row.clone();
+        for row in result[..pivot_row].iter_mut() {
+            subtract_multiple(row, &pivot, col)?;
+        }
+        pivot_row += 1;
+    }
+    Some(result)
+}
+
+/// Subtract the multiple of the pivot that leaves row[col]
+/// between zero and pivot[col]
+fn subtract_multiple<T: HermiteInt>(
+    row: &mut [T],
+    pivot: &[T],
+    col: usize,
+) -> Option<()> {
+    let q = row[col].quotient(&pivot[col])?;
+    for (x, y) in row.iter_mut().zip(pivot) {
+        *x = x.sub_product(&q, y)?;
+    }
+    Some(())
+}
+
+/// Hermite normal form as hermite_normal_form gives it,
+/// but calculated with WideInts.
+/// Only the result has to fit in Exponents.
+pub fn wide_hermite_normal_form(vectors: &[ETMap]) -> Result<Mapping, Error> {
+    checked_hermite_form(&widen(vectors))
+        .ok_or(Error::Overflow)?
+        .iter()
+        .map(|row| {
+            row.iter()
+                .map(|x| x.to_exponent().ok_or(Error::Overflow))
+                .collect()
+        })
+        .collect()
+}
+
+/// Number of linearly independent vectors.
+/// This can only overflow without the "bigint" feature.
+pub fn rank(vectors: &[ETMap]) -> Result<usize, Error> {
+    let zero = WideInt::from(0);
+    Ok(checked_hermite_form(&widen(vectors))
+        .ok_or(Error::Overflow)?
+        .iter()
+        .filter(|row| row.iter().any(|x| *x != zero))
+        .count())
+}
+
+fn widen(vectors: &[ETMap]) -> Vec<Vec<WideInt>> {
+    vectors
+        .iter()
+        .map(|row| row.iter().map(|&x| WideInt::from(x)).collect())
+        .collect()
+}
+
+/// A basis for the integer vectors that every vector here
+/// is orthogonal to, in Hermite normal form.
+/// This is always saturated.
+pub fn integer_kernel(vectors: &[ETMap]) -> Result<Mapping, Error> {
+    let Some(n_cols) = vectors.first().map(Vec::len) else {
+        return Ok(vec![]);
+    };
+    let n_rows = vectors.len();
+    // Row reduce the transpose next to an identity matrix
+    // to keep track of the unimodular transformation
+    let augmented: IntMatrix = transpose(&from_mapping(vectors), n_cols)
+        .into_iter()
+        .zip(identity(n_cols))
+        .map(|(row, unit)| [row, unit].concat())
+        .collect();
+    let basis: IntMatrix = hermite_form(&augmented)?
+        .into_iter()
+        .filter(|row| row[..n_rows].iter().all(|&x| x == 0))
+        .map(|row| row[n_rows..].to_vec())
+        .collect();
+    to_mapping(&nonzero_rows(hermite_form(&basis)?))
+}
+
+/// A basis for the lattice the vectors span, in Hermite normal form
+pub fn integer_image(vectors: &[ETMap]) -> Result<Mapping, Error> {
+    to_mapping(&nonzero_rows(hermite_form(&from_mapping(vectors))?))
+}
+
+fn nonzero_rows(matrix: IntMatrix) -> IntMatrix {
+    matrix.into_iter().filter(|row| row.iter().any(|&x| x != 0)).collect()
+}
+
+/// A basis for all the integer vectors in the rational span of these,
+/// in Hermite normal form
+pub fn saturation(vectors: &[ETMap]) -> Result<Mapping, Error> {
+    let Some(n_cols) = vectors.first().map(Vec::len) else {
+        return Ok(vec![]);
+    };
+    let kernel = integer_kernel(vectors)?;
+    if kernel.is_empty() {
+        return to_mapping(&identity(n_cols));
+    }
+    integer_kernel(&kernel)
+}
+
+#[test]
+fn conversions() {
+    let mapping = vec![vec![1, 2, 3], vec![4, 5, 6]];
+    let matrix = from_mapping(&mapping);
+    assert_eq!(matrix, [[1, 2, 3], [4, 5, 6]]);
+    assert_eq!(to_mapping(&matrix), Ok(mapping));
+    assert_eq!(transpose(&matrix, 3), [[1, 4], [2, 5], [3, 6]]);
+    assert_eq!(to_mapping(&[]), Ok(Mapping::new()));
+    assert_eq!(to_mapping(&[vec![1 << 80]]), Err(Error::Overflow));
+}
+
+#[test]
+fn smith_example() {
+    let matrix = vec![vec![2, 4, 4], vec![-6, 6, 12], vec![10, -4, -16]];
+    let form = smith_normal_form(&matrix).unwrap();
+    assert_eq!(form.invariant_factors(), vec![2, 6, 12]);
+    let left = multiply(&form.left, &from_mapping(&matrix)).unwrap();
+    assert_eq!(multiply(&left, &form.right), Ok(form.smith));
+    assert_eq!(determinant(&form.left).unwrap().abs(), 1);
+    assert_eq!(determinant(&form.right).unwrap().abs(), 1);
+}
+
+#[test]
+fn smith_rectangular() {
+    let marvel = vec![
+        vec![22, 35, 51, 62, 76],
+        vec![31, 49, 72, 87, 107],
+        vec![41, 65, 95, 115, 142],
+    ];
+    let form = smith_normal_form(&marvel).unwrap();
+    assert_eq!(form.invariant_factors(), vec![1, 1, 1]);
+    let left = multiply(&form.left, &from_mapping(&marvel)).unwrap();
+    assert_eq!(multiply(&left, &form.right), Ok(form.smith));
+    let doubled = vec![vec![24, 38, 56], vec![19, 30, 44]];
+    let form = smith_normal_form(&doubled).unwrap();
+    assert_eq!(form.invariant_factors(), vec![1, 2]);
+}
+
+#[test]
+fn determinants() {
+    assert_eq!(determinant(&[]), Ok(1));
+    assert_eq!(determinant(&[vec![0, 1], vec![1, 0]]), Ok(-1));
+    assert_eq!(
+        adjugate(&[vec![2, 1], vec![1, 1]]),
+        Ok(vec![vec![1, -1], vec![-1, 2]])
+    );
+    assert_eq!(adjugate(&[vec![5]]), Ok(vec![vec![1]]));
+    let huge = 1 << 100;
+    assert_eq!(
+        determinant(&[vec![huge, 1], vec![1, huge]]),
+        Err(Error::Overflow)
+    );
+    for matrix in [
+        vec![vec![2, 0, 1], vec![1, 3, 2], vec![1, 1, 4]],
+        // Needs row swaps
+        vec![vec![0, 2, 1], vec![3, 0, 2], vec![1, 1, 0]],
+        vec![
+            vec![0, 0, 1, 2],
+            vec![0, 3, 0, 1],
+            vec![4, 0, 0, 0],
+            vec![1, 1, 1, 1],
+        ],
+        // Singular with and without a zero adjugate
+        vec![vec![1, 2, 3], vec![2, 4, 6], vec![1, 0, 1]],
+        vec![vec![1, 2, 3], vec![2, 4, 6], vec![3, 6, 9]],
+    ] {
+        let n = matrix.len();
+        let det = determinant(&matrix).unwrap();
+        let scaled: IntMatrix = identity(n)
+            .iter()
+            .map(|row| row.iter().map(|x| x * det).collect())
+            .collect();
+        let adjugate = adjugate(&matrix).unwrap();
+        assert_eq!(multiply(&adjugate, &matrix), Ok(scaled));
+        for (i, row) in adjugate.iter().enumerate() {
+            assert_eq!(&adjugate_row(&matrix, i).unwrap(), row);
+        }
+    }
+    assert_eq!(
+        determinant(&[vec![2, 0, 1], vec![1, 3, 2], vec![1, 1, 4]]),
+        Ok(18)
+    );
+}
+
+#[test]
+fn hermite_forms() {
+    let matrix = vec![vec![12, 19, 28], vec![19, 30, 44], vec![31, 49, 72]];
+    let hermite = hermite_form(&from_mapping(&matrix)).unwrap();
+    assert_eq!(to_mapping(&hermite), Ok(super::hermite_normal_form(&matrix)));
+    let huge = 1 << 126;
+    assert_eq!(
+        hermite_form(&[vec![huge, 3], vec![3, huge]]),
+        Err(Error::Overflow)
+    );
+}
+
+#[test]
+fn kernel_and_image() {
+    let meantone = vec![vec![12, 19, 28], vec![19, 30, 44]];
+    assert_eq!(integer_kernel(&meantone), Ok(vec![vec![4, -4, 1]]));
+    assert_eq!(
+        integer_image(&meantone),
+        Ok(vec![vec![1, 0, -4], vec![0, 1, 4]])
+    );
+    let doubled = vec![vec![24, 38, 56], vec![19, 30, 44]];
+    assert_eq!(saturation(&doubled), integer_image(&meantone));
+    assert_eq!(saturation(&[vec![2, 4, 6]]), Ok(vec![vec![1, 2, 3]]));
+    assert_eq!(rank(&doubled), Ok(2));
+    assert_eq!(rank(&[vec![1, 2, 3], vec![2, 4, 6]]), Ok(1));
+    assert_eq!(rank(&[]), Ok(0));
+}
@@ -5,9 +5,10 @@
 use lazy_static::lazy_static;
 use num_integer::div_floor;
 use std::collections::HashMap;
-use std::fmt;
 use std::str::FromStr;
 
+pub use error::Error;
+
 pub type Cents = f64;
 // Human hearing covers about 10 octaves,
 // which means 11 bits (assuming the root is 1).
@@ -25,7 +26,8 @@ lazy_static! {
     static ref PRIME_WARTS: HashMap<String, char> = prime_warts();
 }
 
-#[derive(Debug, Clone)]
+#[derive(Debug, Clone, PartialEq)]
+#[cfg_attr(feature = "serde", derive(serde::Serialize, serde::Deserialize))]
 pub struct PrimeLimit {
     /// Something used for printing
     pub label: String,
@@ -111,12 +113,12 @@ impl PrimeLimit {
 }
 
 impl FromStr for PrimeLimit {
-    type Err = ParseLimitError;
+    type Err = Error;
 
-    fn from_str(src: &str) -> Result<PrimeLimit, ParseLimitError> {
+    fn from_str(src: &str) -> Result<PrimeLimit, Error> {
         if let Ok(limit) = src.parse() {
             if limit == 0 {
-                Err(ParseLimitError {})
+                Err(Error::BadLimit(src.to_string()))
             } else {
                 Ok(PrimeLimit::new(limit))
             }
@@ -125,7 +127,7 @@ impl FromStr for PrimeLimit {
         {
             Ok(limit)
         } else {
-            Err(ParseLimitError {})
+            Err(Error::BadLimit(src.to_string()))
         }
     }
 }
@@ -175,59 +177,76 @@ pub fn warted_et_name(plimit: &PrimeLimit, et: &ETSlice) -> String {
     name
 }
 
-pub fn et_from_name(plimit: &PrimeLimit, name: &str) -> Option<ETMap> {
-    let mut name = name.to_string();
+pub fn et_from_name(plimit: &PrimeLimit, name: &str) -> Result<ETMap, Error> {
+    let bad_name = || Error::BadETName(name.to_string());
+    let mut remaining = name.to_string();
     let warts = plimit.warts();
-    let octave_size = if warts.contains(&name.chars().next()?) {
-        let octave_wart = name.remove(0);
-        *plimit.pitches.get(warts.iter().position(|&c| c == octave_wart)?)?
+    let first = remaining.chars().next().ok_or_else(bad_name)?;
+    let octave_size = if let Some(i) = warts.iter().position(|&c| c == first)
+    {
+        remaining.remove(0);
+        *plimit.pitches.get(i).ok_or_else(bad_name)?
     } else {
-        match name.parse::<usize>() {
+        match remaining.parse::<usize>() {
             // A plain integer is the number of steps
             // to the first element of the plimit
-            Ok(_) => *plimit.pitches.first()?,
+            Ok(_) => *plimit.pitches.first().ok_or_else(bad_name)?,
             // A warted name is based on 1200 cents
             // when there is no prefix wart
             Err(_) => 1200.0,
         }
     };
-    if name.ends_with('p') {
+    if remaining.ends_with('p') {
         // Time to strip this out
-        name.pop().expect("p gone missing");
+        remaining.pop().expect("p gone missing");
     }
     let mut wart_counts: HashMap<_, Exponent> = HashMap::new();
-    while warts.contains(&name.chars().last()?) {
-        let wart = name.pop()?;
-        wart_counts.insert(wart, wart_counts.get(&wart).unwrap_or(&0) + 1);
+    while let Some(wart) = remaining.pop() {
+        if !warts.contains(&wart) {
+            remaining.push(wart);
+            break;
+        }
+        *wart_counts.entry(wart).or_insert(0) += 1;
     }
-    let n_notes: usize = name.parse().ok()?;
+    let n_notes: usize = remaining.parse().map_err(|_| {
+        // A number followed by letters has a wart from somewhere else
+        let digits =
+            remaining.trim_start_matches(|c: char| c.is_ascii_digit());
+        match digits.chars().next() {
+            Some(c) if c.is_alphabetic() && digits != remaining => {
+                Error::UnknownWart(c)
+            }
+            _ => bad_name(),
+        }
+    })?;
     let scaler = n_notes as f64 / octave_size;
-    Some(
-        plimit
-            .pitches
-            .iter()
-            .zip(warts)
-            .map(|(&pitch, wart)| {
-                let target = pitch * scaler;
-                let nearest = target.round();
-                if let Some(&count) = wart_counts.get(&wart) {
-                    let nearest_sharp = nearest > target;
-                    let (approx_sharp, correction) = if (count % 2) == 0 {
-                        (nearest_sharp, count / 2)
-                    } else {
-                        (!nearest_sharp, (count + 1) / 2)
-                    };
-                    nearest as Exponent
-                        + if approx_sharp { correction } else { -correction }
+    Ok(plimit
+        .pitches
+        .iter()
+        .zip(warts)
+        .map(|(&pitch, wart)| {
+            let target = pitch * scaler;
+            let nearest = target.round();
+            if let Some(&count) = wart_counts.get(&wart) {
+                let nearest_sharp = nearest > target;
+                let (approx_sharp, correction) = if (count % 2) == 0 {
+                    (nearest_sharp, count / 2)
                 } else {
-                    nearest as Exponent
-                }
-            })
-            .collect(),
-    )
+                    (!nearest_sharp, (count + 1) / 2)
+                };
+                nearest as Exponent
+                    + if approx_sharp { correction } else { -correction }
+            } else {
+                nearest as Exponent
+            }
+        })
+        .collect())
 }
 
-pub fn mapping_from_name(plimit: &PrimeLimit, name: &str) -> Option<Mapping> {
+pub fn mapping_from_name(
+    plimit: &PrimeLimit,
+    name: &str,
+) -> Result<Mapping, Error> {
     name.replace(['&', '+', '_', ','], " ")
         .split_whitespace()
         .map(|etname| et_from_name(plimit, etname))
@@ -269,15 +288,6 @@ fn next_char(current: char) -> char {
     }
 }
 
-#[derive(Debug)]
-pub struct ParseLimitError {}
-
-impl fmt::Display for ParseLimitError {
-    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
-        "Unrecognized prime limit".fmt(f)
-    }
-}
-
 pub fn normalize_positive(limit: &[Cents], rsvec: ETMap) -> ETMap {
     let pitch_width: Cents =
         limit.iter().zip(rsvec.iter()).map(|(&x, &y)| x * (y as Cents)).sum();
@@ -329,9 +339,24 @@ fn primes_below(n: Harmonic) -> Vec<Harmonic> {
 /// Convert the matrix to a unique column echelon form
 /// with everything as simple as possible,
 /// things positive when they can't be zero,
-/// and within the same lattice (determinant conserved)
+/// and within the same lattice (determinant conserved).
+/// Panics if the result doesn't fit in Exponents.
 pub fn hermite_normal_form(ets: &[ETMap]) -> Mapping {
-    let mut echelon = echelon_form(ets);
+    checked_hermite_normal_form(ets)
+        .expect("Hermite normal form too big for Exponents")
+}
+
+/// Hermite normal form with an error if the result is too big.
+/// Intermediate results that overflow get calculated again
+/// with wider integers (from lattice::WideInt).
+pub fn checked_hermite_normal_form(ets: &[ETMap]) -> Result<Mapping, Error> {
+    narrow_hermite_normal_form(ets)
+        .or_else(|_| lattice::wide_hermite_normal_form(ets))
+}
+
+/// Hermite normal form with only Exponent arithmetic
+fn narrow_hermite_normal_form(ets: &[ETMap]) -> Result<Mapping, Error> {
+    let mut echelon = checked_echelon_form(ets)?;
     // Looks like rows and columns are the other way round from normal
     debug_assert!(ets.iter().all(|col| col.len() == ets[0].len()));
     for col in 1..echelon.len() {
@@ -348,25 +373,32 @@ pub fn hermite_normal_form(ets: &[ETMap]) -> Mapping {
                 if s == 0 {
                     continue;
                 }
+                let q = div_floor(s, n);
                 for (x, y) in scol.iter_mut().zip(top_col.iter()) {
-                    *x -= div_floor(s, n) * y;
+                    *x = sub_product(*x, q, *y)?;
                 }
                 debug_assert!(scol[row] >= 0);
                 debug_assert!(scol[row] < n);
             }
         }
     }
-    echelon
+    Ok(echelon)
 }
 
-/// Echelon form without the extra reduction to get HNF
+/// Echelon form without the extra reduction to get HNF.
+/// Panics if the numbers get too big for Exponents.
 pub fn echelon_form(ets: &[ETMap]) -> Mapping {
+    checked_echelon_form(ets).expect("Echelon form too big for Exponents")
+}
+
+/// Echelon form with an error if the numbers get too big
+pub fn checked_echelon_form(ets: &[ETMap]) -> Result<Mapping, Error> {
     echelon_rec(ets.to_vec(), 0)
 }
 
-fn echelon_rec(mut working: Mapping, row: usize) -> Mapping {
+fn echelon_rec(mut working: Mapping, row: usize) -> Result<Mapping, Error> {
     let Some(first) = working.first() else {
-        return working;
+        return Ok(working);
     };
     let nrows = first.len();
     debug_assert!(working.iter().all(|col| col.len() == nrows));
@@ -376,12 +408,15 @@ fn echelon_rec(mut working: Mapping, row: usize) -> Mapping {
         if let Some(first_non_zero) = column.iter().find(|&&n| n != 0)
             && *first_non_zero < 0
         {
-            *column = map(|&x| -x, column);
+            *column = column
+                .iter()
+                .map(|x| x.checked_neg().ok_or(Error::Overflow))
+                .collect::<Result<_, _>>()?;
         }
     }
 
     if row == nrows {
-        return working;
+        return Ok(working);
     }
     debug_assert!(row < nrows);
 
@@ -395,8 +430,8 @@ fn echelon_rec(mut working: Mapping, row: usize) -> Mapping {
         working.retain(|col| col[row] != 0);
 
         if working.len() < 2 {
-            working.extend_from_slice(&echelon_rec(reduced, row + 1));
-            return working;
+            working.extend_from_slice(&echelon_rec(reduced, row + 1)?);
+            return Ok(working);
         }
 
         working.sort_unstable_by(|a, b| {
@@ -411,14 +446,24 @@ fn echelon_rec(mut working: Mapping, row: usize) -> Mapping {
         // pivot_element must be non-zero or it would be in reduced
         debug_assert!(pivot_element != 0);
         for col in workings {
-            let n = col[row] / pivot_element;
+            let n =
+                col[row].checked_div(pivot_element).ok_or(Error::Overflow)?;
             for (i, &x) in pivot.iter().enumerate() {
-                col[i] -= x * n;
+                col[i] = sub_product(col[i], x, n)?;
             }
         }
     }
 }
 
+/// a - b × c or an error if it overflows
+fn sub_product(
+    a: Exponent,
+    b: Exponent,
+    c: Exponent,
+) -> Result<Exponent, Error> {
+    b.checked_mul(c).and_then(|bc| a.checked_sub(bc)).ok_or(Error::Overflow)
+}
+
 /// Container to keep results ordered by badness
 /// and throw away the bad ones.
 /// Prioritized by badness: low values are preferred.
@@ -488,13 +533,26 @@ macro_rules! assert_between {
 }
 
 pub mod cangwu;
+pub mod constrained;
+pub mod diamond;
+pub mod error;
+pub mod lattice;
+pub mod mos;
+pub mod mts;
 pub mod names;
 pub mod ratio;
+pub mod scala;
+pub mod search;
+pub mod snapshot;
 pub mod te;
 pub mod temperament_class;
 pub mod top;
+pub mod tp;
 pub mod tuned_temperament;
+pub mod tuning_file;
 pub mod uv;
+pub mod wedgie;
+pub mod weighting;
 
 #[cfg(target_arch = "wasm32")]
 pub mod wasm;
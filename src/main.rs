@@ -1,54 +1,225 @@
-use regular::{Cents, Harmonic, PrimeLimit};
+use regular::cangwu::{CangwuTemperament, et_name, higher_rank_search};
+use regular::constrained::ConstrainedTemperament;
+use regular::diamond::{Diamond, DiamondFit, DiamondTemperament};
+use regular::mos::Mos;
+use regular::mts::bulk_dump;
+use regular::ratio::{
+    get_ratio_or_ket_string, parse_as_vector, parse_in_simplest_limit,
+    stringify,
+};
+use regular::search::{CancelToken, Certificate, Search, exact_search};
+use regular::te::TETemperament;
+use regular::temperament_class::TemperamentClass;
+use regular::top::TOPTemperament;
+use regular::tp::TpTemperament;
+use regular::tuned_temperament::TunedTemperament;
+use regular::tuning_file::{Scale, TuningFormat};
+use regular::uv::{
+    ek_for_search, filter_unison_vectors, get_ets_tempering_out,
+    unison_vector_basis,
+};
+use regular::wedgie::saturation_index;
+use regular::{Cents, ETMap, Error, Exponent, Harmonic, Mapping, PrimeLimit};
 use std::io::{self, BufRead, Write, stdout};
+use std::thread;
+use std::time::Duration;
+
+/// Largest safety margin to try with --exact
+const MAX_SAFETY: usize = 640;
+
+const USAGE: &str = "\
+Usage: regular-cli [OPTIONS] [COMMAND] ARGUMENTS
+
+Commands:
+  search N_RESULTS EK LIMIT...  search by badness (the default command)
+  uv N_RESULTS COMMA...         temperaments tempering out the commas
+  rt LIMIT ETS                  report on a temperament like \"22 & 31\"
+  subsets N_RESULTS LIMIT ETS   lower rank temperaments including this one
+  scala LIMIT ETS N_NOTES       tuning file for a Fokker block
+  mos LIMIT ETS MAX_NOTES       MOS scales of a rank 2 temperament
+  mts LIMIT ETS N_NOTES         MIDI Tuning Standard bulk dump (.syx)
+  diamond LIMIT ETS ODD_LIMIT   minimax tuning for a tonality diamond
+
+Options:
+  --format FORMAT   lists (the default), json, or jsonl
+  --limit LIMIT     prime limit for uv (found from the commas by default)
+  --error MULTIPLE  target error multiplier for uv and subsets (default 2)
+  --tuning TUNING   te (the default), pote, cte, top, toppo, or tp
+                    for scala and mts
+  --norm P          p for the Lp norm of tp tunings (default 2)
+                    and to add a Tp tuning to rt
+  --mos             scala or mts for a MOS instead of a Fokker block
+  --file-format EXT scl (the default), tun (AnaMark), or ascl (Ableton)
+                    for scala
+  --rms             least squares instead of minimax for diamond
+  --pure-octaves    keep octaves just for diamond
+  --exact           make the search big enough to prove
+                    no better results were missed
+  --no-contorsion   leave contorted temperament classes out of searches
+  --progress        report how the search is going on stderr
+  --timeout SECONDS stop the search after this long
+                    and only show the ranks that finished";
+
+/// How the results get printed
+#[derive(Clone, Copy, PartialEq)]
+enum OutputFormat {
+    /// Nested lists of mappings, one line for each rank,
+    /// or plain text for a report
+    Lists,
+    /// A single JSON document with everything in it
+    Json,
+    /// A JSON document for each rank, one to a line,
+    /// written as soon as the rank is finished
+    JsonLines,
+}
+
+/// Settings from the arguments starting with --
+struct Options {
+    format: OutputFormat,
+    limit: Option<String>,
+    error: Option<Cents>,
+    tuning: String,
+    norm: Option<f64>,
+    exact: bool,
+    progress: bool,
+    no_contorsion: bool,
+    mos: bool,
+    rms: bool,
+    pure_octaves: bool,
+    file_format: TuningFormat,
+    timeout: Option<Duration>,
+}
 
 fn main() -> Result<(), String> {
-    let (n_results, ek, limit) = command_line_args()?;
+    let (options, args) = split_options(std::env::args().skip(1))?;
+    match args.first().map(String::as_str) {
+        Some("search") => search_command(&options, &args[1..]),
+        Some("uv") => uv_command(&options, &args[1..]),
+        Some("rt") => rt_command(&options, &args[1..]),
+        Some("subsets") => subsets_command(&options, &args[1..]),
+        Some("scala") => scala_command(&options, &args[1..]),
+        Some("mos") => mos_command(&options, &args[1..]),
+        Some("mts") => mts_command(&options, &args[1..]),
+        Some("diamond") => diamond_command(&options, &args[1..]),
+        Some("help") => {
+            print_return_closed(USAGE);
+            Ok(())
+        }
+        None => Err("No arguments: try regular-cli help".to_string()),
+        // Searching is the original behaviour so it stays the default
+        Some(_) => search_command(&options, &args),
+    }
+}
 
-    let dimension = limit.pitches.len();
-    let safety = if dimension < 100 {
-        40
-    } else {
-        4 * (dimension as f64).sqrt().floor() as usize
-    };
-    let mappings = regular::cangwu::get_equal_temperaments(
-        &limit.pitches,
-        ek,
-        n_results + safety,
-    );
-    let mut rts = Vec::with_capacity(mappings.len());
-    for mapping in mappings.iter() {
-        rts.push(vec![mapping.clone()]);
-    }
-    for rank in 2..dimension {
-        let eff_n_results =
-            n_results + if rank == dimension - 1 { 0 } else { safety };
-        let new_rts = regular::cangwu::higher_rank_search(
-            &limit.pitches,
-            &mappings,
-            &rts,
+/// Search by badness, like the general form on the web page
+fn search_command(options: &Options, args: &[String]) -> Result<(), String> {
+    let (n_results, ek, limit) = search_args(args)?;
+
+    let cancel = CancelToken::new();
+    if let Some(timeout) = options.timeout {
+        let cancel = cancel.clone();
+        // Stop after the time limit, keeping the ranks already found
+        thread::spawn(move || {
+            thread::sleep(timeout);
+            cancel.cancel();
+        });
+    }
+    if options.exact {
+        return exact_search_command(options, n_results, ek, &limit, &cancel);
+    }
+    let mut search =
+        Search::new(&limit.pitches, ek, n_results).with_cancel(cancel);
+    if options.no_contorsion {
+        search = search.without_contorsion();
+    }
+    if options.progress {
+        search = search.with_progress(|progress| {
+            let n_notes = match progress.n_notes {
+                Some(n_notes) => format!(" {}-note", n_notes),
+                None => String::new(),
+            };
+            eprintln!(
+                "rank {}{}: cap {:.3} after {} candidates",
+                progress.rank, n_notes, progress.cap, progress.candidates,
+            );
+        });
+    }
+    let mut documents = Vec::new();
+    for rts in search {
+        if output_return_closed(
+            options,
+            &limit,
             ek,
-            eff_n_results,
-        );
-        rts.truncate(n_results);
-        if print_return_closed(&rts) {
+            &rts,
+            None,
+            &mut documents,
+        ) {
             // Return silently if stdout is closed
             return Ok(());
         }
-        rts = new_rts;
     }
-    print_return_closed(&rts);
+    finish_output(options, &limit, ek, &documents);
+    Ok(())
+}
+
+/// Search with big enough safety margins to prove nothing was missed,
+/// or say how much might have been
+fn exact_search_command(
+    options: &Options,
+    n_results: usize,
+    ek: Cents,
+    limit: &PrimeLimit,
+    cancel: &CancelToken,
+) -> Result<(), String> {
+    let results =
+        exact_search(&limit.pitches, ek, n_results, MAX_SAFETY, cancel);
+    let mut documents = Vec::new();
+    for (rts, certificate) in results.ranks.iter().zip(&results.certificates)
+    {
+        if !certificate.is_exact() {
+            eprintln!(
+                "Rank {} is only complete below badness {:.3e} \
+                 but the worst result is {:.3e}",
+                certificate.rank,
+                certificate.complete_below,
+                certificate.worst,
+            );
+        }
+        // Filtered here because exact_search keeps everything
+        let rts: Vec<Mapping> = rts
+            .iter()
+            .filter(|rt| {
+                !options.no_contorsion || saturation_index(rt) == Ok(1)
+            })
+            .cloned()
+            .collect();
+        if output_return_closed(
+            options,
+            limit,
+            ek,
+            &rts,
+            Some(certificate),
+            &mut documents,
+        ) {
+            return Ok(());
+        }
+    }
+    if !results.finished {
+        eprintln!("The search stopped before it finished");
+    }
+    finish_output(options, limit, ek, &documents);
     Ok(())
 }
 
-fn command_line_args() -> Result<(usize, Cents, PrimeLimit), String> {
-    let mut args = std::env::args();
+fn search_args(
+    args: &[String],
+) -> Result<(usize, Cents, PrimeLimit), String> {
+    let mut args = args.iter();
 
-    if let (Some(_), Some(n_results), Some(ek), Some(limit1)) =
-        (args.next(), args.next(), args.next(), args.next())
+    if let (Some(n_results), Some(ek), Some(limit1)) =
+        (args.next(), args.next(), args.next())
     {
-        let n_results: usize = n_results
-            .parse()
-            .map_err(|_| "First argument must be an integer".to_string())?;
+        let n_results = parse_n_results(n_results)?;
         let ek: regular::Cents = ek.parse().map_err(|_| {
             "Specify badness parameter as a number in cents".to_string()
         })?;
@@ -80,6 +251,592 @@ fn command_line_args() -> Result<(usize, Cents, PrimeLimit), String> {
     }
 }
 
+/// Search by unison vectors, like the web page
+fn uv_command(options: &Options, args: &[String]) -> Result<(), String> {
+    let (n_results, uv_strings) = args
+        .split_first()
+        .ok_or("Supply the number of results and some unison vectors")?;
+    let n_results = parse_n_results(n_results)?;
+    let uv_strings: Vec<&str> =
+        uv_strings.iter().map(String::as_str).collect();
+    let (limit, uvs) = if let Some(limit) = &options.limit {
+        let limit = parse_limit(limit)?;
+        let uvs = uv_strings
+            .iter()
+            .map(|uv| parse_as_vector(&limit, uv))
+            .collect::<Result<Mapping, _>>()
+            .map_err(|e| e.to_string())?;
+        (limit, uvs)
+    } else {
+        parse_in_simplest_limit(&uv_strings)
+            .ok_or("Unable to determine prime limit from ratios")?
+    };
+    let uvs = filter_unison_vectors(&limit.pitches, uvs);
+    if uvs.is_empty() {
+        return Err("No valid unison vectors in the limit".to_string());
+    }
+    let ek =
+        ek_for_search(&limit.pitches, &uvs) * options.error.unwrap_or(2.0);
+    let highest_rank =
+        CangwuTemperament::from_unison_vectors(&limit.pitches, &uvs)
+            .and_then(|rt| rt.rank())
+            .map_err(|e| e.to_string())?;
+    let mappings = get_ets_tempering_out(
+        &limit.pitches,
+        ek,
+        &uvs,
+        if highest_rank == 1 { 1 } else { n_results },
+    );
+    print_rank_searches(
+        options,
+        &limit,
+        ek,
+        mappings,
+        highest_rank,
+        n_results,
+    );
+    Ok(())
+}
+
+/// Lower rank temperaments including this one, like the web page
+fn subsets_command(options: &Options, args: &[String]) -> Result<(), String> {
+    let [n_results, limit, ets] = args else {
+        return Err(
+            "Supply the number of results, prime limit, and ETs".to_string()
+        );
+    };
+    let n_results = parse_n_results(n_results)?;
+    let limit = parse_limit(limit)?;
+    let rt = CangwuTemperament::from_name(&limit, ets)
+        .map_err(|e| e.to_string())?;
+    let te_rt = TETemperament::new(&limit.pitches, &rt.melody);
+    let ek = te_rt.error() * options.error.unwrap_or(2.0);
+    let rank = rt.melody.len();
+    let mappings =
+        rt.get_belonging_ets(ek, if rank == 1 { 1 } else { n_results });
+    print_rank_searches(options, &limit, ek, mappings, rank, n_results);
+    Ok(())
+}
+
+/// Build up from the equal temperaments to the highest rank,
+/// which only gets one result
+fn print_rank_searches(
+    options: &Options,
+    limit: &PrimeLimit,
+    ek: Cents,
+    mappings: Mapping,
+    highest_rank: usize,
+    n_results: usize,
+) {
+    let mut documents = Vec::new();
+    let mut rts: Vec<Mapping> =
+        mappings.iter().map(|mapping| vec![mapping.clone()]).collect();
+    if output_return_closed(options, limit, ek, &rts, None, &mut documents) {
+        return;
+    }
+    for rank in 2..=highest_rank {
+        rts = higher_rank_search(
+            &limit.pitches,
+            &mappings,
+            &rts,
+            ek,
+            if rank == highest_rank { 1 } else { n_results },
+        );
+        if !rts.is_empty()
+            && output_return_closed(
+                options,
+                limit,
+                ek,
+                &rts,
+                None,
+                &mut documents,
+            )
+        {
+            return;
+        }
+    }
+    finish_output(options, limit, ek, &documents);
+}
+
+/// Everything the web page shows about a temperament class
+fn rt_command(options: &Options, args: &[String]) -> Result<(), String> {
+    let [limit, ets] = args else {
+        return Err("Supply the prime limit and ETs".to_string());
+    };
+    let limit = parse_limit(limit)?;
+    let rt = CangwuTemperament::from_name(&limit, ets)
+        .map_err(|e| e.to_string())?;
+    let te_rt = TETemperament::new(&limit.pitches, &rt.melody);
+    let redmap = te_rt.reduced_mapping().map_err(|e| e.to_string())?;
+    let key = te_rt.key().map_err(|e| e.to_string())?;
+    let contorsion = te_rt.contorsion().map_err(|e| e.to_string())?;
+    let wedgie = te_rt.wedgie().map_err(|e| e.to_string())?;
+    // Musically meaningful generators
+    let mingen = te_rt.mingen().map_err(|e| e.to_string())?;
+    let generators = TETemperament::new(&limit.pitches, &mingen.mapping);
+    let top_rt = TOPTemperament::new(&limit.pitches, &rt.melody).ok();
+    let tp_rt = options
+        .norm
+        .map(|norm| TpTemperament::new(&limit.pitches, &rt.melody, norm))
+        .transpose()
+        .map_err(|e| e.to_string())?;
+    let dimension = limit.pitches.len();
+    let rank = te_rt.rank().map_err(|e| e.to_string())?;
+    let uvs = if dimension < 12 {
+        let n_results = if (dimension - rank) == 1 { 1 } else { 10 };
+        te_rt.unison_vectors(n_results)
+    } else {
+        // The full search is to inefficient in these reaches
+        unison_vector_basis(&limit.pitches, &te_rt.melody)
+            .map_err(|e| e.to_string())?
+    };
+    let uvs: Vec<String> =
+        uvs.iter().map(|uv| get_ratio_or_ket_string(&limit, uv)).collect();
+    let ets = ets_name(&limit, &rt.melody);
+    let name = rt.name(&limit);
+    let report = if options.format == OutputFormat::Lists {
+        let mut lines = vec![
+            format!("Name: {}", name.unwrap_or("unknown")),
+            format!("ETs: {}", ets),
+            format!("Prime limit: {}", limit.label),
+            format!("Mapping: {:?}", rt.melody),
+            format!("Reduced mapping: {:?}", redmap),
+            format!("Mingen mapping: {:?}", mingen.mapping),
+            format!("Wedgie: {:?}", wedgie),
+            format!("Contorsion: {}", contorsion),
+            format!("TE generators: {}", floats(&generators.tuning, 4)),
+            format!(
+                "POTE generators: {}",
+                floats(&generators.unstretched_tuning(), 4)
+            ),
+            format!("TE step tunings: {}", floats(&te_rt.tuning, 4)),
+            format!("TE tuning map: {}", floats(&te_rt.tuning_map(), 3)),
+            format!(
+                "POTE tuning map: {}",
+                floats(&te_rt.unstretched_tuning_map(), 3)
+            ),
+            format!("TE mistunings: {}", floats(&te_rt.mistunings(), 4)),
+            format!(
+                "POTE mistunings: {}",
+                floats(&te_rt.unstretched_mistunings(), 4)
+            ),
+            format!("Complexity: {:.6}", te_rt.complexity()),
+            format!("Adjusted error: {:.6} cents", te_rt.adjusted_error()),
+            format!("TE error: {:.6} cents/octave", te_rt.error()),
+        ];
+        if let Some(top_rt) = &top_rt {
+            lines.push(format!(
+                "TOP tuning map: {}",
+                floats(&top_rt.tuning_map(), 3)
+            ));
+            lines.push(format!(
+                "TOP mistunings: {}",
+                floats(&top_rt.mistunings(), 4)
+            ));
+            lines.push(format!(
+                "TOP error: {:.6} cents/octave",
+                top_rt.error()
+            ));
+        }
+        if let Some(tp_rt) = &tp_rt {
+            lines.push(format!("Tp norm: {}", tp_rt.norm));
+            lines.push(format!(
+                "Tp tuning map: {}",
+                floats(&tp_rt.tuning_map(), 3)
+            ));
+            lines.push(format!(
+                "Tp mistunings: {}",
+                floats(&tp_rt.mistunings(), 4)
+            ));
+            lines.push(format!("Tp complexity: {:.6}", tp_rt.complexity()));
+            lines
+                .push(format!("Tp error: {:.6} cents/octave", tp_rt.error()));
+        }
+        lines.push(format!("Unison vectors: {}", uvs.join(" ")));
+        lines.join("\n")
+    } else {
+        let top_fields = match &top_rt {
+            Some(top_rt) => format!(
+                "\"top_tuning_map\": {}, \"top_mistunings\": {}, \
+                 \"top_error\": {}",
+                json_floats(&top_rt.tuning_map()),
+                json_floats(&top_rt.mistunings()),
+                json_number(top_rt.error()),
+            ),
+            None => "\"top_error\": null".to_string(),
+        };
+        let tp_fields = match &tp_rt {
+            Some(tp_rt) => format!(
+                ", \"tp_norm\": {}, \"tp_tuning_map\": {}, \
+                 \"tp_mistunings\": {}, \"tp_complexity\": {}, \
+                 \"tp_error\": {}",
+                json_number(tp_rt.norm),
+                json_floats(&tp_rt.tuning_map()),
+                json_floats(&tp_rt.mistunings()),
+                json_number(tp_rt.complexity()),
+                json_number(tp_rt.error()),
+            ),
+            None => String::new(),
+        };
+        format!(
+            "{{\"name\": {}, \"ets\": {}, \"limit\": {}, \"mapping\": {}, \
+             \"reduced_mapping\": {}, \"mingen_mapping\": {}, \
+             \"key\": {}, \"wedgie\": {}, \
+             \"contorsion\": {}, \"generators\": {}, \
+             \"tuning\": {}, \"tuning_map\": {}, \"mistunings\": {}, \
+             \"pote_tuning_map\": {}, \"complexity\": {}, \
+             \"adjusted_error\": {}, \"error\": {}, {}{}, \
+             \"unison_vectors\": {}}}",
+            name.map_or("null".to_string(), json_string),
+            json_string(&ets),
+            json_string(&limit.label),
+            json_mapping(&rt.melody),
+            json_mapping(&redmap),
+            json_mapping(&mingen.mapping),
+            json_list(key.iter()),
+            json_list(wedgie.iter()),
+            contorsion,
+            json_floats(&generators.tuning),
+            json_floats(&te_rt.tuning),
+            json_floats(&te_rt.tuning_map()),
+            json_floats(&te_rt.mistunings()),
+            json_floats(&te_rt.unstretched_tuning_map()),
+            json_number(te_rt.complexity()),
+            json_number(te_rt.adjusted_error()),
+            json_number(te_rt.error()),
+            top_fields,
+            tp_fields,
+            json_list(uvs.iter().map(|uv| json_string(uv))),
+        )
+    };
+    print_return_closed(&report);
+    Ok(())
+}
+
+/// Write a Scala file, or another --file-format, to stdout
+fn scala_command(options: &Options, args: &[String]) -> Result<(), String> {
+    let scale = tuned_scale(options, args)?;
+    let contents = options.file_format.contents(&scale);
+    // The tuning file has its own line endings
+    let _ = stdout().write_all(contents.as_bytes());
+    Ok(())
+}
+
+/// Write a MIDI Tuning Standard bulk dump (.syx) to stdout
+fn mts_command(options: &Options, args: &[String]) -> Result<(), String> {
+    let scale = tuned_scale(options, args)?;
+    let dump = bulk_dump(&scale.pitches, &scale.name, &scale.keyboard);
+    let _ = stdout().write_all(&dump);
+    Ok(())
+}
+
+/// The scale asked for, ready for a tuning file
+fn tuned_scale(options: &Options, args: &[String]) -> Result<Scale, String> {
+    let [limit, ets, n_notes] = args else {
+        return Err(
+            "Supply the prime limit, ETs, and number of notes".to_string()
+        );
+    };
+    let limit = parse_limit(limit)?;
+    let n_notes: Exponent = n_notes
+        .parse()
+        .map_err(|_| "Number of notes must be an integer".to_string())?;
+    let rt = CangwuTemperament::from_name(&limit, ets)
+        .map_err(|e| e.to_string())?;
+    let name = match rt.name(&limit) {
+        Some(name) => name.to_string(),
+        None => ets_name(&limit, &rt.melody),
+    };
+    let te_rt = TETemperament::new(&limit.pitches, &rt.melody);
+    match options.tuning.as_str() {
+        "te" => scale_for(options, &te_rt, n_notes, &name),
+        "pote" => scale_for(
+            options,
+            &te_rt.with_tuning(te_rt.unstretched_tuning()),
+            n_notes,
+            &name,
+        ),
+        "cte" => {
+            let cte_rt =
+                ConstrainedTemperament::cte(&limit.pitches, &rt.melody)
+                    .map_err(|e| e.to_string())?;
+            scale_for(options, &cte_rt, n_notes, &name)
+        }
+        "top" | "toppo" => {
+            let mut top_rt = TOPTemperament::new(&limit.pitches, &rt.melody)
+                .map_err(|e| e.to_string())?;
+            if options.tuning == "toppo" {
+                top_rt.tuning = top_rt.unstretched_tuning();
+            }
+            scale_for(options, &top_rt, n_notes, &name)
+        }
+        "tp" => {
+            let norm = options.norm.unwrap_or(2.0);
+            let tp_rt = TpTemperament::new(&limit.pitches, &rt.melody, norm)
+                .map_err(|e| e.to_string())?;
+            scale_for(options, &tp_rt, n_notes, &name)
+        }
+        tuning => Err(format!("Unknown tuning {}", tuning)),
+    }
+}
+
+/// A Fokker block, or a MOS with --mos
+fn scale_for(
+    options: &Options,
+    rt: &impl TunedTemperament,
+    n_notes: Exponent,
+    name: &str,
+) -> Result<Scale, String> {
+    if !options.mos {
+        return Ok(rt.fokker_block_scale(n_notes, name));
+    }
+    let mos = rt.mos_scale(n_notes).ok_or_else(|| {
+        format!(
+            "No {}-note MOS: try one of {:?}",
+            n_notes,
+            rt.mos_sizes(n_notes.max(50))
+        )
+    })?;
+    Ok(Scale::from_mos(&mos, name))
+}
+
+/// Tune to an odd limit tonality diamond
+/// and show the error of each interval
+fn diamond_command(options: &Options, args: &[String]) -> Result<(), String> {
+    let [limit, ets, odd_limit] = args else {
+        return Err("Supply the prime limit, ETs, and odd limit".to_string());
+    };
+    let limit = parse_limit(limit)?;
+    let odd_limit: u128 = odd_limit
+        .parse()
+        .map_err(|_| "Odd limit must be an integer".to_string())?;
+    let rt = CangwuTemperament::from_name(&limit, ets)
+        .map_err(|e| e.to_string())?;
+    let fit = if options.rms { DiamondFit::Rms } else { DiamondFit::Minimax };
+    let diamond =
+        Diamond::new(&limit, odd_limit).map_err(|e| e.to_string())?;
+    let rt = DiamondTemperament::new(
+        &limit.pitches,
+        &rt.melody,
+        diamond,
+        fit,
+        options.pure_octaves,
+    )
+    .map_err(|e| e.to_string())?;
+    let report = if options.format == OutputFormat::Lists {
+        format!(
+            "{}\nMax error: {:.3}\nRMS error: {:.3}",
+            rt.report(),
+            rt.max_error(),
+            rt.rms_error(),
+        )
+    } else {
+        let ratios = rt.diamond.ratios.iter().map(|&ratio| stringify(ratio));
+        format!(
+            "{{\"odd_limit\": {}, \"tuning_map\": {}, \"ratios\": {}, \
+             \"errors\": {}, \"max_error\": {}, \"rms_error\": {}}}",
+            odd_limit,
+            json_floats(&rt.tuning_map()),
+            json_list(ratios.map(|ratio| json_string(&ratio))),
+            json_floats(&rt.diamond_errors()),
+            json_number(rt.max_error()),
+            json_number(rt.rms_error()),
+        )
+    };
+    print_return_closed(&report);
+    Ok(())
+}
+
+/// List the MOS scales of a rank 2 temperament with their step patterns
+fn mos_command(options: &Options, args: &[String]) -> Result<(), String> {
+    let [limit, ets, max_notes] = args else {
+        return Err(
+            "Supply the prime limit, ETs, and most notes to show".to_string()
+        );
+    };
+    let limit = parse_limit(limit)?;
+    let max_notes: Exponent = max_notes
+        .parse()
+        .map_err(|_| "Number of notes must be an integer".to_string())?;
+    let rt = CangwuTemperament::from_name(&limit, ets)
+        .map_err(|e| e.to_string())?;
+    if rt.rank().map_err(|e| e.to_string())? != 2 {
+        return Err("MOS scales need a rank 2 temperament".to_string());
+    }
+    let te_rt = TETemperament::new(&limit.pitches, &rt.melody);
+    let scales: Vec<Mos> = te_rt
+        .mos_sizes(max_notes)
+        .into_iter()
+        .filter_map(|size| te_rt.mos_scale(size))
+        .collect();
+    let lines: Vec<String> = if options.format == OutputFormat::Lists {
+        scales
+            .iter()
+            .map(|mos| format!("{} {}", mos.size, mos.pattern))
+            .collect()
+    } else {
+        scales
+            .iter()
+            .map(|mos| {
+                format!(
+                    "{{\"size\": {}, \"pattern\": {}, \"steps\": {}, \
+                     \"pitches\": {}}}",
+                    mos.size,
+                    json_string(&mos.pattern),
+                    json_mapping(&mos.steps),
+                    json_floats(&mos.pitches),
+                )
+            })
+            .collect()
+    };
+    let output = if options.format == OutputFormat::Json {
+        format!("[{}]", lines.join(", "))
+    } else {
+        lines.join("\n")
+    };
+    print_return_closed(&output);
+    Ok(())
+}
+
+fn parse_n_results(n_results: &str) -> Result<usize, String> {
+    n_results
+        .parse()
+        .map_err(|_| "Number of results must be an integer".to_string())
+}
+
+fn parse_limit(limit: &str) -> Result<PrimeLimit, String> {
+    limit.parse().map_err(|e: Error| e.to_string())
+}
+
+fn ets_name(limit: &PrimeLimit, mapping: &[ETMap]) -> String {
+    let names: Vec<String> =
+        mapping.iter().map(|et| et_name(limit, et)).collect();
+    names.join(" & ")
+}
+
+fn floats(values: &[Cents], precision: usize) -> String {
+    let values: Vec<String> =
+        values.iter().map(|x| format!("{:.*}", precision, x)).collect();
+    values.join(" ")
+}
+
+/// Pull out the options (starting with --) from the positional arguments
+fn split_options(
+    mut args: impl Iterator<Item = String>,
+) -> Result<(Options, Vec<String>), String> {
+    let mut options = Options {
+        format: OutputFormat::Lists,
+        limit: None,
+        error: None,
+        tuning: "te".to_string(),
+        norm: None,
+        exact: false,
+        progress: false,
+        no_contorsion: false,
+        mos: false,
+        rms: false,
+        pure_octaves: false,
+        file_format: TuningFormat::Scala,
+        timeout: None,
+    };
+    let mut positional = Vec::new();
+    while let Some(arg) = args.next() {
+        let Some(option) = arg.strip_prefix("--") else {
+            positional.push(arg);
+            continue;
+        };
+        // Options without values
+        match option {
+            "exact" => {
+                options.exact = true;
+                continue;
+            }
+            "progress" => {
+                options.progress = true;
+                continue;
+            }
+            "no-contorsion" => {
+                options.no_contorsion = true;
+                continue;
+            }
+            "mos" => {
+                options.mos = true;
+                continue;
+            }
+            "rms" => {
+                options.rms = true;
+                continue;
+            }
+            "pure-octaves" => {
+                options.pure_octaves = true;
+                continue;
+            }
+            _ => (),
+        }
+        let (name, value) = match option.split_once('=') {
+            Some((name, value)) => (name.to_string(), value.to_string()),
+            None => (
+                option.to_string(),
+                args.next().ok_or(format!("{} needs a value", arg))?,
+            ),
+        };
+        match name.as_str() {
+            "format" => {
+                options.format = match value.as_str() {
+                    "lists" => OutputFormat::Lists,
+                    "json" => OutputFormat::Json,
+                    "jsonl" => OutputFormat::JsonLines,
+                    _ => {
+                        return Err(format!(
+                            "Unknown format {}: use lists, json, or jsonl",
+                            value
+                        ));
+                    }
+                }
+            }
+            "limit" => options.limit = Some(value),
+            "error" => {
+                options.error = Some(value.parse().map_err(|_| {
+                    "Error multiplier must be a number".to_string()
+                })?)
+            }
+            "tuning" => options.tuning = value,
+            "norm" => {
+                options.norm = Some(
+                    value
+                        .parse()
+                        .map_err(|_| "Norm must be a number".to_string())?,
+                )
+            }
+            "file-format" => {
+                options.file_format = TuningFormat::from_extension(&value)
+                    .ok_or_else(|| {
+                        format!(
+                            "Unknown file format {}: use scl, tun, or ascl",
+                            value
+                        )
+                    })?
+            }
+            "timeout" => {
+                let seconds: f64 = value.parse().map_err(|_| {
+                    "Timeout must be a number of seconds".to_string()
+                })?;
+                options.timeout = Some(
+                    Duration::try_from_secs_f64(seconds)
+                        .map_err(|e| format!("Bad timeout: {}", e))?,
+                )
+            }
+            _ => {
+                return Err(format!(
+                    "Unknown option --{}: try regular-cli help",
+                    name
+                ));
+            }
+        }
+    }
+    Ok((options, positional))
+}
+
 fn read_cents() -> Result<PrimeLimit, String> {
     println!("List your partials in cents, one to a line");
     let mut result = Vec::new();
@@ -94,8 +851,203 @@ fn read_cents() -> Result<PrimeLimit, String> {
     Ok(PrimeLimit::inharmonic(result))
 }
 
-/// Print debug to stdout or return true if stdout is closed
-fn print_return_closed<T: std::fmt::Debug>(obj: &T) -> bool {
+/// Print the results for one rank, or save them for later,
+/// and return true if stdout is closed
+fn output_return_closed(
+    options: &Options,
+    limit: &PrimeLimit,
+    ek: Cents,
+    rts: &[Mapping],
+    certificate: Option<&Certificate>,
+    documents: &mut Vec<String>,
+) -> bool {
+    match options.format {
+        OutputFormat::Lists => print_return_closed(&format!("{:?}", rts)),
+        OutputFormat::Json => {
+            documents.push(rank_json(limit, ek, rts, certificate));
+            false
+        }
+        OutputFormat::JsonLines => {
+            print_return_closed(&rank_json(limit, ek, rts, certificate))
+        }
+    }
+}
+
+/// Print the JSON document saved up by output_return_closed
+fn finish_output(
+    options: &Options,
+    limit: &PrimeLimit,
+    ek: Cents,
+    documents: &[String],
+) {
+    if options.format == OutputFormat::Json {
+        print_return_closed(&format!(
+            "{{\"limit\": {}, \"ek\": {}, \"ranks\": [{}]}}",
+            json_string(&limit.label),
+            json_number(ek),
+            documents.join(", "),
+        ));
+    }
+}
+
+/// JSON document for all the results of one rank,
+/// with what's proven about them if it's an exact search
+fn rank_json(
+    limit: &PrimeLimit,
+    ek: Cents,
+    rts: &[Mapping],
+    certificate: Option<&Certificate>,
+) -> String {
+    let rank = rts.first().map_or(0, Vec::len);
+    let temperaments: Vec<String> =
+        rts.iter().map(|rt| temperament_json(limit, ek, rt)).collect();
+    let proof = match certificate {
+        Some(certificate) => format!(
+            ", \"exact\": {}, \"complete_below\": {}",
+            certificate.is_exact(),
+            json_number(certificate.complete_below),
+        ),
+        None => String::new(),
+    };
+    format!(
+        "{{\"rank\": {}{}, \"temperaments\": [{}]}}",
+        rank,
+        proof,
+        temperaments.join(", "),
+    )
+}
+
+/// JSON object describing a temperament class with its TE tuning
+fn temperament_json(
+    limit: &PrimeLimit,
+    ek: Cents,
+    mapping: &Mapping,
+) -> String {
+    let rt = TETemperament::new(&limit.pitches, mapping);
+    let badness = CangwuTemperament::new(&limit.pitches, mapping).badness(ek);
+    let name = match rt.name(limit) {
+        Some(name) => json_string(name),
+        None => "null".to_string(),
+    };
+    // Null for anything that overflowed
+    let key =
+        rt.key().map_or("null".to_string(), |key| json_list(key.iter()));
+    let contorsion =
+        rt.contorsion().map_or("null".to_string(), |c| c.to_string());
+    format!(
+        "{{\"mapping\": {}, \"key\": {}, \"name\": {}, \"tuning\": {}, \
+         \"contorsion\": {}, \"complexity\": {}, \"error\": {}, \
+         \"badness\": {}}}",
+        json_mapping(mapping),
+        key,
+        name,
+        json_floats(&rt.tuning),
+        contorsion,
+        json_number(rt.complexity()),
+        json_number(rt.error()),
+        json_number(badness),
+    )
+}
+
+fn json_mapping(mapping: &[ETMap]) -> String {
+    json_list(mapping.iter().map(|et| json_list(et.iter())))
+}
+
+fn json_floats(values: &[Cents]) -> String {
+    json_list(values.iter().map(|&x| json_number(x)))
+}
+
+fn json_list<T: ToString>(items: impl Iterator<Item = T>) -> String {
+    let items: Vec<String> = items.map(|item| item.to_string()).collect();
+    format!("[{}]", items.join(", "))
+}
+
+/// JSON has no infinities or NaN so they become null
+fn json_number(x: f64) -> String {
+    if x.is_finite() { x.to_string() } else { "null".to_string() }
+}
+
+fn json_string(s: &str) -> String {
+    let mut result = String::from('"');
+    for c in s.chars() {
+        match c {
+            '"' => result.push_str("\\\""),
+            '\\' => result.push_str("\\\\"),
+            '\n' => result.push_str("\\n"),
+            '\r' => result.push_str("\\r"),
+            '\t' => result.push_str("\\t"),
+            c if c.is_control() => {
+                result.push_str(&format!("\\u{:04x}", c as u32))
+            }
+            c => result.push(c),
+        }
+    }
+    result.push('"');
+    result
+}
+
+/// Print a line to stdout or return true if stdout is closed
+fn print_return_closed(text: &str) -> bool {
     // This is like println! but without the panic
-    stdout().write_all(&format!("{:?}\n", obj).into_bytes()).is_err()
+    stdout().write_all(&format!("{}\n", text).into_bytes()).is_err()
+}
+
+#[test]
+fn json_helpers() {
+    assert_eq!(json_string("plain"), "\"plain\"");
+    assert_eq!(json_string("say \"hi\"\\"), r#""say \"hi\"\\""#);
+    assert_eq!(json_string("a\nb\tc\u{1}"), r#""a\nb\tc\u0001""#);
+    assert_eq!(json_number(1.5), "1.5");
+    assert_eq!(json_number(f64::INFINITY), "null");
+    assert_eq!(json_number(f64::NAN), "null");
+    assert_eq!(json_list([1, 2, 3].iter()), "[1, 2, 3]");
+    assert_eq!(json_list(Vec::<i32>::new().iter()), "[]");
+    assert_eq!(json_floats(&[1.0, f64::NAN]), "[1, null]");
+    assert_eq!(
+        json_mapping(&[vec![12, 19], vec![7, 11]]),
+        "[[12, 19], [7, 11]]"
+    );
+}
+
+#[test]
+fn rank_document() {
+    let limit7 = PrimeLimit::new(7);
+    let mapping = vec![vec![12, 19, 28, 34], vec![19, 30, 44, 53]];
+    let text = temperament_json(&limit7, 1.0, &mapping);
+    let doc: serde_json::Value =
+        serde_json::from_str(&text).expect("bad JSON");
+    assert_eq!(doc["mapping"], serde_json::json!(mapping));
+    assert_eq!(doc["name"], "Meantone");
+    let tuning = doc["tuning"].as_array().expect("tuning isn't a list");
+    assert_eq!(tuning.len(), 2);
+    assert!(tuning.iter().all(|x| x.is_f64()));
+    assert_eq!(doc["contorsion"].as_i64(), Some(1));
+    assert!(doc["complexity"].is_f64());
+    assert!(doc["error"].is_f64());
+    assert!(doc["badness"].is_f64());
+}
+
+#[test]
+fn options_and_arguments() {
+    let args =
+        |line: &str| split_options(line.split_whitespace().map(String::from));
+    let (options, positional) =
+        args("search --format json 7 --error=1.5 --exact 12 --norm 3")
+            .expect("bad options");
+    assert_eq!(positional, vec!["search", "7", "12"]);
+    assert!(options.format == OutputFormat::Json);
+    assert_eq!(options.error, Some(1.5));
+    assert_eq!(options.norm, Some(3.0));
+    assert!(options.exact);
+    assert!(!options.progress);
+    assert_eq!(options.tuning, "te");
+    let (options, _) = args("--timeout 2.5 --file-format tun --limit 11")
+        .expect("bad options");
+    assert_eq!(options.timeout, Some(Duration::from_millis(2500)));
+    assert_eq!(options.file_format, TuningFormat::AnaMark);
+    assert_eq!(options.limit, Some("11".to_string()));
+    assert!(args("--format xml").is_err());
+    assert!(args("--error").is_err());
+    assert!(args("--norm=p").is_err());
+    assert!(args("--unknown 1").is_err());
 }
@@ -0,0 +1,175 @@
+//! Moment of symmetry (MOS) scales for rank 2 temperaments
+//!
+//! A MOS comes from a chain of generators reduced to within a period
+//! and has exactly two step sizes, large (L) and small (s).
+//! These work with the period and generator of the mingen form.
+
+use super::temperament_class::MingenForm;
+use super::{Cents, ETMap, Exponent, Mapping, Tuning};
+
+/// Step sizes closer than this are the same
+const TOLERANCE: Cents = 1e-6;
+
+#[derive(Clone, Debug, PartialEq)]
+pub struct Mos {
+    /// Number of notes to the equivalence interval (octave)
+    pub size: Exponent,
+    /// Each note above the tonic as periods and generators,
+    /// up to and including the equivalence interval
+    pub steps: Mapping,
+    /// Pitches of the notes in steps
+    pub pitches: Tuning,
+    /// L for a large step and s for a small step, in order
+    pub pattern: String,
+    pub large: Cents,
+    pub small: Cents,
+}
+
+/// Sizes of MOS scales up to max_size notes to the equivalence interval
+/// where n_periods periods make up that equivalence interval
+pub fn mos_sizes(
+    period: Cents,
+    generator: Cents,
+    n_periods: Exponent,
+    max_size: Exponent,
+) -> Vec<Exponent> {
+    if n_periods < 1 {
+        return vec![];
+    }
+    (2..=max_size / n_periods)
+        .filter(|&notes| step_sizes(period, generator, notes).is_some())
+        .map(|notes| notes * n_periods)
+        .collect()
+}
+
+/// The MOS with this many notes to the equivalence interval,
+/// or None if there isn't one
+pub fn mos_scale(
+    period: Cents,
+    generator: Cents,
+    n_periods: Exponent,
+    size: Exponent,
+) -> Option<Mos> {
+    if n_periods < 1 || size % n_periods != 0 {
+        return None;
+    }
+    let notes = size / n_periods;
+    let (large, small) = step_sizes(period, generator, notes)?;
+    let mut chain = generator_chain(period, generator, notes);
+    chain.sort_by(|(_, a), (_, b)| a.total_cmp(b));
+    let mut steps = Vec::new();
+    let mut pitches = Vec::new();
+    for p in 0..n_periods {
+        for &(ref step, pitch) in chain.iter() {
+            steps.push(vec![step[0] + p, step[1]]);
+            pitches.push(pitch + period * p as Cents);
+        }
+    }
+    // Move the tonic to the top
+    steps.remove(0);
+    pitches.remove(0);
+    steps.push(vec![n_periods, 0]);
+    pitches.push(period * n_periods as Cents);
+    let mut last = 0.0;
+    let pattern = pitches
+        .iter()
+        .map(|&pitch| {
+            let step = pitch - last;
+            last = pitch;
+            if (step - large).abs() < TOLERANCE { 'L' } else { 's' }
+        })
+        .collect();
+    Some(Mos { size, steps, pitches, pattern, large, small })
+}
+
+/// Period, generator and number of periods to the equivalence interval
+/// for a rank 2 mingen form
+pub fn period_and_generator(
+    mingen: &MingenForm,
+) -> Option<(Cents, Cents, Exponent)> {
+    match (&mingen.mapping[..], &mingen.generators[..]) {
+        ([period_row, _], &[period, generator]) if period_row[0] > 0 => {
+            Some((period, generator, period_row[0]))
+        }
+        _ => None,
+    }
+}
+
+/// Large and small steps if the chain of this many generators
+/// makes a MOS
+fn step_sizes(
+    period: Cents,
+    generator: Cents,
+    notes: Exponent,
+) -> Option<(Cents, Cents)> {
+    let mut pitches: Tuning = generator_chain(period, generator, notes)
+        .into_iter()
+        .map(|(_, pitch)| pitch)
+        .collect();
+    pitches.sort_by(|a, b| a.total_cmp(b));
+    pitches.push(period);
+    let mut sizes: Tuning = Vec::new();
+    for pair in pitches.windows(2) {
+        let step = pair[1] - pair[0];
+        if step < TOLERANCE {
+            // Notes on top of each other
+            return None;
+        }
+        if !sizes.iter().any(|size| (size - step).abs() < TOLERANCE) {
+            sizes.push(step);
+        }
+    }
+    match sizes[..] {
+        [a, b] => Some((a.max(b), a.min(b))),
+        _ => None,
+    }
+}
+
+/// Generators up from the tonic reduced to within the period,
+/// as steps (periods, generators) and pitches
+fn generator_chain(
+    period: Cents,
+    generator: Cents,
+    notes: Exponent,
+) -> Vec<(ETMap, Cents)> {
+    (0..notes)
+        .map(|k| {
+            let raw = generator * k as Cents;
+            let periods = (raw / period + TOLERANCE).floor();
+            (vec![-periods as Exponent, k], raw - periods * period)
+        })
+        .collect()
+}
+
+#[test]
+fn meantone_sizes() {
+    assert_eq!(
+        mos_sizes(1200.0, 503.42, 1, 40),
+        vec![2, 3, 5, 7, 12, 19, 31]
+    );
+}
+
+#[test]
+fn diatonic() {
+    let mos = mos_scale(1200.0, 503.42, 1, 7).expect("no diatonic");
+    assert_eq!(mos.pattern, "sLLsLLL");
+    assert_eq!(mos.steps.len(), 7);
+    // Five fourths take you to the semitone
+    assert_eq!(mos.steps[0], vec![-2, 5]);
+    assert_eq!(mos.steps[6], vec![1, 0]);
+    assert_eq!(mos.pitches[6], 1200.0);
+    super::assert_between!(193.15, mos.large, 193.17);
+    super::assert_between!(117.09, mos.small, 117.11);
+    assert_eq!(mos_scale(1200.0, 503.42, 1, 8), None);
+}
+
+#[test]
+fn two_periods() {
+    // Pajara
+    assert_eq!(mos_sizes(600.0, 106.8, 2, 40), vec![4, 6, 8, 10, 12, 22, 34]);
+    let mos = mos_scale(600.0, 106.8, 2, 10).expect("no decatonic");
+    assert_eq!(mos.pattern, "ssssLssssL");
+    assert_eq!(mos.steps[4], vec![1, 0]);
+    assert_eq!(mos.pitches.last(), Some(&1200.0));
+    assert_eq!(mos_scale(600.0, 106.8, 2, 7), None);
+}
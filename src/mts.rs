@@ -0,0 +1,201 @@
+//! MIDI Tuning Standard (MTS) messages for hardware synthesizers
+//!
+//! Frequencies are MIDI note numbers with a 14-bit fraction
+//! of a semitone, so the resolution is 100/16384 cents.
+//! Keys get pitches the same way as a Scala keyboard mapping.
+
+use super::tuned_temperament::KeyboardMapping;
+use super::{Cents, Exponent};
+
+/// Send to all devices
+const ALL_DEVICES: u8 = 0x7F;
+
+/// Frequency data that means "leave this key alone"
+const NO_CHANGE: [u8; 3] = [0x7F, 0x7F, 0x7F];
+
+/// Pitch of each of the 128 MIDI keys in cents relative to the
+/// middle note, or None where the keyboard mapping leaves it out.
+/// The pitches are of the notes above the tonic,
+/// ending with the equivalence interval.
+pub fn key_cents(
+    pitches: &[Cents],
+    keyboard: &KeyboardMapping,
+) -> Vec<Option<Cents>> {
+    let Some(&equivalence) = pitches.last() else {
+        return vec![None; 128];
+    };
+    let n_notes = pitches.len() as Exponent;
+    let degree_cents = |degree: Exponent| {
+        let octaves = degree.div_euclid(n_notes);
+        let note = degree.rem_euclid(n_notes) as usize;
+        let above_tonic = if note == 0 { 0.0 } else { pitches[note - 1] };
+        octaves as Cents * equivalence + above_tonic
+    };
+    (0..128)
+        .map(|key| {
+            let offset = key - keyboard.middle_note;
+            if keyboard.pattern.is_empty() {
+                return Some(degree_cents(offset));
+            }
+            let size = keyboard.pattern.len() as Exponent;
+            let degree = keyboard.pattern[offset.rem_euclid(size) as usize]?;
+            Some(degree_cents(offset.div_euclid(size) * n_notes + degree))
+        })
+        .collect()
+}
+
+/// Each key's pitch as a (fractional) MIDI note number,
+/// with the reference note at the reference frequency.
+/// An unmapped reference note puts the middle note there instead.
+pub fn key_semitones(
+    pitches: &[Cents],
+    keyboard: &KeyboardMapping,
+) -> Vec<Option<f64>> {
+    let cents = key_cents(pitches, keyboard);
+    let reference = usize::try_from(keyboard.reference_note)
+        .ok()
+        .and_then(|key| cents.get(key).copied().flatten())
+        .unwrap_or(0.0);
+    let middle = 69.0 + 12.0 * (keyboard.reference_frequency / 440.0).log2()
+        - reference / 100.0;
+    cents
+        .into_iter()
+        .map(|key| key.map(|cents| middle + cents / 100.0))
+        .collect()
+}
+
+/// MTS frequency data: a note number and 14 bits of fraction
+pub fn frequency_data(semitones: f64) -> [u8; 3] {
+    let units = (semitones * 16384.0).round();
+    if !(0.0..128.0 * 16384.0).contains(&units) {
+        return NO_CHANGE;
+    }
+    let units = units as u32;
+    if units == 128 * 16384 - 1 {
+        // 7F 7F 7F is reserved so this is as high as it goes
+        return [0x7F, 0x7F, 0x7E];
+    }
+    [(units >> 14) as u8, ((units >> 7) & 0x7F) as u8, (units & 0x7F) as u8]
+}
+
+/// Bulk tuning dump (a complete .syx file) for all 128 keys
+pub fn bulk_dump(
+    pitches: &[Cents],
+    name: &str,
+    keyboard: &KeyboardMapping,
+) -> Vec<u8> {
+    let mut message = vec![0xF0, 0x7E, ALL_DEVICES, 0x08, 0x01, 0x00];
+    // The name is 16 printable ASCII characters
+    let mut name: Vec<u8> = name
+        .bytes()
+        .map(|c| if c.is_ascii_graphic() { c } else { b' ' })
+        .take(16)
+        .collect();
+    name.resize(16, b' ');
+    message.extend(name);
+    for semitones in key_semitones(pitches, keyboard) {
+        message.extend(semitones.map_or(NO_CHANGE, frequency_data));
+    }
+    let checksum = message[1..].iter().fold(0, |sum, byte| sum ^ byte);
+    message.push(checksum & 0x7F);
+    message.push(0xF7);
+    message
+}
+
+/// Real time single note tuning changes for all the mapped keys,
+/// as many messages as it takes
+pub fn single_note_tuning_change(
+    pitches: &[Cents],
+    keyboard: &KeyboardMapping,
+) -> Vec<u8> {
+    let changes: Vec<(u8, [u8; 3])> = key_semitones(pitches, keyboard)
+        .into_iter()
+        .enumerate()
+        .filter_map(|(key, semitones)| {
+            Some((key as u8, frequency_data(semitones?)))
+        })
+        .collect();
+    let mut messages = Vec::new();
+    // The count is a 7-bit number
+    for chunk in changes.chunks(64) {
+        messages.extend([0xF0, 0x7F, ALL_DEVICES, 0x08, 0x02, 0x00]);
+        messages.push(chunk.len() as u8);
+        for &(key, data) in chunk {
+            messages.push(key);
+            messages.extend(data);
+        }
+        messages.push(0xF7);
+    }
+    messages
+}
+
+#[test]
+fn encode_frequencies() {
+    assert_eq!(frequency_data(60.0), [60, 0, 0]);
+    assert_eq!(frequency_data(60.5), [60, 0x40, 0]);
+    assert_eq!(frequency_data(69.0 + 100.0 / 16384.0 / 100.0), [69, 0, 1]);
+    assert_eq!(frequency_data(60.99999999), [61, 0, 0]);
+    assert_eq!(frequency_data(127.99999999), NO_CHANGE);
+    assert_eq!(frequency_data(127.99995), [0x7F, 0x7F, 0x7E]);
+    assert_eq!(frequency_data(-0.00000001), [0, 0, 0]);
+    assert_eq!(frequency_data(-0.5), NO_CHANGE);
+    assert_eq!(frequency_data(128.0), NO_CHANGE);
+}
+
+#[test]
+fn equal_temperament_dump() {
+    let pitches: Vec<Cents> = (1..=12).map(|i| i as Cents * 100.0).collect();
+    let dump = bulk_dump(&pitches, "12-EDO", &KeyboardMapping::default());
+    assert_eq!(dump.len(), 408);
+    assert_eq!(&dump[..6], &[0xF0, 0x7E, 0x7F, 0x08, 0x01, 0x00]);
+    assert_eq!(&dump[6..22], b"12-EDO          ");
+    for key in 0..128 {
+        let data = &dump[22 + 3 * key..25 + 3 * key];
+        assert_eq!(data, &[key as u8, 0, 0]);
+    }
+    let checksum = dump[1..406].iter().fold(0, |sum, byte| sum ^ byte);
+    assert_eq!(dump[406], checksum);
+    assert!(dump[406] < 0x80);
+    assert_eq!(dump[407], 0xF7);
+}
+
+#[test]
+fn mapped_keys() {
+    // Pythagorean pentatonic on the black keys, A440
+    let pentatonic = [203.91, 407.82, 701.955, 905.865, 1200.0];
+    let keyboard = KeyboardMapping {
+        middle_note: 61,
+        reference_note: 69,
+        reference_frequency: 440.0,
+        pattern: vec![
+            Some(0),
+            None,
+            Some(1),
+            None,
+            None,
+            Some(2),
+            None,
+            Some(3),
+            None,
+            Some(4),
+            None,
+            None,
+        ],
+    };
+    let cents = key_cents(&pentatonic, &keyboard);
+    assert_eq!(cents[61], Some(0.0));
+    assert_eq!(cents[62], None);
+    assert_eq!(cents[73], Some(1200.0));
+    assert_eq!(cents[58], Some(905.865 - 1200.0));
+    let semitones = key_semitones(&pentatonic, &keyboard);
+    assert_eq!(semitones[69], None);
+    // The reference isn't mapped so the middle note gets A440
+    assert_eq!(semitones[61], Some(69.0));
+    let changes = single_note_tuning_change(&pentatonic, &keyboard);
+    // Keys 1, 3, 6, 8, and 10 in each of 10 octaves
+    // then 121, 123, and 126
+    assert_eq!(changes[6], 53);
+    assert_eq!(changes.len(), 8 + 4 * 53);
+    // Five octaves below the middle note
+    assert_eq!(&changes[7..11], &[1, 9, 0, 0]);
+}
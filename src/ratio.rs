@@ -1,40 +1,77 @@
 //! Utilities for dealing with vectors as ratios
 
-use super::{ETMap, Mapping, PrimeLimit, join};
+use super::{ETMap, Error, Exponent, Mapping, PrimeLimit, join};
+#[cfg(feature = "bigint")]
+use num_bigint::BigUint;
+use std::num::{IntErrorKind, ParseIntError};
 
 /// Integers in ratios can get bigger than partials
 type Length = u128;
 type Ratio = (Length, Length);
 
-/// Turn the ratio-space vector (typed as a mapping) into a ratio-string
-pub fn get_ratio_string(limit: &PrimeLimit, rsvec: &ETMap) -> Option<String> {
-    Some(stringify(get_ratio(limit, rsvec)?))
+/// Turn the ratio-space vector (typed as a mapping) into a ratio-string.
+/// With the "bigint" feature, the ratio can be any size.
+pub fn get_ratio_string(
+    limit: &PrimeLimit,
+    rsvec: &ETMap,
+) -> Result<String, Error> {
+    #[cfg(feature = "bigint")]
+    let (numerator, denominator) = get_big_ratio(limit, rsvec)?;
+    #[cfg(not(feature = "bigint"))]
+    let (numerator, denominator) = get_ratio(limit, rsvec)?;
+    Ok(format!("{}:{}", numerator, denominator))
 }
 
 /// Turn the ratio-space vector (typed as a mapping) into a ratio
-pub fn get_ratio(limit: &PrimeLimit, rsvec: &ETMap) -> Option<Ratio> {
+pub fn get_ratio(limit: &PrimeLimit, rsvec: &ETMap) -> Result<Ratio, Error> {
     let mut numerator: Length = 1;
     let mut denominator: Length = 1;
-    let harmonics = integer_partials(limit).ok()?;
+    let harmonics = integer_partials(limit)?;
+    let power = |harmonic: Length, el: Exponent| {
+        harmonic.checked_pow(el.unsigned_abs()).ok_or(Error::RatioOverflow)
+    };
     for (&harmonic, &el) in harmonics.iter().zip(rsvec.iter()) {
         if el > 0 {
-            numerator =
-                numerator.checked_mul(harmonic.checked_pow(el as u32)?)?;
+            numerator = numerator
+                .checked_mul(power(harmonic, el)?)
+                .ok_or(Error::RatioOverflow)?;
         }
         if el < 0 {
-            denominator =
-                denominator.checked_mul(harmonic.checked_pow(-el as u32)?)?;
+            denominator = denominator
+                .checked_mul(power(harmonic, el)?)
+                .ok_or(Error::RatioOverflow)?;
         }
     }
-    Some((numerator, denominator))
+    Ok((numerator, denominator))
+}
+
+/// Turn the ratio-space vector (typed as a mapping) into a ratio
+/// without any limit on the size of the numbers
+#[cfg(feature = "bigint")]
+pub fn get_big_ratio(
+    limit: &PrimeLimit,
+    rsvec: &ETMap,
+) -> Result<(BigUint, BigUint), Error> {
+    let mut numerator = BigUint::from(1u8);
+    let mut denominator = BigUint::from(1u8);
+    let harmonics = integer_partials(limit)?;
+    for (&harmonic, &el) in harmonics.iter().zip(rsvec.iter()) {
+        let power = BigUint::from(harmonic).pow(el.unsigned_abs());
+        if el > 0 {
+            numerator *= power;
+        } else if el < 0 {
+            denominator *= power;
+        }
+    }
+    Ok((numerator, denominator))
 }
 
 /// Turn the ratio-space vector (typed as a mapping) into a ratio-string
 /// or a ket if this is not possible
 pub fn get_ratio_or_ket_string(limit: &PrimeLimit, rsvec: &ETMap) -> String {
     match get_ratio(limit, rsvec) {
-        Some(ratio) => stringify(ratio),
-        None => format!("[{}⟩", join(", ", rsvec)),
+        Ok(ratio) => stringify(ratio),
+        Err(_) => format!("[{}⟩", join(", ", rsvec)),
     }
 }
 
@@ -46,13 +83,22 @@ pub fn stringify(ratio: Ratio) -> String {
 /// Turn the ratio encoded as a string into a vector
 /// in the given prime limit.
 /// Eventually, should work with vectors-as-strings as well
-pub fn parse_as_vector(limit: &PrimeLimit, input: &str) -> Option<ETMap> {
+pub fn parse_as_vector(
+    limit: &PrimeLimit,
+    input: &str,
+) -> Result<ETMap, Error> {
     let input = input.trim();
+    let parse_error = |e: ParseIntError| match e.kind() {
+        IntErrorKind::PosOverflow => Error::RatioOverflow,
+        _ => Error::BadRatio(input.to_string()),
+    };
     let (n, d): Ratio = if let Ok(n) = input.parse() {
         (n, 1)
     } else {
-        let (sn, sd) = input.split_once([':', '/'])?;
-        (sn.parse().ok()?, sd.parse().ok()?)
+        let (sn, sd) = input
+            .split_once([':', '/'])
+            .ok_or_else(|| Error::BadRatio(input.to_string()))?;
+        (sn.parse().map_err(parse_error)?, sd.parse().map_err(parse_error)?)
     };
     factorize_ratio(limit, (n, d))
 }
@@ -73,16 +119,22 @@ fn factorize(limit: &PrimeLimit, n: Length) -> Option<ETMap> {
     if remainder == 1 { Some(result) } else { None }
 }
 
-pub fn factorize_ratio(limit: &PrimeLimit, (n, d): Ratio) -> Option<ETMap> {
-    let numerator = factorize(limit, n)?;
-    let denominator = factorize(limit, d)?;
-    Some(
-        numerator
-            .iter()
-            .zip(denominator.iter())
-            .map(|(&a, &b)| a - b)
-            .collect(),
-    )
+pub fn factorize_ratio(
+    limit: &PrimeLimit,
+    (n, d): Ratio,
+) -> Result<ETMap, Error> {
+    integer_partials(limit)?;
+    if n == 0 || d == 0 {
+        return Err(Error::BadRatio(stringify((n, d))));
+    }
+    let outside = || Error::PrimeOutsideLimit(stringify((n, d)));
+    let numerator = factorize(limit, n).ok_or_else(outside)?;
+    let denominator = factorize(limit, d).ok_or_else(outside)?;
+    Ok(numerator
+        .iter()
+        .zip(denominator.iter())
+        .map(|(&a, &b)| a - b)
+        .collect())
 }
 
 pub fn parse_in_simplest_limit(
@@ -112,7 +164,7 @@ pub fn factorize_ratios_in_simplest_limit(
     let mut limit = PrimeLimit::new(100);
     let mut vectors = ratios
         .iter()
-        .map(|&r| factorize_ratio(&limit, r))
+        .map(|&r| factorize_ratio(&limit, r).ok())
         .collect::<Option<Mapping>>()?;
     let limit_size = limit.pitches.len();
     let trim_point = vectors
@@ -135,24 +187,26 @@ pub fn factorize_ratios_in_simplest_limit(
 }
 
 /// Reverse engineer a prime limit object into a list of integers
-fn integer_partials(
-    limit: &PrimeLimit,
-) -> Result<Vec<Length>, std::num::ParseIntError> {
-    limit.headings.iter().map(|m| m.parse()).collect()
+fn integer_partials(limit: &PrimeLimit) -> Result<Vec<Length>, Error> {
+    limit
+        .headings
+        .iter()
+        .map(|m| m.parse().or(Err(Error::NonPrimeSubgroup(m.clone()))))
+        .collect()
 }
 
 #[test]
 fn get_syntonic_comma() {
     let limit5 = PrimeLimit::new(5);
     let ratio = get_ratio(&limit5, &vec![-4, 4, -1]);
-    assert_eq!(ratio, Some((81, 80)));
+    assert_eq!(ratio, Ok((81, 80)));
 }
 
 #[test]
 fn get_syntonic_comma_string() {
     let limit5 = PrimeLimit::new(5);
     let ratio_string = get_ratio_string(&limit5, &vec![-4, 4, -1]);
-    assert_eq!(ratio_string, Some("81:80".to_string()));
+    assert_eq!(ratio_string, Ok("81:80".to_string()));
 }
 
 #[test]
@@ -173,14 +227,26 @@ fn stringify_syntonic_comma() {
 fn get_major_third() {
     let limit5 = PrimeLimit::new(5);
     let ratio = get_ratio(&limit5, &vec![-2, 0, 1]);
-    assert_eq!(ratio, Some((5, 4)));
+    assert_eq!(ratio, Ok((5, 4)));
 }
 
 #[test]
 fn get_huge_interval() {
     let limit5 = PrimeLimit::new(5);
     let ratio = get_ratio(&limit5, &vec![1000, -1000, 0]);
-    assert_eq!(ratio, None);
+    assert_eq!(ratio, Err(Error::RatioOverflow));
+}
+
+#[test]
+#[cfg(feature = "bigint")]
+fn get_huge_interval_bigint() {
+    let limit5 = PrimeLimit::new(5);
+    let (numerator, denominator) =
+        get_big_ratio(&limit5, &vec![100, -100, 0]).expect("bad limit");
+    assert_eq!(numerator, BigUint::from(2u8).pow(100));
+    assert_eq!(denominator, BigUint::from(3u8).pow(100));
+    let ratio_string = get_ratio_string(&limit5, &vec![100, -100, 0]);
+    assert!(ratio_string.expect("overflow").starts_with("1267650600228"));
 }
 
 #[test]
@@ -199,20 +265,27 @@ fn parse_7_limit() {
 #[test]
 fn parse_7_limit_ratios() {
     let limit = PrimeLimit::new(7);
-    assert_eq!(parse_as_vector(&limit, "225:224"), Some(vec![-5, 2, 2, -1]),);
+    assert_eq!(parse_as_vector(&limit, "225:224"), Ok(vec![-5, 2, 2, -1]),);
+    assert_eq!(parse_as_vector(&limit, "2401:2400"), Ok(vec![-5, -1, -2, 4]),);
+    assert_eq!(parse_as_vector(&limit, "7:4"), Ok(vec![-2, 0, 0, 1]),);
+    assert_eq!(parse_as_vector(&limit, "7/4"), Ok(vec![-2, 0, 0, 1]),);
+    assert_eq!(parse_as_vector(&limit, "   7/4    "), Ok(vec![-2, 0, 0, 1]),);
     assert_eq!(
-        parse_as_vector(&limit, "2401:2400"),
-        Some(vec![-5, -1, -2, 4]),
+        parse_as_vector(&limit, "-7:4"),
+        Err(Error::BadRatio("-7:4".to_string())),
     );
-    assert_eq!(parse_as_vector(&limit, "7:4"), Some(vec![-2, 0, 0, 1]),);
-    assert_eq!(parse_as_vector(&limit, "7/4"), Some(vec![-2, 0, 0, 1]),);
     assert_eq!(
-        parse_as_vector(&limit, "   7/4    "),
-        Some(vec![-2, 0, 0, 1]),
+        parse_as_vector(&limit, "99:100"),
+        Err(Error::PrimeOutsideLimit("99:100".to_string())),
+    );
+    assert_eq!(
+        parse_as_vector(&limit, "foo"),
+        Err(Error::BadRatio("foo".to_string())),
+    );
+    assert_eq!(
+        parse_as_vector(&limit, "1000000000000000000000000000000000000000:1"),
+        Err(Error::RatioOverflow),
     );
-    assert_eq!(parse_as_vector(&limit, "-7:4"), None);
-    assert_eq!(parse_as_vector(&limit, "99:100"), None);
-    assert_eq!(parse_as_vector(&limit, "foo"), None);
 }
 
 #[test]
@@ -239,40 +312,64 @@ fn factorize_13_limit() {
 #[test]
 fn test_5_limit_ratios() {
     let limit = PrimeLimit::new(5);
-    assert_eq!(factorize_ratio(&limit, (1, 1)), Some(vec![0, 0, 0]));
-    assert_eq!(factorize_ratio(&limit, (3, 2)), Some(vec![-1, 1, 0]));
-    assert_eq!(factorize_ratio(&limit, (5, 4)), Some(vec![-2, 0, 1]));
-    assert_eq!(factorize_ratio(&limit, (81, 80)), Some(vec![-4, 4, -1]));
-    assert_eq!(factorize_ratio(&limit, (225, 224)), None);
-    assert_eq!(factorize_ratio(&limit, (1, 0)), None);
-    assert_eq!(factorize_ratio(&limit, (0, 1)), None);
-    assert_eq!(factorize_ratio(&limit, (0, 0)), None);
+    assert_eq!(factorize_ratio(&limit, (1, 1)), Ok(vec![0, 0, 0]));
+    assert_eq!(factorize_ratio(&limit, (3, 2)), Ok(vec![-1, 1, 0]));
+    assert_eq!(factorize_ratio(&limit, (5, 4)), Ok(vec![-2, 0, 1]));
+    assert_eq!(factorize_ratio(&limit, (81, 80)), Ok(vec![-4, 4, -1]));
+    assert_eq!(
+        factorize_ratio(&limit, (225, 224)),
+        Err(Error::PrimeOutsideLimit("225:224".to_string())),
+    );
+    assert_eq!(
+        factorize_ratio(&limit, (1, 0)),
+        Err(Error::BadRatio("1:0".to_string())),
+    );
+    assert_eq!(
+        factorize_ratio(&limit, (0, 1)),
+        Err(Error::BadRatio("0:1".to_string())),
+    );
+    assert_eq!(
+        factorize_ratio(&limit, (0, 0)),
+        Err(Error::BadRatio("0:0".to_string())),
+    );
 }
 
 #[test]
 fn test_13_limit_ratios() {
     let limit = PrimeLimit::new(13);
-    assert_eq!(factorize_ratio(&limit, (1, 1)), Some(vec![0, 0, 0, 0, 0, 0]),);
+    assert_eq!(factorize_ratio(&limit, (1, 1)), Ok(vec![0, 0, 0, 0, 0, 0]),);
     assert_eq!(
         factorize_ratio(&limit, (144, 143)),
-        Some(vec![4, 2, 0, 0, -1, -1]),
+        Ok(vec![4, 2, 0, 0, -1, -1]),
     );
     assert_eq!(
         factorize_ratio(&limit, (143, 144)),
-        Some(vec![-4, -2, 0, 0, 1, 1]),
+        Ok(vec![-4, -2, 0, 0, 1, 1]),
     );
     assert_eq!(
         factorize_ratio(&limit, (225, 224)),
-        Some(vec![-5, 2, 2, -1, 0, 0]),
+        Ok(vec![-5, 2, 2, -1, 0, 0]),
     );
     assert_eq!(
         factorize_ratio(&limit, (100, 99)),
-        Some(vec![2, -2, 2, 0, -1, 0]),
+        Ok(vec![2, -2, 2, 0, -1, 0]),
+    );
+    assert_eq!(
+        factorize_ratio(&limit, (256, 255)),
+        Err(Error::PrimeOutsideLimit("256:255".to_string())),
+    );
+    assert_eq!(
+        factorize_ratio(&limit, (1, 0)),
+        Err(Error::BadRatio("1:0".to_string())),
+    );
+    assert_eq!(
+        factorize_ratio(&limit, (0, 1)),
+        Err(Error::BadRatio("0:1".to_string())),
+    );
+    assert_eq!(
+        factorize_ratio(&limit, (0, 0)),
+        Err(Error::BadRatio("0:0".to_string())),
     );
-    assert_eq!(factorize_ratio(&limit, (256, 255)), None);
-    assert_eq!(factorize_ratio(&limit, (1, 0)), None);
-    assert_eq!(factorize_ratio(&limit, (0, 1)), None);
-    assert_eq!(factorize_ratio(&limit, (0, 0)), None);
 }
 
 #[test]
@@ -385,3 +482,16 @@ fn detect_silly_limit() {
     let result = factorize_ratios_in_simplest_limit(&[(65536, 65535)]);
     assert!(result.is_none());
 }
+
+#[test]
+fn ratio_in_non_prime_subgroup() {
+    let limit: PrimeLimit = "2.3.7/5".parse().expect("bad limit");
+    assert_eq!(
+        get_ratio(&limit, &vec![0, 0, 1]),
+        Err(Error::NonPrimeSubgroup("7/5".to_string())),
+    );
+    assert_eq!(
+        parse_as_vector(&limit, "7:5"),
+        Err(Error::NonPrimeSubgroup("7/5".to_string())),
+    );
+}
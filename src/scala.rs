@@ -0,0 +1,209 @@
+//! Read Scala (.scl) scale files
+//!
+//! The format is described at https://www.huygens-fokker.org/scala/scl_format.html
+//! Lines starting with ! are comments.  The first other line is
+//! a description, the next is the number of notes, and then there's
+//! a line for each note.  A note with a full stop is in cents,
+//! otherwise it's a ratio or an integer.  The 1/1 is left out
+//! and anything after the pitch on a line is ignored.
+
+use super::ratio::{factorize_ratio, factorize_ratios_in_simplest_limit};
+use super::{Cents, ETMap, Error, Mapping, PrimeLimit, Tuning};
+
+/// A note in a Scala file
+#[derive(Clone, Debug, PartialEq)]
+pub enum ScalaPitch {
+    Cents(Cents),
+    Ratio(u128, u128),
+}
+
+impl ScalaPitch {
+    pub fn cents(&self) -> Cents {
+        match *self {
+            ScalaPitch::Cents(cents) => cents,
+            ScalaPitch::Ratio(n, d) => {
+                1200.0 * ((n as f64).log2() - (d as f64).log2())
+            }
+        }
+    }
+}
+
+#[derive(Clone, Debug, PartialEq)]
+pub struct ScalaScale {
+    pub description: String,
+    /// Notes above the 1/1, usually ending with the interval
+    /// of equivalence
+    pub pitches: Vec<ScalaPitch>,
+}
+
+impl ScalaScale {
+    /// Size of each note in cents
+    pub fn cents(&self) -> Tuning {
+        self.pitches.iter().map(ScalaPitch::cents).collect()
+    }
+
+    /// Each note as a vector in the prime limit,
+    /// or None for cents and ratios that don't factorize
+    pub fn vectors(&self, limit: &PrimeLimit) -> Vec<Option<ETMap>> {
+        self.pitches
+            .iter()
+            .map(|pitch| match *pitch {
+                ScalaPitch::Ratio(n, d) => {
+                    factorize_ratio(limit, (n, d)).ok()
+                }
+                ScalaPitch::Cents(_) => None,
+            })
+            .collect()
+    }
+
+    /// The smallest prime limit the notes all fit in
+    /// and the vectors in it, or None unless every note is a ratio
+    /// with prime factors below 100
+    pub fn vectors_in_simplest_limit(&self) -> Option<(PrimeLimit, Mapping)> {
+        let ratios: Vec<(u128, u128)> = self
+            .pitches
+            .iter()
+            .map(|pitch| match *pitch {
+                ScalaPitch::Ratio(n, d) => Some((n, d)),
+                ScalaPitch::Cents(_) => None,
+            })
+            .collect::<Option<_>>()?;
+        factorize_ratios_in_simplest_limit(&ratios)
+    }
+}
+
+/// Read the contents of a Scala file
+pub fn parse_scala(contents: &str) -> Result<ScalaScale, Error> {
+    let mut lines = contents
+        .lines()
+        .map(|line| line.trim_end_matches('\r'))
+        .filter(|line| !line.starts_with('!'));
+    let bad = |reason: &str| Error::BadScalaFile(reason.to_string());
+    let description =
+        lines.next().ok_or_else(|| bad("no description"))?.trim().to_string();
+    let count_line = lines.next().ok_or_else(|| bad("no note count"))?;
+    let n_notes: usize = first_word(count_line)
+        .parse()
+        .map_err(|_| bad(&format!("bad note count {}", count_line.trim())))?;
+    let pitches = lines
+        .take(n_notes)
+        .map(parse_pitch)
+        .collect::<Result<Vec<_>, _>>()?;
+    if pitches.len() < n_notes {
+        return Err(bad(&format!(
+            "expected {} notes but found {}",
+            n_notes,
+            pitches.len()
+        )));
+    }
+    Ok(ScalaScale { description, pitches })
+}
+
+fn parse_pitch(line: &str) -> Result<ScalaPitch, Error> {
+    let word = first_word(line);
+    let bad = || Error::BadScalaFile(format!("bad pitch {}", line.trim()));
+    if word.contains('.') {
+        return word.parse().map(ScalaPitch::Cents).map_err(|_| bad());
+    }
+    let (n, d) = word.split_once('/').unwrap_or((word, "1"));
+    match (n.parse(), d.parse()) {
+        (Ok(n), Ok(d)) if n > 0 && d > 0 => Ok(ScalaPitch::Ratio(n, d)),
+        _ => Err(bad()),
+    }
+}
+
+fn first_word(line: &str) -> &str {
+    line.split_whitespace().next().unwrap_or("")
+}
+
+#[test]
+fn read_mixed() {
+    let contents = "! mixed.scl\r\n\
+        !\r\n\
+        A test scale\r\n \
+        5\r\n\
+        !\r\n\
+        9/8\r\n \
+        386.314 a major third\r\n\
+        4/3\r\n\
+        3\r\n\
+        2/1\r\n";
+    let scale = parse_scala(contents).expect("bad file");
+    assert_eq!(scale.description, "A test scale");
+    assert_eq!(
+        scale.pitches,
+        vec![
+            ScalaPitch::Ratio(9, 8),
+            ScalaPitch::Cents(386.314),
+            ScalaPitch::Ratio(4, 3),
+            ScalaPitch::Ratio(3, 1),
+            ScalaPitch::Ratio(2, 1),
+        ]
+    );
+    let cents = scale.cents();
+    super::assert_between!(203.9, cents[0], 203.92);
+    assert_eq!(cents[4], 1200.0);
+    let limit5 = PrimeLimit::new(5);
+    assert_eq!(
+        scale.vectors(&limit5),
+        vec![
+            Some(vec![-3, 2, 0]),
+            None,
+            Some(vec![2, -1, 0]),
+            Some(vec![0, 1, 0]),
+            Some(vec![1, 0, 0]),
+        ]
+    );
+    assert_eq!(scale.vectors_in_simplest_limit(), None);
+}
+
+#[test]
+fn simplest_limit() {
+    let scale = parse_scala("\n3\n7/6\n3/2\n2\n").expect("bad file");
+    assert_eq!(scale.description, "");
+    let (limit, vectors) =
+        scale.vectors_in_simplest_limit().expect("no limit");
+    assert_eq!(limit.label, "7");
+    assert_eq!(vectors[0], vec![-1, -1, 0, 1]);
+}
+
+#[test]
+fn round_trip() {
+    use super::te::TETemperament;
+    use super::tuned_temperament::TunedTemperament;
+    let limit7 = PrimeLimit::new(7);
+    let meantone = TETemperament::new(
+        &limit7.pitches,
+        &[vec![12, 19, 28, 34], vec![19, 30, 44, 53]],
+    );
+    let scale =
+        parse_scala(&meantone.scala_file(12, "Meantone")).expect("bad file");
+    assert_eq!(
+        scale.description,
+        "12 note scale for some Meantone temperament."
+    );
+    let block = meantone.fokker_block_pitches(12);
+    for (&read, &written) in scale.cents().iter().zip(&block) {
+        super::assert_between!(written - 0.001, read, written + 0.001);
+    }
+}
+
+#[test]
+fn bad_files() {
+    assert_eq!(
+        parse_scala("! empty\n"),
+        Err(Error::BadScalaFile("no description".to_string()))
+    );
+    assert_eq!(
+        parse_scala("desc\nmany\n"),
+        Err(Error::BadScalaFile("bad note count many".to_string()))
+    );
+    assert_eq!(
+        parse_scala("desc\n3\n1.5\n2/1\n"),
+        Err(Error::BadScalaFile("expected 3 notes but found 2".to_string()))
+    );
+    assert_eq!(
+        parse_scala("desc\n1\n-3/2\n"),
+        Err(Error::BadScalaFile("bad pitch -3/2".to_string()))
+    );
+}
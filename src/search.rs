@@ -0,0 +1,511 @@
+//! Badness searches that can report progress and be stopped early
+//!
+//! A search starts with the equal temperaments and builds up
+//! one rank at a time, so the results for each rank
+//! come out of an iterator as soon as they're ready.
+//!
+//! The search for each rank builds on a longer list of results
+//! for the rank below, and the equal temperaments.
+//! How much longer is a safety margin that usually works
+//! but doesn't guarantee anything.  Each rank comes with
+//! a Certificate showing how much of the list is proven complete,
+//! and exact_search raises the margin until the whole list is proven.
+//!
+//! The proof treats badness as a lattice determinant.  Every val
+//! belonging to a temperament class is a point on a lattice,
+//! with the Cangwu badness of the val as its length,
+//! and the badness of the class is the determinant of that lattice.
+//! By Minkowski's second theorem, the successive minima
+//! λ₁ ≤ … ≤ λᵣ of a rank r class with badness B satisfy
+//! λ₁⋯λᵣ ≤ γᵣ^(r/2) B (in octave units) where γᵣ is Hermite's constant.
+//! There's a basis with lengths no more than max(1, √i/2) λᵢ.
+//! λ₁ is at least the badness of the best val, m,
+//! so the longest basis vector (an equal temperament)
+//! and the class from the others (a rank r-1 result)
+//! have bounded badness.  If the lists they come from
+//! are complete to those bounds, the class gets found.
+
+use super::cangwu::{
+    CangwuTemperament, best_zero_first_badness, watched_equal_temperaments,
+    watched_higher_rank_search,
+};
+use super::wedgie::saturation_index;
+use super::{Cents, Exponent, Mapping};
+use std::sync::Arc;
+use std::sync::atomic::{AtomicBool, Ordering};
+
+/// Shared flag to stop a search from another thread
+/// (or a callback)
+#[derive(Clone, Debug, Default)]
+pub struct CancelToken(Arc<AtomicBool>);
+
+impl CancelToken {
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    /// Ask the search to stop as soon as it can
+    pub fn cancel(&self) {
+        self.0.store(true, Ordering::Relaxed);
+    }
+
+    pub fn is_cancelled(&self) -> bool {
+        self.0.load(Ordering::Relaxed)
+    }
+}
+
+/// How far the search has got
+#[derive(Clone, Debug, PartialEq)]
+pub struct Progress {
+    /// The rank being searched for
+    pub rank: usize,
+
+    /// Steps to the octave of the equal temperaments being examined,
+    /// or None above rank 1
+    pub n_notes: Option<Exponent>,
+
+    /// The worst badness that could still make it into the results
+    pub cap: Cents,
+
+    /// How many candidates have been examined for this rank
+    pub candidates: usize,
+}
+
+/// What a search proves about the results for one rank
+#[derive(Clone, Debug, PartialEq)]
+pub struct Certificate {
+    pub rank: usize,
+
+    /// Badness of the worst result returned,
+    /// or infinity if there weren't enough results
+    pub worst: Cents,
+
+    /// Every temperament class of this rank with a lower badness
+    /// than this is in the results, if it's better than the worst.
+    /// If this is lower than the worst, some results might be missing.
+    pub complete_below: Cents,
+}
+
+impl Certificate {
+    /// No temperament class of this rank was missed
+    pub fn is_exact(&self) -> bool {
+        self.complete_below >= self.worst
+    }
+}
+
+/// Results from exact_search
+#[derive(Clone, Debug, PartialEq)]
+pub struct ExactResults {
+    /// Results for each rank, the same as Search gives
+    pub ranks: Vec<Vec<Mapping>>,
+
+    /// Certificate for each rank
+    pub certificates: Vec<Certificate>,
+
+    /// The safety margin that was used in the end
+    pub safety: usize,
+
+    /// False if the search got cancelled before all ranks were found
+    pub finished: bool,
+}
+
+impl ExactResults {
+    pub fn is_exact(&self) -> bool {
+        self.finished && self.certificates.iter().all(Certificate::is_exact)
+    }
+}
+
+/// Search by badness, giving the results for each rank in turn.
+/// Results for rank 1 are equal temperaments as single-row mappings.
+/// The highest rank is one less than the number of primes,
+/// or 1 for a single prime.
+pub struct Search<'a> {
+    plimit: &'a [Cents],
+    ek: Cents,
+    n_results: usize,
+    safety: usize,
+    keep_contorted: bool,
+    cancel: CancelToken,
+    progress: Box<dyn FnMut(&Progress) + 'a>,
+    ets: Mapping,
+    rts: Vec<Mapping>,
+    rank: usize,
+    /// Badness below which the equal temperament list is complete
+    et_complete: Cents,
+    /// Lower limit on the badness of any val
+    shortest: Cents,
+    /// Badness below which the full list in rts is complete
+    complete: Cents,
+    certificate: Option<Certificate>,
+}
+
+impl<'a> Search<'a> {
+    /// plimit: Sizes of prime harmonics in cents
+    ///
+    /// ek: The Cangwu parameter in cents/octave
+    ///
+    /// n_results: How many to return for each rank
+    pub fn new(plimit: &'a [Cents], ek: Cents, n_results: usize) -> Self {
+        let dimension = plimit.len();
+        // Extra results for lower ranks to find the best higher ranks
+        let safety = if dimension < 100 {
+            40
+        } else {
+            4 * (dimension as f64).sqrt().floor() as usize
+        };
+        Search {
+            plimit,
+            ek,
+            n_results,
+            safety,
+            keep_contorted: true,
+            cancel: CancelToken::new(),
+            progress: Box::new(|_| ()),
+            ets: Vec::new(),
+            rts: Vec::new(),
+            rank: 0,
+            et_complete: 0.0,
+            shortest: 0.0,
+            complete: 0.0,
+            certificate: None,
+        }
+    }
+
+    /// Keep this many extra results for each rank below the highest
+    /// to build the next rank from
+    pub fn with_safety(mut self, safety: usize) -> Self {
+        self.safety = safety;
+        self
+    }
+
+    /// Leave out contorted temperament classes.
+    /// They're never needed to build the saturated classes
+    /// of the next rank, so this doesn't lose anything else.
+    pub fn without_contorsion(mut self) -> Self {
+        self.keep_contorted = false;
+        self
+    }
+
+    /// Stop the search when this token gets cancelled
+    pub fn with_cancel(mut self, cancel: CancelToken) -> Self {
+        self.cancel = cancel;
+        self
+    }
+
+    /// Call this now and then while the search is running
+    pub fn with_progress(
+        mut self,
+        progress: impl FnMut(&Progress) + 'a,
+    ) -> Self {
+        self.progress = Box::new(progress);
+        self
+    }
+
+    /// What's proven about the rank last returned by next()
+    pub fn certificate(&self) -> Option<&Certificate> {
+        self.certificate.as_ref()
+    }
+
+    fn highest_rank(&self) -> usize {
+        (self.plimit.len() - 1).max(1)
+    }
+
+    fn badness(&self, mapping: &[Vec<Exponent>]) -> Cents {
+        CangwuTemperament::new(self.plimit, mapping).badness(self.ek)
+    }
+
+    /// Badness below which the search found everything,
+    /// with n_results asked for
+    fn list_worst(&self, n_results: usize) -> Cents {
+        if self.rts.len() < n_results {
+            f64::INFINITY
+        } else {
+            self.rts.last().map_or(f64::INFINITY, |rt| self.badness(rt))
+        }
+    }
+
+    /// Work out the certificate for the results just found
+    fn certify(&mut self, rank: usize, list_worst: Cents) {
+        if rank == 1 {
+            // Vals that map the first prime to nothing
+            // weren't in the search
+            let zero_first =
+                best_zero_first_badness(self.plimit, self.ek, list_worst)
+                    .unwrap_or(list_worst);
+            self.et_complete = list_worst.min(zero_first);
+            self.shortest = self
+                .rts
+                .first()
+                .map_or(zero_first, |et| self.badness(et).min(zero_first));
+            self.complete = self.et_complete;
+        } else {
+            let r = rank as f64;
+            let hermite = hermite_factor(rank);
+            let scale = self.shortest / 12e2;
+            let from_ets = self.et_complete * scale.powf(r - 1.0)
+                / (basis_factor(rank) * hermite);
+            let sub_factor: f64 = (1..rank).map(basis_factor).product();
+            let from_lower = self.complete * scale / (sub_factor * hermite);
+            self.complete = list_worst.min(from_ets).min(from_lower);
+        }
+        let worst = match self.rts.get(self.n_results.max(1) - 1) {
+            Some(rt) => self.badness(rt),
+            None => f64::INFINITY,
+        };
+        self.certificate =
+            Some(Certificate { rank, worst, complete_below: self.complete });
+    }
+}
+
+/// γᵣ^(r/2) for Hermite's constant γᵣ
+fn hermite_factor(rank: usize) -> f64 {
+    // γᵣ^r is known for these ranks
+    const POWERS: [f64; 8] =
+        [1.0, 4.0 / 3.0, 2.0, 4.0, 8.0, 64.0 / 3.0, 64.0, 256.0];
+    match POWERS.get(rank.wrapping_sub(1)) {
+        Some(power) => power.sqrt(),
+        // γᵣ ≤ 1 + r/4
+        None => (1.0 + rank as f64 / 4.0).powf(rank as f64 / 2.0),
+    }
+}
+
+/// How much longer the ith basis vector can be
+/// than the ith successive minimum
+fn basis_factor(i: usize) -> f64 {
+    ((i as f64).sqrt() / 2.0).max(1.0)
+}
+
+/// Repeat the search with a doubling safety margin until
+/// every rank is proven complete or the margin reaches max_safety.
+/// If the cancel token gets cancelled, the last finished search
+/// is returned, or as much as there is of the one that was running.
+pub fn exact_search(
+    plimit: &[Cents],
+    ek: Cents,
+    n_results: usize,
+    max_safety: usize,
+    cancel: &CancelToken,
+) -> ExactResults {
+    let mut safety =
+        Search::new(plimit, ek, n_results).safety.min(max_safety);
+    let mut finished: Option<ExactResults> = None;
+    loop {
+        let mut search = Search::new(plimit, ek, n_results)
+            .with_safety(safety)
+            .with_cancel(cancel.clone());
+        let mut results = ExactResults {
+            ranks: Vec::new(),
+            certificates: Vec::new(),
+            safety,
+            finished: false,
+        };
+        while let Some(rts) = search.next() {
+            results.ranks.push(rts);
+            let certificate = search.certificate().expect("no certificate");
+            results.certificates.push(certificate.clone());
+        }
+        if cancel.is_cancelled() {
+            return finished.unwrap_or(results);
+        }
+        results.finished = true;
+        if results.is_exact() || safety >= max_safety {
+            return results;
+        }
+        finished = Some(results);
+        safety = (safety * 2).min(max_safety);
+    }
+}
+
+impl Iterator for Search<'_> {
+    type Item = Vec<Mapping>;
+
+    /// The results for the next rank, or None if the search
+    /// has finished or been cancelled
+    fn next(&mut self) -> Option<Vec<Mapping>> {
+        if self.rank >= self.highest_rank() || self.cancel.is_cancelled() {
+            return None;
+        }
+        let rank = self.rank + 1;
+        let n_results = if rank == self.highest_rank() {
+            self.n_results
+        } else {
+            self.n_results + self.safety
+        };
+        let cancel = &self.cancel;
+        let progress = &mut self.progress;
+        if rank == 1 {
+            // All equal temperaments are needed to build the higher ranks
+            let n_results = self.n_results + self.safety;
+            self.ets = watched_equal_temperaments(
+                self.plimit,
+                self.ek,
+                n_results,
+                |n_notes, cap, candidates| {
+                    progress(&Progress {
+                        rank,
+                        n_notes: Some(n_notes),
+                        cap,
+                        candidates,
+                    });
+                    !cancel.is_cancelled()
+                },
+            )?;
+            self.rts = self.ets.iter().map(|et| vec![et.clone()]).collect();
+        } else {
+            self.rts = watched_higher_rank_search(
+                self.plimit,
+                &self.ets,
+                &self.rts,
+                self.ek,
+                n_results,
+                |cap, candidates| {
+                    progress(&Progress {
+                        rank,
+                        n_notes: None,
+                        cap,
+                        candidates,
+                    });
+                    !cancel.is_cancelled()
+                },
+            )?;
+        }
+        let list_worst = self.list_worst(n_results);
+        if !self.keep_contorted {
+            self.rts.retain(|rt| saturation_index(rt) == Ok(1));
+            if rank == 1 {
+                self.ets.retain(|et| {
+                    saturation_index(std::slice::from_ref(et)) == Ok(1)
+                });
+            }
+        }
+        self.certify(rank, list_worst);
+        self.rank = rank;
+        Some(self.rts.iter().take(self.n_results).cloned().collect())
+    }
+}
+
+#[test]
+fn search_matches_functions() {
+    use super::cangwu::{get_equal_temperaments, higher_rank_search};
+    let limit7 = super::PrimeLimit::new(7).pitches;
+    let ranks: Vec<Vec<Mapping>> = Search::new(&limit7, 1.0, 5).collect();
+    assert_eq!(ranks.len(), 3);
+    let ets = get_equal_temperaments(&limit7, 1.0, 45);
+    let rank1: Vec<Mapping> = ets.iter().map(|et| vec![et.clone()]).collect();
+    assert_eq!(ranks[0], rank1[..5]);
+    let rank2 = higher_rank_search(&limit7, &ets, &rank1, 1.0, 45);
+    assert_eq!(ranks[1], rank2[..5]);
+    let rank3 = higher_rank_search(&limit7, &ets, &rank2, 1.0, 5);
+    assert_eq!(ranks[2], rank3);
+}
+
+#[test]
+fn search_progress() {
+    let limit7 = super::PrimeLimit::new(7).pitches;
+    let mut reports = Vec::new();
+    let n_ranks = Search::new(&limit7, 1.0, 5)
+        .with_progress(|progress| reports.push(progress.clone()))
+        .count();
+    assert_eq!(n_ranks, 3);
+    let first = reports.first().expect("no progress");
+    assert_eq!((first.rank, first.n_notes), (1, Some(1)));
+    let last = reports.last().expect("no progress");
+    assert_eq!((last.rank, last.n_notes), (3, None));
+    assert!(last.cap.is_finite());
+    assert!(reports.windows(2).all(|pair| pair[0].rank <= pair[1].rank));
+}
+
+#[test]
+fn cancelled_search() {
+    let limit11 = super::PrimeLimit::new(11).pitches;
+    let cancel = CancelToken::new();
+    let mut search =
+        Search::new(&limit11, 1.0, 5).with_cancel(cancel.clone());
+    assert_eq!(search.next().map(|ets| ets.len()), Some(5));
+    cancel.cancel();
+    assert_eq!(search.next(), None);
+
+    // Cancelling from the progress callback stops a rank part way
+    let cancel = CancelToken::new();
+    let stopper = cancel.clone();
+    let ranks: Vec<_> = Search::new(&limit11, 1.0, 5)
+        .with_cancel(cancel)
+        .with_progress(|progress| {
+            if progress.rank == 2 {
+                stopper.cancel()
+            }
+        })
+        .collect();
+    assert_eq!(ranks.len(), 1);
+}
+
+#[test]
+fn search_without_contorsion() {
+    let limit5 = super::PrimeLimit::new(5).pitches;
+    let all: Vec<Vec<Mapping>> = Search::new(&limit5, 1.0, 50).collect();
+    let saturated: Vec<Vec<Mapping>> =
+        Search::new(&limit5, 1.0, 50).without_contorsion().collect();
+    assert!(all.iter().flatten().any(|rt| saturation_index(rt).unwrap() > 1));
+    for (all, saturated) in all.iter().zip(&saturated) {
+        assert!(
+            saturated.iter().all(|rt| saturation_index(rt).unwrap() == 1)
+        );
+        let expected: Vec<&Mapping> = all
+            .iter()
+            .filter(|rt| saturation_index(rt).unwrap() == 1)
+            .collect();
+        assert_eq!(
+            saturated.iter().collect::<Vec<_>>()[..expected.len()],
+            expected
+        );
+    }
+}
+
+#[test]
+fn exact_seven_limit() {
+    let limit7 = super::PrimeLimit::new(7).pitches;
+    let results = exact_search(&limit7, 1.0, 10, 640, &CancelToken::new());
+    assert!(results.is_exact());
+    assert_eq!(results.certificates.len(), 3);
+    let ranks: Vec<_> =
+        Search::new(&limit7, 1.0, 10).with_safety(results.safety).collect();
+    assert_eq!(results.ranks, ranks);
+}
+
+#[test]
+fn certificates() {
+    let limit11 = super::PrimeLimit::new(11).pitches;
+    let mut search = Search::new(&limit11, 1.0, 10);
+    assert_eq!(search.certificate(), None);
+    let mut rank = 0;
+    while search.next().is_some() {
+        rank += 1;
+        let certificate = search.certificate().expect("no certificate");
+        assert_eq!(certificate.rank, rank);
+        assert!(certificate.complete_below > 0.0);
+        assert!(certificate.worst.is_finite());
+    }
+    assert_eq!(rank, 4);
+    // The bounds get weaker as the rank goes up
+    let certificate = search.certificate().expect("no certificate");
+    assert!(!certificate.is_exact());
+}
+
+#[test]
+fn cancelled_exact_search() {
+    let limit11 = super::PrimeLimit::new(11).pitches;
+    let cancel = CancelToken::new();
+    cancel.cancel();
+    let results = exact_search(&limit11, 1.0, 10, 640, &cancel);
+    assert!(results.ranks.is_empty());
+    assert!(!results.finished);
+    assert!(!results.is_exact());
+}
+
+#[test]
+fn hermite_factors() {
+    super::assert_between!(1.1547, hermite_factor(2), 1.1548);
+    assert_eq!(hermite_factor(3), 2f64.sqrt());
+    assert_eq!(hermite_factor(8), 16.0);
+    assert!(hermite_factor(9) > hermite_factor(8));
+}
@@ -0,0 +1,198 @@
+//! Owned records of tuned temperaments for storing results.
+//!
+//! With the "serde" feature, a snapshot serializes to JSON as
+//!
+//! ```json
+//! {
+//!   "limit": {"label": "5", "pitches": [1200.0, ...], "headings": ["2", ...]},
+//!   "mapping": [[12, 19, 28], [19, 30, 44]],
+//!   "key": [1, 4, 1, 0, -4],
+//!   "name": "Meantone",
+//!   "tuning": [...],
+//!   "weighting": "tenney",
+//!   "tuning_map": [...],
+//!   "mistunings": [...],
+//!   "max_error": 2.214,
+//!   "rms_error": 1.582
+//! }
+//! ```
+//!
+//! The mapping is a list of vals, one for each generator in the tuning.
+//! The key identifies the temperament class (see `TemperamentClass::key`)
+//! and the name is null if the class isn't in the names table.
+//! Tunings and mistunings are in cents, with the tuning map and mistunings
+//! following the prime limit.
+//! The weighting is the one the tuning was optimized for
+//! ("tenney", "weil", "frobenius", "benedetti",
+//! or {"custom": [...]} with a weight for each prime)
+//! and is Tenney if it's missing, as in older documents.
+//! The errors are Tenney-weighted in cents per octave:
+//! the maximum (as for TOP) and the root-mean-square of the
+//! weighted mistunings.
+//! New fields may be added but existing ones won't change meaning.
+
+use super::cangwu::TenneyWeighted;
+use super::te::TETemperament;
+use super::temperament_class::TemperamentClass;
+use super::tuned_temperament::TunedTemperament;
+use super::weighting::Weighting;
+use super::{Cents, ETMap, Error, Mapping, PrimeLimit, Tuning};
+use std::borrow::Cow;
+
+#[derive(Debug, Clone, PartialEq)]
+#[cfg_attr(feature = "serde", derive(serde::Serialize, serde::Deserialize))]
+pub struct TemperamentSnapshot {
+    pub limit: PrimeLimit,
+    pub mapping: Mapping,
+    pub key: ETMap,
+    pub name: Option<String>,
+    pub tuning: Tuning,
+    #[cfg_attr(feature = "serde", serde(default))]
+    pub weighting: Weighting,
+    pub tuning_map: Tuning,
+    pub mistunings: Tuning,
+    pub max_error: Cents,
+    pub rms_error: Cents,
+}
+
+impl TemperamentSnapshot {
+    /// Record the current state of any tuned temperament
+    /// that uses this prime limit
+    pub fn new(
+        limit: &PrimeLimit,
+        rt: &(impl TunedTemperament + TenneyWeighted),
+    ) -> Result<Self, Error> {
+        debug_assert_eq!(limit.pitches, TunedTemperament::plimit(rt));
+        let weighted_errors: Tuning =
+            rt.weighted_tuning_map().iter().map(|&w| w - 1.0).collect();
+        let max_error =
+            weighted_errors.iter().map(|e| e.abs()).fold(0.0, f64::max)
+                * 12e2;
+        let mean_square = weighted_errors.iter().map(|e| e * e).sum::<f64>()
+            / weighted_errors.len() as f64;
+        Ok(TemperamentSnapshot {
+            limit: limit.clone(),
+            mapping: TemperamentClass::mapping(rt).clone(),
+            key: rt.key()?,
+            name: rt.name(limit).map(str::to_string),
+            tuning: rt.tuning().clone(),
+            weighting: rt.weighting().clone(),
+            tuning_map: rt.tuning_map(),
+            mistunings: rt.mistunings(),
+            max_error,
+            rms_error: mean_square.sqrt() * 12e2,
+        })
+    }
+
+    /// Bring the temperament back to life with the stored tuning
+    pub fn temperament(&self) -> TETemperament<'_> {
+        TETemperament {
+            plimit: Cow::Borrowed(&self.limit.pitches),
+            melody: self.mapping.clone(),
+            tuning: self.tuning.clone(),
+            weighting: self.weighting.clone(),
+        }
+    }
+}
+
+#[cfg(test)]
+fn make_meantone(limit: &PrimeLimit) -> TemperamentSnapshot {
+    let mapping = vec![vec![12, 19, 28], vec![19, 30, 44]];
+    TemperamentSnapshot::new(
+        limit,
+        &TETemperament::new(&limit.pitches, &mapping),
+    )
+    .expect("Meantone key overflowed")
+}
+
+#[test]
+fn meantone_snapshot() {
+    let limit = PrimeLimit::new(5);
+    let snapshot = make_meantone(&limit);
+    assert_eq!(snapshot.name, Some("Meantone".to_string()));
+    assert_eq!(snapshot.key, vec![1, 4, 1, 0, -4]);
+    assert_eq!(snapshot.mistunings.len(), 3);
+    assert!(snapshot.rms_error < snapshot.max_error);
+    let rt = snapshot.temperament();
+    assert_eq!(TemperamentSnapshot::new(&limit, &rt), Ok(snapshot));
+}
+
+#[test]
+fn weil_snapshot() {
+    let limit = PrimeLimit::new(5);
+    let mapping = vec![vec![12, 19, 28], vec![19, 30, 44]];
+    let rt =
+        TETemperament::weighted(&limit.pitches, &mapping, Weighting::Weil)
+            .expect("bad weighting");
+    let snapshot = TemperamentSnapshot::new(&limit, &rt)
+        .expect("Meantone key overflowed");
+    assert_eq!(snapshot.weighting, Weighting::Weil);
+    let restored = snapshot.temperament();
+    assert_eq!(restored.weighting, Weighting::Weil);
+    assert_eq!(restored.error(), rt.error());
+    assert_eq!(restored.complexity(), rt.complexity());
+}
+
+#[test]
+fn top_snapshot() {
+    let limit = PrimeLimit::new(5);
+    let mapping = vec![vec![12, 19, 28], vec![19, 30, 44]];
+    let top = super::top::TOPTemperament::new(&limit.pitches, &mapping)
+        .expect("Couldn't optimize meantone");
+    let snapshot = TemperamentSnapshot::new(&limit, &top)
+        .expect("Meantone key overflowed");
+    super::assert_between!(1.6985, snapshot.max_error, 1.6986);
+}
+
+#[cfg(feature = "serde")]
+#[test]
+fn json_round_trip() {
+    let limit = PrimeLimit::new(5);
+    let snapshot = make_meantone(&limit);
+    let json = serde_json::to_string(&snapshot).expect("serialize failed");
+    let loaded: TemperamentSnapshot =
+        serde_json::from_str(&json).expect("deserialize failed");
+    assert_eq!(loaded, snapshot);
+    // Older documents without a weighting are Tenney weighted
+    let mut json = serde_json::to_value(&snapshot).expect("serialize failed");
+    json.as_object_mut().expect("not an object").remove("weighting");
+    let loaded: TemperamentSnapshot =
+        serde_json::from_value(json).expect("deserialize failed");
+    assert_eq!(loaded.weighting, Weighting::Tenney);
+}
+
+#[cfg(feature = "serde")]
+#[test]
+fn json_schema() {
+    let limit = PrimeLimit::new(5);
+    let json = serde_json::to_value(make_meantone(&limit))
+        .expect("serialize failed");
+    assert_eq!(json["limit"]["label"], "5");
+    assert_eq!(json["limit"]["headings"], serde_json::json!(["2", "3", "5"]));
+    assert_eq!(
+        json["mapping"],
+        serde_json::json!([[12, 19, 28], [19, 30, 44]])
+    );
+    assert_eq!(json["key"], serde_json::json!([1, 4, 1, 0, -4]));
+    assert_eq!(json["name"], "Meantone");
+    assert_eq!(json["weighting"], "tenney");
+    let custom = TETemperament::weighted(
+        &limit.pitches,
+        &[vec![12, 19, 28]],
+        Weighting::Custom(vec![1.0, 0.5, 0.25]),
+    )
+    .expect("bad weighting");
+    let json = serde_json::to_value(
+        TemperamentSnapshot::new(&limit, &custom).expect("key overflowed"),
+    )
+    .expect("serialize failed");
+    assert_eq!(
+        json["weighting"],
+        serde_json::json!({"custom": [1.0, 0.5, 0.25]})
+    );
+    for field in
+        ["tuning", "tuning_map", "mistunings", "max_error", "rms_error"]
+    {
+        assert!(json.get(field).is_some(), "missing {}", field);
+    }
+}
@@ -4,12 +4,19 @@ use na::DMatrix;
 use super::cangwu::{CangwuTemperament, TenneyWeighted, rms_of_matrix};
 use super::temperament_class::TemperamentClass;
 use super::tuned_temperament::TunedTemperament;
-use super::{Cents, ETMap, Mapping, Tuning};
-
+use super::weighting::Weighting;
+use super::{Cents, ETMap, Error, Mapping, Tuning};
+use std::borrow::Cow;
+
+/// Tenney-Euclidean tuned temperament.
+/// The prime limit can be borrowed or owned:
+/// TETemperament<'static> is the owned form.
+#[derive(Clone, Debug)]
 pub struct TETemperament<'a> {
-    pub plimit: &'a [Cents],
+    pub plimit: Cow<'a, [Cents]>,
     pub melody: Mapping,
     pub tuning: Tuning,
+    pub weighting: Weighting,
 }
 
 impl TemperamentClass for TETemperament<'_> {
@@ -20,7 +27,7 @@ impl TemperamentClass for TETemperament<'_> {
 
 impl TunedTemperament for TETemperament<'_> {
     fn plimit(&self) -> &[Cents] {
-        self.plimit
+        &self.plimit
     }
 
     fn tuning(&self) -> &Tuning {
@@ -34,22 +41,79 @@ impl TenneyWeighted for TETemperament<'_> {
     }
 
     fn plimit(&self) -> &[Cents] {
-        self.plimit
+        &self.plimit
+    }
+
+    fn weighting(&self) -> &Weighting {
+        &self.weighting
     }
 }
 
 impl<'a> TETemperament<'a> {
     /// Upgrade vectors into a struct of nalgebra objects
-    pub fn new(plimit: &'a [Cents], melody: &[ETMap]) -> Self {
-        let melody = melody.to_vec();
-        let mut rt = TETemperament { plimit, melody, tuning: vec![0.0] };
+    pub fn new(
+        plimit: impl Into<Cow<'a, [Cents]>>,
+        melody: &[ETMap],
+    ) -> Self {
+        Self::optimal(plimit.into(), melody.to_vec(), Weighting::Tenney)
+    }
+
+    /// Optimal tuning with a weighting other than Tenney,
+    /// which also goes into the error and complexity
+    pub fn weighted(
+        plimit: impl Into<Cow<'a, [Cents]>>,
+        melody: &[ETMap],
+        weighting: Weighting,
+    ) -> Result<Self, Error> {
+        let plimit = plimit.into();
+        weighting.check(&plimit)?;
+        Ok(Self::optimal(plimit, melody.to_vec(), weighting))
+    }
+
+    fn optimal(
+        plimit: Cow<'a, [Cents]>,
+        melody: Mapping,
+        weighting: Weighting,
+    ) -> Self {
+        let mut rt =
+            TETemperament { plimit, melody, tuning: vec![0.0], weighting };
         let wmap = rt.weighted_mapping();
         let pinv = wmap.pseudo_inverse(0.0).expect("no pseudoinverse");
-        let tuning = pinv.column_sum() * 12e2;
+        let tuning = match rt.weighting {
+            // The weighted JI point is all 1200 cents
+            Weighting::Tenney => pinv.column_sum() * 12e2,
+            _ => pinv * rt.weighting.weighted_ji(&rt.plimit),
+        };
         rt.tuning = tuning.iter().cloned().collect();
         rt
     }
 
+    /// The same temperament class with a different tuning,
+    /// sharing the prime limit
+    pub fn with_tuning(&self, tuning: Tuning) -> TETemperament<'_> {
+        TETemperament {
+            plimit: Cow::Borrowed(&self.plimit),
+            melody: self.melody.clone(),
+            tuning,
+            weighting: self.weighting.clone(),
+        }
+    }
+
+    /// Copy that borrows the prime limit from this one
+    pub fn borrowed(&self) -> TETemperament<'_> {
+        self.with_tuning(self.tuning.clone())
+    }
+
+    /// Take ownership of the prime limit to lose the lifetime
+    pub fn into_owned(self) -> TETemperament<'static> {
+        TETemperament {
+            plimit: Cow::Owned(self.plimit.into_owned()),
+            melody: self.melody,
+            tuning: self.tuning,
+            weighting: self.weighting,
+        }
+    }
+
     /// Optimal TE error
     pub fn error(&self) -> f64 {
         self.badness() / self.complexity()
@@ -69,6 +133,9 @@ impl<'a> TETemperament<'a> {
     }
 
     pub fn badness(&self) -> Cents {
+        if self.weighting != Weighting::Tenney {
+            return self.weighted_badness();
+        }
         let rank = self.melody.len();
         let dimension = self.plimit.len();
         let m = self.weighted_mapping();
@@ -81,6 +148,18 @@ impl<'a> TETemperament<'a> {
         rms_of_matrix(&(m - translation.transpose())) * 1200.0
     }
 
+    /// Badness as the size of the weighted mapping
+    /// without the part along the weighted JI point,
+    /// scaled so that Tenney weighting agrees with badness()
+    fn weighted_badness(&self) -> Cents {
+        let m = self.weighted_mapping();
+        let ji = self.weighting.weighted_ji(&self.plimit);
+        let unit = ji.normalize();
+        let along_ji = &unit * (unit.transpose() * &m);
+        let scale = ji.norm() / (self.plimit.len() as f64).sqrt();
+        rms_of_matrix(&(m - along_ji)) * scale
+    }
+
     /// Strictly, pure equivalence interval TE
     pub fn pote_tuning(&self) -> Tuning {
         self.unstretched_tuning()
@@ -97,7 +176,7 @@ impl<'a> TETemperament<'a> {
     }
 
     pub fn unison_vectors(&self, n_results: usize) -> Mapping {
-        let tc = CangwuTemperament::new(self.plimit, &self.melody);
+        let tc = CangwuTemperament::new(&*self.plimit, &self.melody);
         tc.unison_vectors(self.error(), n_results)
     }
 }
@@ -215,6 +294,35 @@ fn pote_mistunings() {
     check_float_vec(&jove.pote_mistunings(), 3, expected);
 }
 
+/// The prime limit here doesn't outlive the function
+#[cfg(test)]
+fn make_owned_marvel() -> TETemperament<'static> {
+    let limit11 = super::PrimeLimit::new(11);
+    make_marvel(&limit11).into_owned()
+}
+
+#[test]
+fn owned_marvel() {
+    let marvel = make_owned_marvel();
+    let expected = "0.000 -1.566 -2.773 -0.968 -2.328";
+    check_float_vec(&marvel.pote_mistunings(), 3, expected);
+    // Owned temperaments can go to other threads
+    let handle = std::thread::spawn(move || marvel.complexity());
+    let complexity = handle.join().expect("thread failed");
+    let limit11 = super::PrimeLimit::new(11);
+    assert_eq!(complexity, make_marvel(&limit11).complexity());
+}
+
+#[test]
+fn marvel_with_pote_tuning() {
+    let limit11 = super::PrimeLimit::new(11);
+    let marvel = make_marvel(&limit11);
+    let pote = marvel.with_tuning(marvel.pote_tuning());
+    let expected = "0.000 -1.566 -2.773 -0.968 -2.328";
+    check_float_vec(&pote.mistunings(), 3, expected);
+    assert_eq!(pote.melody, marvel.borrowed().melody);
+}
+
 #[rustfmt::skip]
 #[test]
 fn mystery() {
@@ -360,6 +468,64 @@ fn scala_file() {
     assert_eq!(content, expected.replace('\n', "\r\n"));
 }
 
+#[test]
+fn kbm_file() {
+    use super::tuned_temperament::{KeyboardMapping, kbm_file};
+    let white_keys = KeyboardMapping {
+        middle_note: 62,
+        reference_note: 69,
+        reference_frequency: 440.0,
+        pattern: vec![
+            Some(0),
+            None,
+            Some(1),
+            Some(2),
+            None,
+            Some(3),
+            None,
+            Some(4),
+            None,
+            Some(5),
+            Some(6),
+            None,
+        ],
+    };
+    let content = kbm_file(7, &white_keys);
+    let expected = r#"! Generated by https://x31eq.com/temper/client.html
+! Size of map:
+12
+! First MIDI note number to retune:
+0
+! Last MIDI note number to retune:
+127
+! Middle note where the first entry of the mapping is mapped to:
+62
+! Reference note for which frequency is given:
+69
+! Frequency to tune the above note to:
+440.000000
+! Scale degree to consider as formal octave:
+7
+! Mapping.
+0
+x
+1
+2
+x
+3
+x
+4
+x
+5
+6
+x
+"#;
+    assert_eq!(content, expected.replace('\n', "\r\n"));
+    let linear = kbm_file(12, &KeyboardMapping::default());
+    assert!(linear.contains("! Size of map:\r\n0\r\n"));
+    assert!(linear.ends_with("formal octave:\r\n12\r\n! Mapping.\r\n"));
+}
+
 // Duplicate of TemperamentClass test
 #[test]
 fn generators() {
@@ -392,3 +558,100 @@ fn check_float_vec(tuning: &Tuning, decimals: usize, expected: &str) {
     formatted.remove(0);
     assert_eq!(formatted, expected.to_string());
 }
+
+#[test]
+fn weightings() {
+    let limit11 = super::PrimeLimit::new(11);
+    let marvel = make_marvel(&limit11);
+    // The general badness agrees with the Tenney shortcut
+    super::assert_between!(
+        marvel.badness() - 1e-9,
+        marvel.weighted_badness(),
+        marvel.badness() + 1e-9
+    );
+    let tenney_weights =
+        Weighting::Custom(Weighting::Tenney.weights(&limit11.pitches));
+    let custom = TETemperament::weighted(
+        &limit11.pitches,
+        &marvel.melody,
+        tenney_weights,
+    )
+    .unwrap();
+    for (&x, &y) in custom.tuning.iter().zip(&marvel.tuning) {
+        super::assert_between!(y - 1e-6, x, y + 1e-6);
+    }
+    super::assert_between!(
+        marvel.error() - 1e-6,
+        custom.error(),
+        marvel.error() + 1e-6
+    );
+    for weighting in Weighting::NAMED {
+        let rt = TETemperament::weighted(
+            &limit11.pitches,
+            &marvel.melody,
+            weighting.clone(),
+        )
+        .unwrap();
+        assert_eq!(rt.with_tuning(vec![]).weighting, weighting);
+        assert!(rt.error() > 0.0);
+        assert!(rt.error() < 1.0, "{:?} error {}", weighting, rt.error());
+        let octave = rt.tuning_map()[0];
+        super::assert_between!(1199.0, octave, 1201.0);
+    }
+    // Unweighted tuning is the least squares fit to the primes
+    let frobenius = TETemperament::weighted(
+        &limit11.pitches,
+        &marvel.melody,
+        Weighting::Frobenius,
+    )
+    .unwrap();
+    assert!(
+        TETemperament::weighted(
+            &limit11.pitches,
+            &marvel.melody,
+            Weighting::Custom(vec![1.0; 3]),
+        )
+        .is_err()
+    );
+    let sum_squares = |rt: &TETemperament| {
+        rt.mistunings().iter().map(|x| x * x).sum::<f64>()
+    };
+    assert!(sum_squares(&frobenius) < sum_squares(&marvel));
+}
+
+#[test]
+fn meantone_from_ratios() {
+    let limit5 = super::PrimeLimit::new(5);
+    let meantone = TETemperament::new(
+        &limit5.pitches,
+        &[vec![12, 19, 28], vec![19, 30, 44]],
+    );
+    let class = CangwuTemperament::from_ratios(&limit5, &["81/80"])
+        .expect("no meantone");
+    let rt = TETemperament::new(&limit5.pitches, &class.melody);
+    assert_eq!(rt.melody, meantone.reduced_mapping().unwrap());
+    assert_eq!(rt.key().unwrap(), meantone.key().unwrap());
+    super::assert_between!(1.58222, rt.error(), 1.58223);
+    super::assert_between!(1.58222, meantone.error(), 1.58223);
+}
+
+#[test]
+fn meantone_mos() {
+    let limit5 = super::PrimeLimit::new(5);
+    let meantone = TETemperament::new(
+        &limit5.pitches,
+        &[vec![12, 19, 28], vec![19, 30, 44]],
+    );
+    assert_eq!(meantone.mos_sizes(20), vec![2, 3, 5, 7, 12, 19]);
+    let diatonic = meantone.mos_scale(7).expect("no diatonic");
+    assert_eq!(diatonic.pattern, "sLLsLLL");
+    let octave = meantone.tuning_map()[0];
+    assert_eq!(diatonic.pitches.last(), Some(&octave));
+    let content = meantone.mos_scala_file(7, "Meantone").expect("no file");
+    assert!(content.contains("! MOS sLLsLLL\r\n"));
+    assert!(content.starts_with("! Meantone_7.scl\r\n"));
+    assert_eq!(meantone.mos_scale(8), None);
+    assert!(
+        make_marvel(&super::PrimeLimit::new(11)).mos_sizes(20).is_empty()
+    );
+}
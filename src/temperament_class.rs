@@ -1,5 +1,12 @@
+extern crate nalgebra as na;
+use super::lattice;
 use super::names::NAMES_BY_LIMIT;
-use super::{ETMap, ETSlice, Exponent, Mapping, PrimeLimit, map};
+use super::uv::{mapping_from_unison_vectors, saturated_kernel_basis};
+use super::wedgie::{Wedgie, normalize, saturation_index, wedge};
+use super::{
+    Cents, ETMap, ETSlice, Error, Exponent, Mapping, PrimeLimit, Tuning, map,
+};
+use na::{DMatrix, DVector};
 
 pub trait TemperamentClass {
     fn mapping(&self) -> &Mapping;
@@ -7,28 +14,51 @@ pub trait TemperamentClass {
     /// Unique identifier for the mapping
     /// (hermite normal form flattened and
     /// with always-zero entries removed)
-    fn key(&self) -> ETMap {
-        self.reduced_mapping()
+    fn key(&self) -> Result<ETMap, Error> {
+        Ok(self
+            .reduced_mapping()?
             .iter()
             .enumerate()
             .rev()
             .flat_map(|(i, col)| col[i..].iter().cloned())
-            .collect()
+            .collect())
     }
 
-    fn reduced_mapping(&self) -> Mapping {
-        super::hermite_normal_form(self.mapping())
+    fn reduced_mapping(&self) -> Result<Mapping, Error> {
+        super::checked_hermite_normal_form(self.mapping())
     }
 
-    /// Actual rank of the mapping matrix
-    fn rank(&self) -> usize {
-        let mut result = 0;
-        for col in self.reduced_mapping().iter() {
-            if col.iter().any(|&x| x != 0) {
-                result += 1;
-            }
-        }
-        result
+    /// Actual rank of the mapping matrix.
+    /// Errors if the calculation overflows,
+    /// which it can't with the "bigint" feature.
+    fn rank(&self) -> Result<usize, Error> {
+        lattice::rank(self.mapping())
+    }
+
+    /// Order of contorsion: how many times over the mapping
+    /// covers the vals of its temperament class.
+    /// 1 means no contorsion.
+    fn contorsion(&self) -> Result<i64, Error> {
+        saturation_index(self.mapping())
+    }
+
+    fn is_contorted(&self) -> Result<bool, Error> {
+        Ok(self.contorsion()? > 1)
+    }
+
+    /// Mapping of the temperament class without contorsion,
+    /// in Hermite normal form
+    fn defactored_mapping(&self) -> Result<Mapping, Error> {
+        let Some(n_primes) = self.mapping().first().map(Vec::len) else {
+            return Ok(vec![]);
+        };
+        let uvs = saturated_kernel_basis(self.mapping())?;
+        mapping_from_unison_vectors(n_primes, &uvs)
+    }
+
+    /// Normalized multival, without contorsion
+    fn wedgie(&self) -> Result<Wedgie, Error> {
+        Ok(normalize(&wedge(self.mapping())?))
     }
 
     fn name(&self, limit: &PrimeLimit) -> Option<&'static str> {
@@ -37,17 +67,21 @@ pub trait TemperamentClass {
             limit.headings.iter().map(|s| s.as_str()).collect();
         match NAMES_BY_LIMIT.get(&limit_key) {
             Some(names_by_linmap) => {
-                names_by_linmap.get(&self.key()).copied()
+                names_by_linmap.get(&self.key().ok()?).copied()
             }
             None => None,
         }
     }
 
+    /// False if the ET doesn't belong or the rank overflows
     fn et_belongs(&self, et: &ETSlice) -> bool {
         let mut melody = self.mapping().clone();
         melody.insert(0, et.to_vec());
         let new_rt = StubTemperamentClass { melody };
-        self.rank() == new_rt.rank()
+        matches!(
+            (self.rank(), new_rt.rank()),
+            (Ok(rank), Ok(new_rank)) if rank == new_rank
+        )
     }
 
     fn generators_from_primes(&self, interval: &ETSlice) -> ETMap {
@@ -63,6 +97,11 @@ pub trait TemperamentClass {
         )
     }
 
+    /// Period and generators with sizes taken from the tuning map
+    fn mingen_form(&self, tuning_map: &[Cents]) -> Result<MingenForm, Error> {
+        Ok(mingen_form(&self.reduced_mapping()?, tuning_map))
+    }
+
     /// Fokker block as steps as integers, not pitches.
     /// This might not actually be a periodicity block
     /// because there's no check on n_pitches
@@ -72,22 +111,191 @@ pub trait TemperamentClass {
     }
 }
 
+/// Mapping with a period and generators chosen to be
+/// musically meaningful ("mingen" form).
+/// The period is an octave (or first harmonic) divided by the
+/// first entry of the reduced mapping.
+/// Each other generator is made as small as possible by adding
+/// periods and inverting it, so it ends up between zero and
+/// half a period.  With more than one generator, each one is
+/// also reduced by adding or taking away the earlier generators
+/// while that makes it smaller.
+#[derive(Clone, Debug, PartialEq)]
+pub struct MingenForm {
+    /// Rows for the period and then each generator
+    pub mapping: Mapping,
+    /// Sizes of the period and generators in cents
+    pub generators: Tuning,
+    /// Unimodular matrix with mapping = basis_change × reduced,
+    /// where reduced is the non-zero rows of the reduced mapping
+    pub basis_change: Mapping,
+}
+
+impl MingenForm {
+    /// Steps of the reduced mapping's generators
+    /// as steps of these generators
+    pub fn steps_from_reduced(&self, steps: &ETSlice) -> ETMap {
+        map(
+            |row| row.iter().zip(steps).map(|(&x, &y)| x * y).sum(),
+            &self.basis_change,
+        )
+    }
+
+    /// Sizes of these generators for a different tuning map
+    /// of the same temperament class
+    pub fn generator_sizes(&self, tuning_map: &[Cents]) -> Option<Tuning> {
+        let mapping =
+            DMatrix::from_fn(self.mapping.len(), tuning_map.len(), |i, j| {
+                self.mapping[i][j] as Cents
+            });
+        let tuning_map = DVector::from_column_slice(tuning_map);
+        let sizes = (&mapping * mapping.transpose())
+            .lu()
+            .solve(&(&mapping * tuning_map))?;
+        Some(sizes.iter().copied().collect())
+    }
+}
+
+/// Mingen form of a reduced (Hermite normal form) mapping
+/// with sizes from the tuning map
+pub fn mingen_form(reduced: &[ETMap], tuning_map: &[Cents]) -> MingenForm {
+    let mut mapping: Mapping = reduced
+        .iter()
+        .filter(|row| row.iter().any(|&x| x != 0))
+        .cloned()
+        .collect();
+    let rank = mapping.len();
+    let mut basis_change: Mapping = (0..rank)
+        .map(|i| (0..rank).map(|j| if i == j { 1 } else { 0 }).collect())
+        .collect();
+    // Sizes of the reduced mapping's generators
+    // by back substitution from the pivots
+    let mut generators: Tuning = Vec::with_capacity(rank);
+    for row in mapping.iter() {
+        let (pivot, &n) = row
+            .iter()
+            .enumerate()
+            .find(|&(_, &n)| n != 0)
+            .expect("Zero row in reduced mapping");
+        let known: Cents = generators
+            .iter()
+            .zip(&mapping)
+            .map(|(&g, other)| g * other[pivot] as Cents)
+            .sum();
+        generators.push((tuning_map[pivot] - known) / n as Cents);
+    }
+    if rank == 0 {
+        return MingenForm { mapping, generators, basis_change };
+    }
+    if generators[0] < 0.0 {
+        negate_row(&mut mapping, &mut basis_change, &mut generators, 0);
+    }
+    let period = generators[0];
+    // Size of a generator reduced by the period
+    let reduced_size = |size: Cents| {
+        let size = size.rem_euclid(period);
+        size.min(period - size)
+    };
+    // Only take real improvements so rounding errors can't loop
+    let tolerance = period.abs() * 1e-9;
+    for j in 1..rank {
+        reduce_by_period(&mut mapping, &mut basis_change, &mut generators, j);
+        let mut improved = true;
+        while improved {
+            improved = false;
+            for i in 1..j {
+                for sign in [1, -1] {
+                    let size = generators[j] - sign as Cents * generators[i];
+                    if reduced_size(size) < generators[j] - tolerance {
+                        // The new generator is the old one
+                        // minus sign × generator i
+                        add_rows(&mut mapping, &mut basis_change, i, j, sign);
+                        generators[j] = size;
+                        reduce_by_period(
+                            &mut mapping,
+                            &mut basis_change,
+                            &mut generators,
+                            j,
+                        );
+                        improved = true;
+                    }
+                }
+            }
+        }
+    }
+    MingenForm { mapping, generators, basis_change }
+}
+
+/// Make generator i (not the period) between zero and half a period
+fn reduce_by_period(
+    mapping: &mut Mapping,
+    basis_change: &mut Mapping,
+    generators: &mut Tuning,
+    i: usize,
+) {
+    let period = generators[0];
+    // Take away whole periods so 0 ≤ generator < period
+    let periods = (generators[i] / period).floor() as Exponent;
+    add_rows(mapping, basis_change, 0, i, periods);
+    generators[i] -= periods as Cents * period;
+    if generators[i] > period / 2.0 {
+        // The generator becomes the period minus the generator
+        add_rows(mapping, basis_change, 0, i, 1);
+        negate_row(mapping, basis_change, generators, i);
+        generators[i] += period;
+    }
+}
+
+/// Row target += multiple × row source for both matrices
+fn add_rows(
+    mapping: &mut Mapping,
+    basis_change: &mut Mapping,
+    target: usize,
+    source: usize,
+    multiple: Exponent,
+) {
+    for matrix in [mapping, basis_change] {
+        let source_row = matrix[source].clone();
+        for (x, y) in matrix[target].iter_mut().zip(source_row) {
+            *x += multiple * y;
+        }
+    }
+}
+
+fn negate_row(
+    mapping: &mut Mapping,
+    basis_change: &mut Mapping,
+    generators: &mut Tuning,
+    row: usize,
+) {
+    mapping[row] = map(|x| -x, &mapping[row]);
+    basis_change[row] = map(|x| -x, &basis_change[row]);
+    generators[row] = -generators[row];
+}
+
 /// Reverse engineer a key to get a mapping suitable for
 /// constructing a temperament class object
-pub fn key_to_mapping(n_primes: usize, key: &ETSlice) -> Option<Mapping> {
+pub fn key_to_mapping(
+    n_primes: usize,
+    key: &ETSlice,
+) -> Result<Mapping, Error> {
+    let bad_key = || Error::BadKey { dimension: n_primes, length: key.len() };
     let mut result = vec![];
     let mut remaining = key.to_vec();
     while !remaining.is_empty() {
+        if result.len() == n_primes {
+            return Err(bad_key());
+        }
         let mut new_vec = vec![];
         for _ in 0..(n_primes - result.len()) {
-            new_vec.insert(0, remaining.pop()?);
+            new_vec.insert(0, remaining.pop().ok_or_else(bad_key)?);
         }
         for _ in 0..result.len() {
             new_vec.insert(0, 0);
         }
         result.push(new_vec);
     }
-    Some(result)
+    Ok(result)
 }
 
 fn fokker_block(n_pitches: Exponent, octaves: ETMap) -> Mapping {
@@ -177,30 +385,39 @@ fn hermite() {
         vec![[1, 0, 0, -5, 12],
              [0, 1, 0, 2, -1],
              [0, 0, 1, 2, -3]];
-    assert_eq!(marvel.reduced_mapping(), marvel_hermite);
+    assert_eq!(marvel.reduced_mapping().unwrap(), marvel_hermite);
 
     let jove = make_jove();
     let jove_hermite = vec![[1, 1, 1, 2, 2],
                             [0, 2, 1, 1, 5],
                             [0, 0, 2, 1, 0]];
-    assert_eq!(jove.reduced_mapping(), jove_hermite);
+    assert_eq!(jove.reduced_mapping().unwrap(), jove_hermite);
 }
 
 #[rustfmt::skip]
 #[test]
 fn key() {
     assert_eq!(
-        make_marvel().key(),
+        make_marvel().key().unwrap(),
         vec![1, 2, -3,
           1, 0, 2, -1,
        1, 0, 0, -5, 12]
     );
 
-    assert_eq!(make_jove().key(), vec![2, 1, 0,
+    assert_eq!(make_jove().key().unwrap(), vec![2, 1, 0,
                                     2, 1, 1, 5,
                                  1, 1, 1, 2, 2]);
 }
 
+#[test]
+fn key_overflow() {
+    // The Hermite normal form needs the sign flipped
+    let rt = StubTemperamentClass { melody: vec![vec![Exponent::MIN, 0]] };
+    assert_eq!(rt.key(), Err(Error::Overflow));
+    assert_eq!(rt.rank(), Ok(1));
+    assert_eq!(rt.contorsion(), Ok(1 << 31));
+}
+
 #[test]
 fn meantone_name() {
     let limit5 = PrimeLimit::new(5);
@@ -269,37 +486,45 @@ fn marvel_has_72() {
 #[test]
 fn marvel_key_to_mapping() {
     let marvel = make_marvel();
-    let key = marvel.key();
-    let redmap = marvel.reduced_mapping();
+    let key = marvel.key().unwrap();
+    let redmap = marvel.reduced_mapping().unwrap();
     let dimension = redmap[0].len();
     let poss_mapping = key_to_mapping(dimension, &key);
-    assert_eq!(Some(redmap), poss_mapping);
+    assert_eq!(Ok(redmap), poss_mapping);
 }
 
 #[test]
 fn meantone_key_to_mapping() {
     let meantone = make_meantone();
-    let key = meantone.key();
-    let redmap = meantone.reduced_mapping();
+    let key = meantone.key().unwrap();
+    let redmap = meantone.reduced_mapping().unwrap();
     let dimension = redmap[0].len();
     let poss_mapping = key_to_mapping(dimension, &key);
-    assert_eq!(Some(redmap), poss_mapping);
+    assert_eq!(Ok(redmap), poss_mapping);
 }
 
 #[test]
 fn jove_key_to_mapping() {
     let jove = make_jove();
-    let key = jove.key();
-    let redmap = jove.reduced_mapping();
+    let key = jove.key().unwrap();
+    let redmap = jove.reduced_mapping().unwrap();
     let dimension = redmap[0].len();
     let poss_mapping = key_to_mapping(dimension, &key);
-    assert_eq!(Some(redmap), poss_mapping);
+    assert_eq!(Ok(redmap), poss_mapping);
+}
+
+#[test]
+fn bad_key_to_mapping() {
+    let bad_key = Error::BadKey { dimension: 3, length: 4 };
+    assert_eq!(Err(bad_key), key_to_mapping(3, &[1, 2, 3, 4]));
+    let bad_key = Error::BadKey { dimension: 2, length: 4 };
+    assert_eq!(Err(bad_key), key_to_mapping(2, &[1, 2, 3, 4]));
 }
 
 #[test]
 fn rank() {
-    assert_eq!(make_marvel().rank(), 3);
-    assert_eq!(make_jove().rank(), 3);
+    assert_eq!(make_marvel().rank(), Ok(3));
+    assert_eq!(make_jove().rank(), Ok(3));
 }
 
 #[test]
@@ -446,3 +671,142 @@ fn test_maximally_even() {
     }
     assert_eq!(maximally_even(0, 10, 11).len(), 0);
 }
+
+#[test]
+fn meantone_wedgie() {
+    assert_eq!(make_meantone().wedgie().unwrap(), vec![1, 4, 4]);
+}
+
+/// Every named temperament class comes back from its wedgie,
+/// apart from contorsion
+#[test]
+fn names_from_wedgies() {
+    use super::wedgie::{contorsion, mapping_from_wedgie};
+    for (headings, names) in NAMES_BY_LIMIT.iter() {
+        for key in names.keys() {
+            let melody = key_to_mapping(headings.len(), key)
+                .expect("Bad key in names table");
+            let rt = StubTemperamentClass { melody };
+            let mapping = mapping_from_wedgie(
+                headings.len(),
+                rt.rank().unwrap(),
+                &rt.wedgie().unwrap(),
+            )
+            .expect("No mapping from wedgie");
+            assert_eq!(
+                normalize(&wedge(&mapping).unwrap()),
+                rt.wedgie().unwrap()
+            );
+            if contorsion(&wedge(rt.mapping()).unwrap()) == 1 {
+                assert_eq!(mapping, rt.reduced_mapping().unwrap());
+            }
+        }
+    }
+}
+
+#[test]
+fn doubled_meantone() {
+    let doubled = StubTemperamentClass {
+        melody: vec![vec![24, 38, 56], vec![19, 30, 44]],
+    };
+    assert_eq!(doubled.contorsion().unwrap(), 2);
+    assert!(doubled.is_contorted().unwrap());
+    assert_eq!(
+        doubled.defactored_mapping().unwrap(),
+        vec![vec![1, 0, -4], vec![0, 1, 4]]
+    );
+    assert_eq!(doubled.wedgie().unwrap(), make_meantone().wedgie().unwrap());
+    assert!(!make_meantone().is_contorted().unwrap());
+    assert_eq!(
+        make_marvel().defactored_mapping().unwrap(),
+        make_marvel().reduced_mapping().unwrap()
+    );
+}
+
+#[test]
+fn meantone_mingen() {
+    // Quarter-comma meantone
+    let tuning_map = vec![1200.0, 1896.578, 2786.314];
+    let mingen = make_meantone().mingen_form(&tuning_map).unwrap();
+    assert_eq!(mingen.mapping, vec![vec![1, 2, 4], vec![0, -1, -4]]);
+    assert_eq!(mingen.generators[0], 1200.0);
+    super::assert_between!(503.42, mingen.generators[1], 503.43);
+    assert_eq!(mingen.basis_change, vec![vec![1, 2], vec![0, -1]]);
+    // A fifth is a period minus a generator
+    let reduced = StubTemperamentClass {
+        melody: make_meantone().reduced_mapping().unwrap(),
+    };
+    let fifth = reduced.generators_from_primes(&[-1, 1, 0]);
+    assert_eq!(mingen.steps_from_reduced(&fifth), vec![1, -1]);
+    // Pythagorean tuning with the same generators
+    let sizes = mingen
+        .generator_sizes(&[1200.0, 1901.955, 2807.82])
+        .expect("no generator sizes");
+    super::assert_between!(1199.999, sizes[0], 1200.001);
+    super::assert_between!(498.044, sizes[1], 498.046);
+}
+
+#[test]
+fn ji_mingen() {
+    // Rank 3: 5:4 gets reduced by 4:3 to 16:15
+    let ji = StubTemperamentClass {
+        melody: vec![vec![1, 0, 0], vec![0, 1, 0], vec![0, 0, 1]],
+    };
+    let limit5 = PrimeLimit::new(5);
+    let mingen = ji.mingen_form(&limit5.pitches).unwrap();
+    assert_eq!(
+        mingen.mapping,
+        vec![vec![1, 2, 2], vec![0, -1, 1], vec![0, 0, -1]]
+    );
+    assert_eq!(mingen.generators[0], 1200.0);
+    super::assert_between!(498.044, mingen.generators[1], 498.046);
+    super::assert_between!(111.730, mingen.generators[2], 111.732);
+    assert_eq!(mingen.basis_change, mingen.mapping);
+}
+
+#[test]
+fn pajara_mingen() {
+    // 22-EDO
+    let tuning_map: Vec<Cents> =
+        [22.0, 35.0, 51.0, 62.0].iter().map(|x| x * 1200.0 / 22.0).collect();
+    let pajara = StubTemperamentClass {
+        melody: vec![vec![22, 35, 51, 62], vec![12, 19, 28, 34]],
+    };
+    let mingen = pajara.mingen_form(&tuning_map).unwrap();
+    assert_eq!(mingen.generators[0], 600.0);
+    super::assert_between!(109.09, mingen.generators[1], 109.10);
+    for (prime, &size) in tuning_map.iter().enumerate() {
+        let tempered: Cents = mingen
+            .mapping
+            .iter()
+            .zip(&mingen.generators)
+            .map(|(row, g)| row[prime] as Cents * g)
+            .sum();
+        super::assert_between!(size - 1e-6, tempered, size + 1e-6);
+    }
+}
+
+#[test]
+fn marvel_mingen() {
+    let tuning_map = vec![1200.0, 1901.0, 2785.0, 3369.0, 4151.0];
+    let mingen = make_marvel().mingen_form(&tuning_map).unwrap();
+    assert_eq!(mingen.mapping.len(), 3);
+    assert_eq!(mingen.generators[0], 1200.0);
+    assert!(
+        mingen.generators[1..].iter().all(|&g| (0.0..=600.0).contains(&g))
+    );
+    assert_eq!(
+        super::hermite_normal_form(&mingen.mapping),
+        make_marvel().reduced_mapping().unwrap()
+    );
+    // 4:3 and 16:15 rather than 4:3 and 5:4
+    assert_eq!(mingen.generators, vec![1200.0, 499.0, 114.0]);
+    assert_eq!(
+        mingen.mapping,
+        vec![
+            vec![1, 2, 2, 3, 4],
+            vec![0, -1, 1, 0, -2],
+            vec![0, 0, -1, -2, 3]
+        ]
+    );
+}
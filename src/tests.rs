@@ -40,7 +40,7 @@ fn non_consecutive_limit_from_str() {
 fn bad_limit_from_str() {
     let label = "foo";
     let limit: Result<PrimeLimit, _> = label.parse();
-    assert!(limit.is_err());
+    assert_eq!(limit.err(), Some(Error::BadLimit("foo".to_string())));
 }
 
 #[test]
@@ -138,9 +138,9 @@ fn name_12p() {
     let et = prime_mapping(&limit.pitches, 12);
     let name = warted_et_name(&limit, &et);
     assert_eq!(name, "12p");
-    assert_eq!(Some(et.clone()), et_from_name(&limit, &name));
+    assert_eq!(Ok(et.clone()), et_from_name(&limit, &name));
     // Check that the same result comes back without the "p"
-    assert_eq!(Some(et), et_from_name(&limit, "12"));
+    assert_eq!(Ok(et), et_from_name(&limit, "12"));
 }
 
 #[test]
@@ -149,7 +149,7 @@ fn name_38de() {
     let et = vec![38, 60, 88, 106, 132];
     let name = warted_et_name(&limit, &et);
     assert_eq!(name, "38de");
-    assert_eq!(Some(et), et_from_name(&limit, &name));
+    assert_eq!(Ok(et), et_from_name(&limit, &name));
 }
 
 #[test]
@@ -158,7 +158,7 @@ fn name_b4() {
     let et = vec![4, 6, 7];
     let name = warted_et_name(&limit, &et);
     assert_eq!(name, "b4p");
-    assert_eq!(Some(et), et_from_name(&limit, &name));
+    assert_eq!(Ok(et), et_from_name(&limit, &name));
 }
 
 #[test]
@@ -167,7 +167,7 @@ fn name_q15e() {
     let et = vec![15, 15, 16];
     let name = warted_et_name(&limit, &et);
     assert_eq!(name, "q15e");
-    assert_eq!(Some(et), et_from_name(&limit, &name));
+    assert_eq!(Ok(et), et_from_name(&limit, &name));
 }
 
 #[test]
@@ -176,7 +176,7 @@ fn name_q32r() {
     let et = vec![32, 33, 34];
     let name = warted_et_name(&limit, &et);
     assert_eq!(name, "q32r");
-    assert_eq!(Some(et), et_from_name(&limit, &name));
+    assert_eq!(Ok(et), et_from_name(&limit, &name));
 }
 
 #[test]
@@ -185,7 +185,7 @@ fn name_q22() {
     let et = vec![22, 23, 24];
     let name = warted_et_name(&limit, &et);
     assert_eq!(name, "q22p");
-    assert_eq!(Some(et), et_from_name(&limit, &name));
+    assert_eq!(Ok(et), et_from_name(&limit, &name));
 }
 
 #[test]
@@ -194,7 +194,7 @@ fn name_q11() {
     let et = vec![11, 12, 13, 14];
     let name = warted_et_name(&limit, &et);
     assert_eq!(name, "q11p");
-    assert_eq!(Some(et), et_from_name(&limit, &name));
+    assert_eq!(Ok(et), et_from_name(&limit, &name));
 }
 
 #[test]
@@ -203,7 +203,7 @@ fn name_q2t() {
     let et = vec![2, 2, 2, 2]; // contorted
     let name = warted_et_name(&limit, &et);
     assert_eq!(name, "q2t");
-    assert_eq!(Some(et), et_from_name(&limit, &name));
+    assert_eq!(Ok(et), et_from_name(&limit, &name));
 }
 
 #[test]
@@ -212,7 +212,7 @@ fn name_8dee() {
     let et = vec![8, 13, 19, 23, 29];
     let name = warted_et_name(&limit, &et);
     assert_eq!(name, "8dee");
-    assert_eq!(Some(et), et_from_name(&limit, &name));
+    assert_eq!(Ok(et), et_from_name(&limit, &name));
 }
 
 #[test]
@@ -221,7 +221,7 @@ fn name_2egg() {
     let et = vec![2, 3, 5, 6, 6, 7, 7];
     let name = warted_et_name(&limit, &et);
     assert_eq!(name, "2egg");
-    assert_eq!(Some(et), et_from_name(&limit, &name));
+    assert_eq!(Ok(et), et_from_name(&limit, &name));
 }
 
 #[test]
@@ -230,7 +230,7 @@ fn name_4ef() {
     let et = vec![4, 6, 9, 11, 13, 14, 16];
     let name = warted_et_name(&limit, &et);
     assert_eq!(name, "4ef");
-    assert_eq!(Some(et), et_from_name(&limit, &name));
+    assert_eq!(Ok(et), et_from_name(&limit, &name));
 }
 
 #[test]
@@ -239,7 +239,7 @@ fn name_4efgg() {
     let et = vec![4, 6, 9, 11, 13, 14, 15];
     let name = warted_et_name(&limit, &et);
     assert_eq!(name, "4efgg");
-    assert_eq!(Some(et), et_from_name(&limit, &name));
+    assert_eq!(Ok(et), et_from_name(&limit, &name));
 }
 
 #[test]
@@ -248,7 +248,7 @@ fn name_4efgggg() {
     let et = vec![4, 6, 9, 11, 13, 14, 14];
     let name = warted_et_name(&limit, &et);
     assert_eq!(name, "4efgggg");
-    assert_eq!(Some(et), et_from_name(&limit, &name));
+    assert_eq!(Ok(et), et_from_name(&limit, &name));
 }
 
 #[test]
@@ -257,7 +257,7 @@ fn name_4efg() {
     let et = vec![4, 6, 9, 11, 13, 14, 17];
     let name = warted_et_name(&limit, &et);
     assert_eq!(name, "4efg");
-    assert_eq!(Some(et), et_from_name(&limit, &name));
+    assert_eq!(Ok(et), et_from_name(&limit, &name));
 }
 
 #[test]
@@ -266,18 +266,18 @@ fn name_4efggg() {
     let et = vec![4, 6, 9, 11, 13, 14, 18];
     let name = warted_et_name(&limit, &et);
     assert_eq!(name, "4efggg");
-    assert_eq!(Some(et), et_from_name(&limit, &name));
+    assert_eq!(Ok(et), et_from_name(&limit, &name));
 }
 
 #[test]
 fn rt12_from_name() {
     let limit = PrimeLimit::new(7);
     assert_eq!(
-        Some(vec![vec![12, 19, 28, 34]]),
+        Ok(vec![vec![12, 19, 28, 34]]),
         mapping_from_name(&limit, "12p"),
     );
     assert_eq!(
-        Some(vec![vec![12, 19, 28, 34]]),
+        Ok(vec![vec![12, 19, 28, 34]]),
         mapping_from_name(&limit, "12"),
     );
 }
@@ -286,14 +286,14 @@ fn rt12_from_name() {
 fn meantone_from_name() {
     let limit = PrimeLimit::new(7);
     let expected = vec![vec![12, 19, 28, 34], vec![19, 30, 44, 53]];
-    assert_eq!(Some(expected.clone()), mapping_from_name(&limit, "12 & 19"));
+    assert_eq!(Ok(expected.clone()), mapping_from_name(&limit, "12 & 19"));
     // The & is optional
-    assert_eq!(Some(expected.clone()), mapping_from_name(&limit, "12 19"));
-    assert_eq!(Some(expected.clone()), mapping_from_name(&limit, "12 + 19"));
+    assert_eq!(Ok(expected.clone()), mapping_from_name(&limit, "12 19"));
+    assert_eq!(Ok(expected.clone()), mapping_from_name(&limit, "12 + 19"));
     // + is also supported
     // Extra whitespace should be ignored
     assert_eq!(
-        Some(expected.clone()),
+        Ok(expected.clone()),
         mapping_from_name(&limit, "   12  &  19  &&&& "),
     );
 }
@@ -301,7 +301,14 @@ fn meantone_from_name() {
 #[test]
 fn bad_rt_from_name() {
     let limit = PrimeLimit::new(7);
-    assert_eq!(None, mapping_from_name(&limit, "bad name"));
+    assert_eq!(
+        Err(Error::BadETName("bad".to_string())),
+        mapping_from_name(&limit, "bad name"),
+    );
+    assert_eq!(
+        Err(Error::UnknownWart('e')),
+        mapping_from_name(&limit, "12 & 19e"),
+    );
 }
 
 /// Test a case where a Chinese character is used for the wart
@@ -314,7 +321,7 @@ fn name_chinese_wart() {
     ];
     let name = warted_et_name(&limit, &et);
     assert_eq!(name, "62rsvw一");
-    assert_eq!(Some(et), et_from_name(&limit, &name));
+    assert_eq!(Ok(et), et_from_name(&limit, &name));
 }
 
 #[test]
@@ -366,6 +373,33 @@ fn hermite_reduction() {
     assert_eq!(redundant_normal[2], vec![0, 0, 0, 0]);
 }
 
+#[test]
+fn hermite_overflow() {
+    // Small numbers that get too big for Exponents on the way
+    let matrix = vec![
+        vec![8, 16, 25, 0],
+        vec![5, -29, 16, -7],
+        vec![-13, 13, -30, 11],
+        vec![-16, -3, -13, -5],
+    ];
+    assert_eq!(
+        super::narrow_hermite_normal_form(&matrix),
+        Err(Error::Overflow)
+    );
+    let expected = vec![
+        vec![1, 0, 0, 56336],
+        vec![0, 1, 0, 67743],
+        vec![0, 0, 1, 59308],
+        vec![0, 0, 0, 81548],
+    ];
+    assert_eq!(hermite_normal_form(&matrix), expected);
+    // The result itself doesn't fit
+    assert_eq!(
+        super::checked_hermite_normal_form(&[vec![Exponent::MIN, 0]]),
+        Err(Error::Overflow)
+    );
+}
+
 #[test]
 fn normalize_already_positive() {
     let limit5 = PrimeLimit::new(5);
@@ -1,12 +1,26 @@
-use microlp::{ComparisonOp, Error, OptimizationDirection, Problem};
+extern crate nalgebra as na;
+use microlp::{ComparisonOp, OptimizationDirection, Problem};
+use na::{DMatrix, DVector};
 
 use super::cangwu::TenneyWeighted;
 use super::temperament_class::TemperamentClass;
 use super::tuned_temperament::TunedTemperament;
-use super::{Cents, ETMap, Mapping, Tuning};
+use super::{Cents, ETMap, Error, Mapping, Tuning};
+use std::borrow::Cow;
 
+/// The tolerance microlp works to
+pub(crate) const SOLVER_EPSILON: f64 = 1e-10;
+
+/// Differences in weighted errors smaller than this are ignored.
+/// It's well above the solver's own tolerance so that
+/// rounding errors don't stop primes from looking stuck.
+pub(crate) const TOLERANCE: f64 = 1e4 * SOLVER_EPSILON;
+
+/// Tenney OPtimal (minimax) tuned temperament.
+/// TOPTemperament<'static> owns its prime limit.
+#[derive(Clone, Debug)]
 pub struct TOPTemperament<'a> {
-    plimit: &'a [Cents],
+    plimit: Cow<'a, [Cents]>,
     pub melody: Mapping,
     pub tuning: Tuning,
 }
@@ -19,7 +33,7 @@ impl TemperamentClass for TOPTemperament<'_> {
 
 impl TunedTemperament for TOPTemperament<'_> {
     fn plimit(&self) -> &[Cents] {
-        self.plimit
+        &self.plimit
     }
 
     fn tuning(&self) -> &Tuning {
@@ -33,16 +47,19 @@ impl TenneyWeighted for TOPTemperament<'_> {
     }
 
     fn plimit(&self) -> &[Cents] {
-        self.plimit
+        &self.plimit
     }
 }
 
 impl<'a> TOPTemperament<'a> {
     /// Upgrade vectors into a struct of nalgebra objects
-    pub fn new(plimit: &'a [Cents], melody: &[ETMap]) -> Result<Self, Error> {
+    pub fn new(
+        plimit: impl Into<Cow<'a, [Cents]>>,
+        melody: &[ETMap],
+    ) -> Result<Self, Error> {
         let melody = melody.to_vec();
         let mut rt = TOPTemperament {
-            plimit,
+            plimit: plimit.into(),
             melody: melody.to_vec(),
             tuning: vec![0.0],
         };
@@ -50,6 +67,30 @@ impl<'a> TOPTemperament<'a> {
         Ok(rt)
     }
 
+    /// The same temperament class with a different tuning,
+    /// sharing the prime limit
+    pub fn with_tuning(&self, tuning: Tuning) -> TOPTemperament<'_> {
+        TOPTemperament {
+            plimit: Cow::Borrowed(&self.plimit),
+            melody: self.melody.clone(),
+            tuning,
+        }
+    }
+
+    /// Copy that borrows the prime limit from this one
+    pub fn borrowed(&self) -> TOPTemperament<'_> {
+        self.with_tuning(self.tuning.clone())
+    }
+
+    /// Take ownership of the prime limit to lose the lifetime
+    pub fn into_owned(self) -> TOPTemperament<'static> {
+        TOPTemperament {
+            plimit: Cow::Owned(self.plimit.into_owned()),
+            melody: self.melody,
+            tuning: self.tuning,
+        }
+    }
+
     pub fn error(&self) -> Cents {
         // no .max() for f64
         self.weighted_tuning_map()
@@ -69,35 +110,177 @@ impl<'a> TOPTemperament<'a> {
         (max - min) / (max + min) * 12e2
     }
 
+    /// TIPTOP tuning: the minimax (TOP) error,
+    /// then the minimax error of the primes that aren't stuck
+    /// at that error, and so on until the tuning is unique
     pub fn optimize(&mut self) -> Result<(), Error> {
-        let mut problem = Problem::new(OptimizationDirection::Minimize);
-        let error = problem.add_var(1.0, (0.0, f64::INFINITY));
-        let mut vars = Vec::new();
-        for _ in &self.melody {
-            vars.push(problem.add_var(0.0, (0.0, f64::INFINITY)));
+        let rank = self.melody.len();
+        // Primes with a fixed tuning, as (index, tuned size)
+        let mut fixed: Vec<(usize, Cents)> = Vec::new();
+        // A subset of the fixed primes that are independent
+        let mut constraints: Vec<(usize, Cents)> = Vec::new();
+        loop {
+            let free: Vec<usize> = (0..self.plimit.len())
+                .filter(|i| fixed.iter().all(|(j, _)| i != j))
+                .collect();
+            let (error, tuning) =
+                self.solve_level(&constraints, &free, None)?;
+            self.tuning = tuning;
+            let weighted = self.weighted_tuning_map();
+            // Ranges of tunings at this error, as (index, lowest, highest)
+            let mut ranges = Vec::new();
+            for &i in &free {
+                // Only primes at the worst error can be stuck there
+                if ((weighted[i] - 1.0).abs() - error).abs() > TOLERANCE {
+                    continue;
+                }
+                let (_, lowest) = self.solve_level(
+                    &constraints,
+                    &free,
+                    Some((i, OptimizationDirection::Minimize, error)),
+                )?;
+                let (_, highest) = self.solve_level(
+                    &constraints,
+                    &free,
+                    Some((i, OptimizationDirection::Maximize, error)),
+                )?;
+                ranges.push((
+                    i,
+                    self.tuned_prime(&lowest, i),
+                    self.tuned_prime(&highest, i),
+                ));
+            }
+            for prime in stuck_primes(&self.plimit, &ranges)? {
+                fixed.push(prime);
+                let mut extended = constraints.clone();
+                extended.push(prime);
+                if self.fixed_rank(&extended) > constraints.len() {
+                    constraints = extended;
+                }
+            }
+            if constraints.len() == rank {
+                break;
+            }
+            if fixed.len() == self.plimit.len() {
+                // The mapping must be rank deficient
+                return Ok(());
+            }
         }
-        for (i, &p) in self.plimit.iter().enumerate() {
-            let tuned_prime: Vec<_> = self
-                .melody
+        let primes = DMatrix::from_fn(rank, rank, |i, j| {
+            self.melody[j][constraints[i].0] as f64
+        });
+        let sizes = DVector::from_iterator(
+            rank,
+            constraints.iter().map(|&(_, size)| size),
+        );
+        let tuning = primes.lu().solve(&sizes).expect("no unique tuning");
+        self.tuning = tuning.iter().cloned().collect();
+        Ok(())
+    }
+
+    /// Solve the linear program for one level of the nested minimax
+    /// with the constrained primes fixed and the free primes
+    /// counting towards the error.  With a target,
+    /// the error is held to the given value and the target prime
+    /// tuned as flat or sharp as possible.
+    fn solve_level(
+        &self,
+        constraints: &[(usize, Cents)],
+        free: &[usize],
+        target: Option<(usize, OptimizationDirection, f64)>,
+    ) -> Result<(f64, Tuning), Error> {
+        let (direction, error_bounds) = match target {
+            Some((_, direction, max_error)) => {
+                (direction, (0.0, max_error + 10.0 * SOLVER_EPSILON))
+            }
+            None => (OptimizationDirection::Minimize, (0.0, f64::INFINITY)),
+        };
+        let mut problem = Problem::new(direction);
+        let error_cost = if target.is_some() { 0.0 } else { 1.0 };
+        let error = problem.add_var(error_cost, error_bounds);
+        let vars: Vec<_> = self
+            .melody
+            .iter()
+            .map(|m| {
+                let cost = match target {
+                    Some((i, _, _)) => m[i] as f64,
+                    None => 0.0,
+                };
+                problem.add_var(cost, (f64::NEG_INFINITY, f64::INFINITY))
+            })
+            .collect();
+        let tuned_prime = |i: usize| -> Vec<_> {
+            self.melody
                 .iter()
                 .zip(vars.iter())
                 .map(|(m, &v)| (v, m[i] as f64))
-                .collect();
+                .collect()
+        };
+        for &i in free {
+            let p = self.plimit[i];
             // error >= tuned_prime/p - 1 and error >= 1 - tuned_prime/p
             // error*p >= tuned_prime - p and error*p >= p - tuned_prime
             // error*p - tuned_prime >= -p and error*p + tuned_prime >= p
             // tuned_prime - error*p <= p and error*p + tuned_prime >= p
-            let mut constraint = tuned_prime.clone();
+            let mut constraint = tuned_prime(i);
             constraint.push((error, -p));
             problem.add_constraint(&constraint, ComparisonOp::Le, p);
             constraint.pop();
             constraint.push((error, p));
             problem.add_constraint(&constraint, ComparisonOp::Ge, p);
         }
+        for &(i, size) in constraints {
+            problem.add_constraint(tuned_prime(i), ComparisonOp::Eq, size);
+        }
         let solution = problem.solve()?;
-        self.tuning = vars.iter().map(|&v| solution[v]).collect();
-        Ok(())
+        let tuning = vars.iter().map(|&v| solution[v]).collect();
+        Ok((solution[error], tuning))
     }
+
+    fn tuned_prime(&self, tuning: &[Cents], i: usize) -> Cents {
+        self.melody.iter().zip(tuning).map(|(m, &x)| m[i] as f64 * x).sum()
+    }
+
+    /// How many independent primes are in this list
+    fn fixed_rank(&self, primes: &[(usize, Cents)]) -> usize {
+        let mapping =
+            DMatrix::from_fn(primes.len(), self.melody.len(), |i, j| {
+                self.melody[j][primes[i].0] as f64
+            });
+        mapping.rank(1e-9)
+    }
+}
+
+/// The primes with tunings fixed at this level of the minimax,
+/// as (index, tuned size), from the ranges of tunings
+/// the primes at the worst error can take.
+/// There's always at least one, so if rounding errors hide it,
+/// the prime with the narrowest range (relative to its size)
+/// gets fixed in the middle of that range.
+/// Diamond minimax tunings use it with intervals in place of primes.
+pub(crate) fn stuck_primes(
+    plimit: &[Cents],
+    ranges: &[(usize, Cents, Cents)],
+) -> Result<Vec<(usize, Cents)>, Error> {
+    let width = |&(i, lowest, highest): &(usize, Cents, Cents)| {
+        (highest - lowest) / plimit[i]
+    };
+    let middle = |&(i, lowest, highest): &(usize, Cents, Cents)| {
+        (i, (lowest + highest) / 2.0)
+    };
+    let stuck: Vec<_> = ranges
+        .iter()
+        .filter(|range| width(range) < TOLERANCE)
+        .map(middle)
+        .collect();
+    if !stuck.is_empty() {
+        return Ok(stuck);
+    }
+    let narrowest = ranges
+        .iter()
+        .min_by(|a, b| width(a).total_cmp(&width(b)))
+        .ok_or(Error::NestedMinimax)?;
+    Ok(vec![middle(narrowest)])
 }
 
 #[cfg(test)]
@@ -218,3 +401,67 @@ fn generators() {
     let twotoe = marvel.generators_from_primes(&vec![3, 0, 0, -1, 0]);
     assert_eq!(twotoe, vec![4, 6, 8]);
 }
+
+#[test]
+fn owned_meantone() {
+    let meantone = {
+        let limit5 = super::PrimeLimit::new(5);
+        make_meantone(&limit5).into_owned()
+    };
+    super::assert_between!(6.07, meantone.tuning[0], 6.08);
+    super::assert_between!(35.03, meantone.borrowed().tuning[1], 35.04);
+}
+
+#[test]
+fn tiptop() {
+    // 7-limit augmented has a range of TOP tunings for 3 and 7
+    let limit7 = super::PrimeLimit::new(7);
+    let tuning_map = |a, b| {
+        let ets = vec![
+            super::prime_mapping(&limit7.pitches, a),
+            super::prime_mapping(&limit7.pitches, b),
+        ];
+        TOPTemperament::new(&limit7.pitches, &ets)
+            .expect("no TOP tuning")
+            .tuning_map()
+    };
+    let expected = tuning_map(12, 15);
+    for (a, b) in [(12, 27), (15, 27)] {
+        for (x, y) in tuning_map(a, b).iter().zip(&expected) {
+            super::assert_between!(y - 1e-6, *x, y + 1e-6);
+        }
+    }
+    let rt = TOPTemperament::new(
+        &limit7.pitches,
+        &[vec![12, 19, 28, 34], vec![15, 24, 35, 42]],
+    )
+    .expect("no TOP tuning");
+    super::assert_between!(2.93, rt.error(), 2.95);
+    super::assert_between!(1197.06, expected[0], 1197.07);
+    // The second level of the minimax balances 3 and 7
+    let weighted = rt.weighted_tuning_map();
+    super::assert_between!(
+        weighted[1] - 1e-9,
+        weighted[3],
+        weighted[1] + 1e-9
+    );
+    super::assert_between!(1.0013, weighted[1], 1.0014);
+}
+
+#[test]
+fn stuck_prime_rounding() {
+    let limit5 = super::PrimeLimit::new(5);
+    let ranges =
+        [(0, 1200.0, 1200.1), (1, 1901.9, 1902.0), (2, 2786.0, 2787.0)];
+    // Nothing's quite stuck, so the narrowest range wins
+    let stuck = stuck_primes(&limit5.pitches, &ranges).expect("no prime");
+    assert_eq!(stuck.len(), 1);
+    assert_eq!(stuck[0].0, 1);
+    super::assert_between!(1901.94, stuck[0].1, 1901.96);
+    // Anything that is stuck is used as it is
+    let ranges = [(0, 1200.0, 1200.1), (2, 2786.0, 2786.0 + 1e-6)];
+    let stuck = stuck_primes(&limit5.pitches, &ranges).expect("no prime");
+    assert_eq!(stuck.len(), 1);
+    assert_eq!(stuck[0].0, 2);
+    assert_eq!(stuck_primes(&limit5.pitches, &[]), Err(Error::NestedMinimax));
+}
@@ -0,0 +1,362 @@
+//! Tunings that minimize the Lp norm of the weighted mistunings
+//!
+//! TE is the p = 2 case and TOP the limit as p goes to infinity.
+//! The Tp tuning is the one that keeps the worst error of any interval
+//! smallest in proportion to its complexity in the dual norm
+//! (q with 1/p + 1/q = 1).  Complexity of the temperament class
+//! comes from the same p-norm as the error, so that Tp error
+//! times complexity agrees with TE badness for p = 2.
+//!
+//! The tuning is found by Newton's method on a smoothed version
+//! of the norm, starting from the TE tuning and moving p
+//! towards the one asked for a step at a time.  The smoothing
+//! is taken away in stages so that p = 1 still converges.
+
+extern crate nalgebra as na;
+use na::{DMatrix, DVector};
+
+use super::cangwu::TenneyWeighted;
+use super::temperament_class::TemperamentClass;
+use super::tuned_temperament::TunedTemperament;
+use super::wedgie::combinations;
+use super::weighting::Weighting;
+use super::{Cents, ETMap, Error, Mapping, Tuning};
+use std::borrow::Cow;
+
+/// Smoothing for the first stage of the solver,
+/// relative to the worst TE error
+const FIRST_SMOOTHING: f64 = 1e-1;
+
+/// Smoothing for the last stage
+const LAST_SMOOTHING: f64 = 1e-9;
+
+/// How much p changes between starting points for Newton's method
+const P_STEP: f64 = 1.5;
+
+const MAX_NEWTON_STEPS: usize = 100;
+
+/// Lp-norm optimal tuned temperament.
+/// TpTemperament<'static> owns its prime limit.
+#[derive(Clone, Debug)]
+pub struct TpTemperament<'a> {
+    plimit: Cow<'a, [Cents]>,
+    pub melody: Mapping,
+    pub tuning: Tuning,
+    pub weighting: Weighting,
+    /// The p of the Lp norm, at least 1 and finite
+    pub norm: f64,
+}
+
+impl TemperamentClass for TpTemperament<'_> {
+    fn mapping(&self) -> &Mapping {
+        &self.melody
+    }
+}
+
+impl TunedTemperament for TpTemperament<'_> {
+    fn plimit(&self) -> &[Cents] {
+        &self.plimit
+    }
+
+    fn tuning(&self) -> &Tuning {
+        &self.tuning
+    }
+}
+
+impl TenneyWeighted for TpTemperament<'_> {
+    fn mapping(&self) -> &Mapping {
+        &self.melody
+    }
+
+    fn plimit(&self) -> &[Cents] {
+        &self.plimit
+    }
+
+    fn weighting(&self) -> &Weighting {
+        &self.weighting
+    }
+}
+
+impl<'a> TpTemperament<'a> {
+    /// Tenney weighted tuning for the p-norm
+    pub fn new(
+        plimit: impl Into<Cow<'a, [Cents]>>,
+        melody: &[ETMap],
+        norm: f64,
+    ) -> Result<Self, Error> {
+        Self::weighted(plimit, melody, norm, Weighting::Tenney)
+    }
+
+    /// Optimal tuning for the p-norm with a weighting other than Tenney,
+    /// which also goes into the error and complexity
+    pub fn weighted(
+        plimit: impl Into<Cow<'a, [Cents]>>,
+        melody: &[ETMap],
+        norm: f64,
+        weighting: Weighting,
+    ) -> Result<Self, Error> {
+        if !(norm.is_finite() && norm >= 1.0) {
+            return Err(Error::BadNorm(norm));
+        }
+        let plimit = plimit.into();
+        weighting.check(&plimit)?;
+        let mut rt = TpTemperament {
+            plimit,
+            melody: melody.to_vec(),
+            tuning: vec![],
+            weighting,
+            norm,
+        };
+        rt.optimize();
+        Ok(rt)
+    }
+
+    /// The same temperament class with a different tuning,
+    /// sharing the prime limit
+    pub fn with_tuning(&self, tuning: Tuning) -> TpTemperament<'_> {
+        TpTemperament {
+            plimit: Cow::Borrowed(&self.plimit),
+            melody: self.melody.clone(),
+            tuning,
+            weighting: self.weighting.clone(),
+            norm: self.norm,
+        }
+    }
+
+    /// Copy that borrows the prime limit from this one
+    pub fn borrowed(&self) -> TpTemperament<'_> {
+        self.with_tuning(self.tuning.clone())
+    }
+
+    /// Take ownership of the prime limit to lose the lifetime
+    pub fn into_owned(self) -> TpTemperament<'static> {
+        TpTemperament {
+            plimit: Cow::Owned(self.plimit.into_owned()),
+            melody: self.melody,
+            tuning: self.tuning,
+            weighting: self.weighting,
+            norm: self.norm,
+        }
+    }
+
+    /// The q for the norm of intervals that this tuning is optimal for
+    pub fn dual_norm(&self) -> f64 {
+        if self.norm == 1.0 {
+            f64::INFINITY
+        } else {
+            self.norm / (self.norm - 1.0)
+        }
+    }
+
+    /// Power mean of the weighted mistunings:
+    /// the TE error for p = 2
+    pub fn error(&self) -> Cents {
+        let residuals = self.weighted_mistunings(self.tuning.as_slice());
+        power_mean(residuals.iter().copied(), self.norm)
+    }
+
+    /// Power mean of the weighted wedgie,
+    /// the same as TE complexity for p = 2
+    pub fn complexity(&self) -> f64 {
+        let wmap = self.weighted_mapping();
+        let rank = wmap.ncols();
+        let minors =
+            combinations(wmap.nrows(), rank).into_iter().map(|rows| {
+                DMatrix::from_fn(rank, rank, |i, j| wmap[(rows[i], j)])
+                    .lu()
+                    .determinant()
+            });
+        // The normalization makes the mean over all the minors
+        // agree with rms_of_matrix for p = 2
+        let n_minors = combinations(wmap.nrows(), rank).len() as f64;
+        let dimension = wmap.nrows() as f64;
+        let scale =
+            (n_minors / dimension.powi(rank as i32)).powf(1.0 / self.norm);
+        power_mean(minors, self.norm) * scale
+    }
+
+    /// Weighted errors of the primes (or whatever the weighting uses)
+    fn weighted_mistunings(&self, tuning: &[Cents]) -> DVector<f64> {
+        let wmap = self.weighted_mapping();
+        let ji = self.weighting.weighted_ji(&self.plimit);
+        wmap * DVector::from_column_slice(tuning) - ji
+    }
+
+    fn optimize(&mut self) {
+        let wmap = self.weighted_mapping();
+        let ji = self.weighting.weighted_ji(&self.plimit);
+        let pinv =
+            wmap.clone().pseudo_inverse(0.0).expect("no pseudoinverse");
+        let mut tuning = pinv * &ji;
+        let scale = (&wmap * &tuning - &ji).amax();
+        if self.norm != 2.0 && scale > 0.0 {
+            // Work with errors around 1 so that big p doesn't overflow
+            let wmap = wmap / scale;
+            let ji = ji / scale;
+            // Newton's method only converges from close enough,
+            // so work out from p = 2 a bit at a time
+            let mut p = 2.0;
+            while p != self.norm {
+                p = if self.norm > p {
+                    (p * P_STEP).min(self.norm)
+                } else {
+                    (p / P_STEP).max(self.norm)
+                };
+                tuning =
+                    newton_minimize(&wmap, &ji, p, FIRST_SMOOTHING, tuning);
+            }
+            let mut smoothing = FIRST_SMOOTHING / 10.0;
+            while smoothing >= LAST_SMOOTHING {
+                tuning =
+                    newton_minimize(&wmap, &ji, self.norm, smoothing, tuning);
+                smoothing /= 10.0;
+            }
+        }
+        self.tuning = tuning.iter().cloned().collect();
+    }
+}
+
+/// The x that minimizes the sum of (r² + s²)^(p/2)
+/// where r is each element of a·x - b and s the smoothing
+fn newton_minimize(
+    a: &DMatrix<f64>,
+    b: &DVector<f64>,
+    p: f64,
+    smoothing: f64,
+    mut x: DVector<f64>,
+) -> DVector<f64> {
+    let s2 = smoothing * smoothing;
+    let objective = |x: &DVector<f64>| -> f64 {
+        (a * x - b).iter().map(|r| (r * r + s2).powf(p / 2.0)).sum()
+    };
+    let mut value = objective(&x);
+    for _ in 0..MAX_NEWTON_STEPS {
+        let residuals = a * &x - b;
+        let first: DVector<f64> =
+            residuals.map(|r| p * (r * r + s2).powf(p / 2.0 - 1.0) * r);
+        let second: DVector<f64> = residuals.map(|r| {
+            p * (r * r + s2).powf(p / 2.0 - 2.0) * ((p - 1.0) * r * r + s2)
+        });
+        let gradient = a.transpose() * first;
+        let mut weighted = a.clone();
+        for (mut row, &w) in weighted.row_iter_mut().zip(second.iter()) {
+            row *= w;
+        }
+        let hessian = a.transpose() * weighted;
+        // The Hessian can be very small for big p
+        let tolerance = hessian.amax() * 1e-12;
+        let Some(inverse) = hessian.pseudo_inverse(tolerance).ok() else {
+            break;
+        };
+        let step = -(inverse * &gradient);
+        let decrement = -gradient.dot(&step);
+        if decrement.is_nan() || decrement <= 1e-15 * value {
+            break;
+        }
+        // Backtracking line search
+        let mut size = 1.0;
+        let mut improved = false;
+        while size > 1e-10 {
+            let candidate = &x + &step * size;
+            let new_value = objective(&candidate);
+            if new_value < value {
+                x = candidate;
+                value = new_value;
+                improved = true;
+                break;
+            }
+            size /= 2.0;
+        }
+        if !improved {
+            break;
+        }
+    }
+    x
+}
+
+/// The p-th root of the mean of the p-th powers of the absolute values
+fn power_mean(values: impl Iterator<Item = f64>, p: f64) -> f64 {
+    let values: Vec<f64> = values.map(f64::abs).collect();
+    let biggest = values.iter().copied().fold(0.0, f64::max);
+    if biggest == 0.0 {
+        return 0.0;
+    }
+    // Scaled by the biggest value so that big p doesn't overflow
+    let sum: f64 = values.iter().map(|x| (x / biggest).powf(p)).sum();
+    (sum / values.len() as f64).powf(1.0 / p) * biggest
+}
+
+#[test]
+fn te_is_t2() {
+    let limit7 = super::PrimeLimit::new(7);
+    let mapping = vec![vec![12, 19, 28, 34], vec![19, 30, 44, 53]];
+    let te = super::te::TETemperament::new(&limit7.pitches, &mapping);
+    let t2 = TpTemperament::new(&limit7.pitches, &mapping, 2.0)
+        .expect("no T2 tuning");
+    for (x, y) in t2.tuning.iter().zip(&te.tuning) {
+        super::assert_between!(y - 1e-6, *x, y + 1e-6);
+    }
+    super::assert_between!(te.error() - 1e-6, t2.error(), te.error() + 1e-6);
+    super::assert_between!(
+        te.complexity() - 1e-6,
+        t2.complexity(),
+        te.complexity() + 1e-6
+    );
+    assert_eq!(t2.dual_norm(), 2.0);
+}
+
+#[test]
+fn towards_top() {
+    let limit5 = super::PrimeLimit::new(5);
+    let mapping = vec![vec![19, 30, 44], vec![31, 49, 72]];
+    let top = super::top::TOPTemperament::new(&limit5.pitches, &mapping)
+        .expect("no TOP tuning");
+    let mut last_error = 0.0;
+    for p in [1.0, 1.5, 3.0, 8.0, 64.0] {
+        let rt = TpTemperament::new(&limit5.pitches, &mapping, p)
+            .expect("no Tp tuning");
+        // The worst error only gets better as p goes up
+        let worst = rt.weighted_mistunings(&rt.tuning).amax();
+        assert!(worst >= top.error() - 1e-6);
+        // and the power mean gets bigger
+        assert!(rt.error() > last_error);
+        last_error = rt.error();
+    }
+    let rt = TpTemperament::new(&limit5.pitches, &mapping, 64.0)
+        .expect("no Tp tuning");
+    for (x, y) in rt.tuning_map().iter().zip(top.tuning_map()) {
+        super::assert_between!(y - 0.1, *x, y + 0.1);
+    }
+    super::assert_between!(top.error() * 0.9, rt.error(), top.error());
+    // Big norms still converge
+    let rt = TpTemperament::new(&limit5.pitches, &mapping, 1000.0)
+        .expect("no Tp tuning");
+    let worst = rt.weighted_mistunings(&rt.tuning).amax();
+    super::assert_between!(top.error(), worst, top.error() + 1e-3);
+}
+
+#[test]
+fn t1_and_bad_norms() {
+    let limit5 = super::PrimeLimit::new(5);
+    let mapping = vec![vec![1, 0, -4], vec![0, 1, 4]];
+    let rt = TpTemperament::weighted(
+        &limit5.pitches,
+        &mapping,
+        1.0,
+        Weighting::Frobenius,
+    )
+    .expect("no T1 tuning");
+    assert_eq!(rt.dual_norm(), f64::INFINITY);
+    // Any tuning with 5 just and a quarter of the syntonic comma
+    // split between 2 and 3 is optimal.  The smoothing splits it evenly.
+    let mistunings = rt.mistunings();
+    super::assert_between!(-1e-6, mistunings[2], 1e-6);
+    super::assert_between!(2.688, mistunings[0], 2.689);
+    super::assert_between!(-2.689, mistunings[1], -2.688);
+    for norm in [0.5, f64::INFINITY, f64::NAN] {
+        assert!(matches!(
+            TpTemperament::new(&limit5.pitches, &mapping, norm),
+            Err(Error::BadNorm(_))
+        ));
+    }
+}
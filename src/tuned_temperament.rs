@@ -1,6 +1,9 @@
 extern crate nalgebra as na;
-use super::temperament_class::TemperamentClass;
-use super::{Cents, ETSlice, Exponent, Tuning, map};
+use super::mos::{Mos, mos_scale, mos_sizes, period_and_generator};
+use super::mts;
+use super::temperament_class::{MingenForm, TemperamentClass};
+use super::tuning_file::{Scale, TuningFormat};
+use super::{Cents, ETSlice, Error, Exponent, Tuning, map};
 use na::{DMatrix, DVector};
 
 pub trait TunedTemperament: TemperamentClass {
@@ -52,6 +55,11 @@ pub trait TunedTemperament: TemperamentClass {
         comparison.map(|(&x, y)| x - y).collect()
     }
 
+    /// Period and generators in "mingen" form for this tuning
+    fn mingen(&self) -> Result<MingenForm, Error> {
+        self.mingen_form(&self.tuning_map())
+    }
+
     fn pitch_from_steps(&self, interval: &ETSlice) -> Cents {
         self.tuning()
             .iter()
@@ -73,25 +81,119 @@ pub trait TunedTemperament: TemperamentClass {
             .collect()
     }
 
+    fn fokker_block_scale(&self, n_pitches: Exponent, name: &str) -> Scale {
+        let pitches = self.fokker_block_pitches(n_pitches);
+        Scale::new(name, "fokker block", pitches)
+    }
+
+    /// A Fokker block as a tuning file in the given format
+    fn tuning_file(
+        &self,
+        format: TuningFormat,
+        n_pitches: Exponent,
+        name: &str,
+    ) -> String {
+        format.contents(&self.fokker_block_scale(n_pitches, name))
+    }
+
     fn scala_file(&self, n_pitches: Exponent, name: &str) -> String {
-        let notes = self
-            .fokker_block_pitches(n_pitches)
-            .iter()
-            .map(|pitch| format!("{:8.3}", pitch))
-            .collect::<Vec<_>>()
-            .join("\r\n");
-        format!(
-            "! {name}_{n_notes}.scl\r\n\
-             !\r\n\
-             {n_notes} note scale for some {name} temperament.\r\n\
-             ! fokker block\r\n\
-             ! Generated by https://x31eq.com/temper/client.html\r\n\
-             {n_notes}\r\n\
-             !\r\n\
-             {notes}\r\n",
-            n_notes = n_pitches,
-            name = name,
-            notes = notes
-        )
+        self.tuning_file(TuningFormat::Scala, n_pitches, name)
+    }
+
+    /// Sizes of moment of symmetry scales up to max_size notes,
+    /// or nothing if this isn't rank 2
+    /// (or the mapping overflows when it's reduced)
+    fn mos_sizes(&self, max_size: Exponent) -> Vec<Exponent> {
+        let Ok(mingen) = self.mingen() else {
+            return vec![];
+        };
+        match period_and_generator(&mingen) {
+            Some((period, generator, n_periods)) => {
+                mos_sizes(period, generator, n_periods, max_size)
+            }
+            None => vec![],
+        }
+    }
+
+    /// The moment of symmetry scale with this many notes,
+    /// with steps in the mingen form's period and generator
+    fn mos_scale(&self, size: Exponent) -> Option<Mos> {
+        let (period, generator, n_periods) =
+            period_and_generator(&self.mingen().ok()?)?;
+        mos_scale(period, generator, n_periods, size)
     }
+
+    /// MIDI Tuning Standard bulk dump (.syx) of a Fokker block
+    fn mts_bulk_dump(
+        &self,
+        n_pitches: Exponent,
+        name: &str,
+        keyboard: &KeyboardMapping,
+    ) -> Vec<u8> {
+        let pitches = self.fokker_block_pitches(n_pitches);
+        mts::bulk_dump(&pitches, name, keyboard)
+    }
+
+    fn mos_scala_file(&self, size: Exponent, name: &str) -> Option<String> {
+        let mos = self.mos_scale(size)?;
+        Some(TuningFormat::Scala.contents(&Scale::from_mos(&mos, name)))
+    }
+}
+
+/// Settings for a Scala keyboard mapping (.kbm) file
+#[derive(Clone, Debug, PartialEq)]
+pub struct KeyboardMapping {
+    /// MIDI note for the 1/1 of the scale
+    pub middle_note: Exponent,
+    /// MIDI note with a fixed frequency
+    pub reference_note: Exponent,
+    /// Frequency of the reference note in Hz
+    pub reference_frequency: f64,
+    /// Scale degree for each key in a repeating pattern,
+    /// None to leave the key unmapped,
+    /// or empty for one key to each note
+    pub pattern: Vec<Option<Exponent>>,
+}
+
+impl Default for KeyboardMapping {
+    /// Every key mapped with the 1/1 on middle C
+    /// at its 12 equal pitch
+    fn default() -> Self {
+        KeyboardMapping {
+            middle_note: 60,
+            reference_note: 60,
+            reference_frequency: 440.0 * (-0.75f64).exp2(),
+            pattern: vec![],
+        }
+    }
+}
+
+/// Contents of a Scala keyboard mapping file for a scale
+/// of n_pitches notes to the equivalence interval
+pub fn kbm_file(n_pitches: Exponent, keyboard: &KeyboardMapping) -> String {
+    let mut lines = vec![
+        "! Generated by https://x31eq.com/temper/client.html".to_string(),
+        "! Size of map:".to_string(),
+        keyboard.pattern.len().to_string(),
+        "! First MIDI note number to retune:".to_string(),
+        "0".to_string(),
+        "! Last MIDI note number to retune:".to_string(),
+        "127".to_string(),
+        "! Middle note where the first entry of the mapping is mapped to:"
+            .to_string(),
+        keyboard.middle_note.to_string(),
+        "! Reference note for which frequency is given:".to_string(),
+        keyboard.reference_note.to_string(),
+        "! Frequency to tune the above note to:".to_string(),
+        format!("{:.6}", keyboard.reference_frequency),
+        "! Scale degree to consider as formal octave:".to_string(),
+        n_pitches.to_string(),
+        "! Mapping.".to_string(),
+    ];
+    lines.extend(keyboard.pattern.iter().map(|degree| match degree {
+        Some(degree) => degree.to_string(),
+        None => "x".to_string(),
+    }));
+    lines.push(String::new());
+    lines.join("\r\n")
 }
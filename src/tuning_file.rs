@@ -0,0 +1,235 @@
+//! Tuning files for synthesizers and plugins
+//!
+//! A Scale holds everything the files need to know
+//! and a TuningFormat writes it out as Scala (.scl),
+//! AnaMark (.tun), or Ableton (.ascl).
+
+use super::mos::Mos;
+use super::mts::key_semitones;
+use super::tuned_temperament::KeyboardMapping;
+use super::{Cents, Tuning};
+
+/// Frequency of MIDI note 0 with A440
+const ANAMARK_BASE_FREQUENCY: f64 = 8.175_798_915_643_707;
+
+const GENERATED_BY: &str =
+    "Generated by https://x31eq.com/temper/client.html";
+
+/// A scale ready to be written to a tuning file
+#[derive(Clone, Debug, PartialEq)]
+pub struct Scale {
+    /// Name of the temperament
+    pub name: String,
+    /// What kind of scale this is, like "fokker block"
+    pub description: String,
+    /// Notes above the tonic, ending with the equivalence interval
+    pub pitches: Tuning,
+    /// Where the scale goes on the keyboard
+    /// and the reference frequency
+    pub keyboard: KeyboardMapping,
+}
+
+impl Scale {
+    pub fn new(name: &str, description: &str, pitches: Tuning) -> Self {
+        Scale {
+            name: name.to_string(),
+            description: description.to_string(),
+            pitches,
+            keyboard: KeyboardMapping::default(),
+        }
+    }
+
+    pub fn from_mos(mos: &Mos, name: &str) -> Self {
+        let description = format!("MOS {}", mos.pattern);
+        Scale::new(name, &description, mos.pitches.clone())
+    }
+
+    /// Frequency in Hz of the tonic on the middle note
+    pub fn tonic_frequency(&self) -> f64 {
+        let middle = usize::try_from(self.keyboard.middle_note).ok();
+        match middle.and_then(|key| {
+            key_semitones(&self.pitches, &self.keyboard).get(key).copied()
+        }) {
+            Some(Some(semitones)) => {
+                440.0 * ((semitones - 69.0) / 12.0).exp2()
+            }
+            _ => self.keyboard.reference_frequency,
+        }
+    }
+}
+
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub enum TuningFormat {
+    Scala,
+    AnaMark,
+    Ableton,
+}
+
+impl TuningFormat {
+    pub const ALL: [TuningFormat; 3] =
+        [TuningFormat::Scala, TuningFormat::AnaMark, TuningFormat::Ableton];
+
+    pub fn extension(self) -> &'static str {
+        match self {
+            TuningFormat::Scala => "scl",
+            TuningFormat::AnaMark => "tun",
+            TuningFormat::Ableton => "ascl",
+        }
+    }
+
+    pub fn from_extension(extension: &str) -> Option<Self> {
+        TuningFormat::ALL
+            .into_iter()
+            .find(|format| format.extension() == extension)
+    }
+
+    pub fn filename(self, scale: &Scale) -> String {
+        format!("{}_{}.{}", scale.name, scale.pitches.len(), self.extension())
+    }
+
+    /// The file, with DOS line endings
+    pub fn contents(self, scale: &Scale) -> String {
+        match self {
+            TuningFormat::Scala => scala_contents(scale, self, &[]),
+            TuningFormat::AnaMark => anamark_contents(scale),
+            TuningFormat::Ableton => {
+                let extra = [format!(
+                    "! @ABL REFERENCE_PITCH 4 0 {:.6}",
+                    scale.tonic_frequency()
+                )];
+                scala_contents(scale, self, &extra)
+            }
+        }
+    }
+}
+
+/// Contents of a Scala file with any extra lines at the end
+fn scala_contents(
+    scale: &Scale,
+    format: TuningFormat,
+    extra: &[String],
+) -> String {
+    let mut lines = vec![
+        format!("! {}", format.filename(scale)),
+        "!".to_string(),
+        format!(
+            "{} note scale for some {} temperament.",
+            scale.pitches.len(),
+            scale.name
+        ),
+        format!("! {}", scale.description),
+        format!("! {}", GENERATED_BY),
+        scale.pitches.len().to_string(),
+        "!".to_string(),
+    ];
+    lines.extend(scale.pitches.iter().map(|pitch| format!("{:8.3}", pitch)));
+    lines.extend(extra.iter().cloned());
+    lines.push(String::new());
+    lines.join("\r\n")
+}
+
+/// Contents of an AnaMark version 2 file
+/// with cents for every MIDI note
+fn anamark_contents(scale: &Scale) -> String {
+    let key_cents: Vec<Cents> =
+        key_semitones(&scale.pitches, &scale.keyboard)
+            .into_iter()
+            .enumerate()
+            // Unmapped keys stay in 12 equal
+            .map(|(key, semitones)| semitones.unwrap_or(key as f64) * 100.0)
+            .collect();
+    let filename = TuningFormat::AnaMark.filename(scale);
+    let mut lines = vec![
+        format!("; {}", filename),
+        format!("; {}", scale.description),
+        format!("; {}", GENERATED_BY),
+        "[Scale Begin]".to_string(),
+        "Format = \"AnaMark-TUN\"".to_string(),
+        "FormatVersion = 200".to_string(),
+        "FormatSpecs = \"http://www.mark-henning.de/eternity/tuningspecs.html\""
+            .to_string(),
+        String::new(),
+        "[Info]".to_string(),
+        format!("Name = \"{}\"", filename),
+        format!(
+            "Description = \"{} note scale for some {} temperament.\"",
+            scale.pitches.len(),
+            scale.name
+        ),
+        String::new(),
+        "[Tuning]".to_string(),
+    ];
+    lines.extend(
+        key_cents
+            .iter()
+            .enumerate()
+            .map(|(key, cents)| format!("note {}={:.0}", key, cents)),
+    );
+    lines.push(String::new());
+    lines.push("[Exact Tuning]".to_string());
+    lines.push(format!("BaseFreq = {:.10}", ANAMARK_BASE_FREQUENCY));
+    lines.extend(
+        key_cents
+            .iter()
+            .enumerate()
+            .map(|(key, cents)| format!("note {}={:.6}", key, cents)),
+    );
+    lines.push(String::new());
+    lines.push("[Scale End]".to_string());
+    lines.push(String::new());
+    lines.join("\r\n")
+}
+
+#[cfg(test)]
+fn twelve_equal() -> Scale {
+    let pitches = (1..=12).map(|i| i as Cents * 100.0).collect();
+    Scale::new("Twelve", "equal temperament", pitches)
+}
+
+#[test]
+fn formats() {
+    for format in TuningFormat::ALL {
+        assert_eq!(
+            TuningFormat::from_extension(format.extension()),
+            Some(format)
+        );
+    }
+    assert_eq!(TuningFormat::from_extension("txt"), None);
+    let scale = twelve_equal();
+    assert_eq!(TuningFormat::Ableton.filename(&scale), "Twelve_12.ascl");
+    super::assert_between!(261.62, scale.tonic_frequency(), 261.63);
+}
+
+#[test]
+fn anamark() {
+    let contents = TuningFormat::AnaMark.contents(&twelve_equal());
+    let lines: Vec<&str> = contents.split("\r\n").collect();
+    assert_eq!(lines[0], "; Twelve_12.tun");
+    assert!(lines.contains(&"[Scale Begin]"));
+    assert!(lines.contains(&"FormatVersion = 200"));
+    assert!(lines.contains(&"Name = \"Twelve_12.tun\""));
+    assert!(lines.contains(&"note 0=0"));
+    assert!(lines.contains(&"note 69=6900"));
+    assert!(lines.contains(&"note 127=12700"));
+    assert!(lines.contains(&"BaseFreq = 8.1757989156"));
+    assert!(lines.contains(&"note 60=6000.000000"));
+    assert_eq!(
+        lines.iter().filter(|line| line.starts_with("note ")).count(),
+        256
+    );
+    assert_eq!(lines[lines.len() - 2], "[Scale End]");
+}
+
+#[test]
+fn ableton() {
+    let contents = TuningFormat::Ableton.contents(&twelve_equal());
+    let lines: Vec<&str> = contents.split("\r\n").collect();
+    assert_eq!(lines[0], "! Twelve_12.ascl");
+    assert_eq!(lines[5], "12");
+    assert_eq!(lines[7], " 100.000");
+    assert_eq!(lines[18], "1200.000");
+    assert_eq!(lines[19], "! @ABL REFERENCE_PITCH 4 0 261.625565");
+    // Scala can still read it
+    let scala = super::scala::parse_scala(&contents).expect("bad file");
+    assert_eq!(scala.cents(), twelve_equal().pitches);
+}
@@ -1,14 +1,14 @@
-extern crate nalgebra as na;
-use na::DMatrix;
-
 use super::cangwu::filtered_equal_temperaments;
+use super::lattice::{self, IntMatrix};
+use super::wedgie::saturation_index;
 use super::{
-    Cents, ETMap, ETSlice, Exponent, Mapping, echelon_form,
-    hermite_normal_form, normalize_positive,
+    Cents, ETMap, ETSlice, Error, Exponent, Mapping, checked_echelon_form,
+    checked_hermite_normal_form, normalize_positive,
 };
 
 /// Return the commatic unison vector for a mapping with
-/// only one dimension short
+/// only one dimension short.
+/// None if there isn't one, or it's too big for Exponents.
 pub fn only_unison_vector(mapping: &Mapping) -> Option<ETMap> {
     let rank = mapping.len();
     if rank == 0 {
@@ -18,17 +18,19 @@ pub fn only_unison_vector(mapping: &Mapping) -> Option<ETMap> {
     if rank + 1 != dimension {
         return None;
     }
-    let fmap = float_matrix_from_mapping(mapping);
-    let mut sq = fmap.transpose().insert_column(0, 0.0);
-    for i in 0..dimension {
-        sq[(i, 0)] = 1.0;
-        if let Some(inverse) = sq.clone().try_inverse() {
-            let adjoint = inverse * sq.clone().determinant();
-            return mapping_from_float_matrix(adjoint).into_iter().next();
-        }
-        sq[(i, 0)] = 0.0;
+    // The first row of the adjugate doesn't depend on the first
+    // column, and it's orthogonal to all the others
+    let square: IntMatrix =
+        lattice::transpose(&lattice::from_mapping(mapping), dimension)
+            .into_iter()
+            .map(|row| [vec![0], row].concat())
+            .collect();
+    let uv = lattice::adjugate_row(&square, 0).ok()?;
+    if uv.iter().all(|&x| x == 0) {
+        // The mapping's rank is too low
+        return None;
     }
-    None
+    lattice::to_mapping(&[uv]).ok()?.pop()
 }
 
 pub fn get_ets_tempering_out(
@@ -45,6 +47,20 @@ pub fn get_ets_tempering_out(
     )
 }
 
+/// Make the unison vectors positive and throw away
+/// anything larger than a whole tone as not a unison vector
+pub fn filter_unison_vectors(plimit: &[Cents], uvs: Mapping) -> Mapping {
+    uvs.into_iter()
+        // Ensure everything's positive before the size check
+        .map(|uv| normalize_positive(plimit, uv))
+        .filter(|uv| interval_size(plimit, uv) < 200.0)
+        .collect()
+}
+
+fn interval_size(plimit: &[Cents], interval: &ETSlice) -> Cents {
+    plimit.iter().zip(interval).map(|(&p, &i)| p * (i as Cents)).sum()
+}
+
 fn tempers_out(mapping: &[ETMap], interval: &ETSlice) -> bool {
     mapping.iter().all(|et| dotprod(et, interval) == 0)
 }
@@ -79,28 +95,110 @@ fn dotprod(a: &[Exponent], b: &[Exponent]) -> i64 {
 }
 
 /// Get unison vectors from a mapping and TLL-reduce them
-pub fn unison_vector_basis(plimit: &[Cents], mapping: &[ETMap]) -> Mapping {
-    rtlll(plimit, &saturated_kernel_basis(mapping))
+pub fn unison_vector_basis(
+    plimit: &[Cents],
+    mapping: &[ETMap],
+) -> Result<Mapping, Error> {
+    Ok(rtlll(plimit, &saturated_kernel_basis(mapping)?)
         .into_iter()
         .map(|uv| normalize_positive(plimit, uv))
-        .collect()
+        .collect())
+}
+
+/// The reduced mapping (in Hermite normal form) of the
+/// temperament class that tempers out exactly these unison vectors
+/// (as prime exponents) and nothing else that isn't implied.
+/// No unison vectors give just intonation.
+pub fn mapping_from_unison_vectors(
+    n_primes: usize,
+    uvs: &[ETMap],
+) -> Result<Mapping, Error> {
+    check_dimensions(n_primes, uvs.iter())?;
+    let uvs: Mapping =
+        uvs.iter().filter(|uv| uv.iter().any(|&x| x != 0)).cloned().collect();
+    let mapping = if uvs.is_empty() {
+        (0..n_primes)
+            .map(|i| (0..n_primes).map(|j| Exponent::from(i == j)).collect())
+            .collect()
+    } else {
+        saturated_kernel_basis(&uvs)?
+    };
+    let mapping: Mapping = checked_hermite_normal_form(&mapping)?
+        .into_iter()
+        .filter(|row| row.iter().any(|&x| x != 0))
+        .collect();
+    if mapping.is_empty() {
+        Err(Error::TooManyUnisonVectors)
+    } else {
+        Ok(mapping)
+    }
+}
+
+/// Reduced mapping of the temperament class that tempers out
+/// the unison vectors of both mappings
+pub fn join_mappings(
+    n_primes: usize,
+    a: &[ETMap],
+    b: &[ETMap],
+) -> Result<Mapping, Error> {
+    check_dimensions(n_primes, a.iter().chain(b))?;
+    let mut uvs = saturated_kernel_basis(a)?;
+    uvs.extend(saturated_kernel_basis(b)?);
+    mapping_from_unison_vectors(n_primes, &uvs)
+}
+
+/// Reduced mapping of the temperament class spanned by
+/// the vals of both mappings
+pub fn meet_mappings(
+    n_primes: usize,
+    a: &[ETMap],
+    b: &[ETMap],
+) -> Result<Mapping, Error> {
+    check_dimensions(n_primes, a.iter().chain(b))?;
+    let vals: Mapping = a.iter().chain(b).cloned().collect();
+    mapping_from_unison_vectors(n_primes, &saturated_kernel_basis(&vals)?)
+}
+
+fn check_dimensions<'a>(
+    n_primes: usize,
+    mut vectors: impl Iterator<Item = &'a ETMap>,
+) -> Result<(), Error> {
+    match vectors.find(|v| v.len() != n_primes) {
+        Some(v) => Err(Error::DimensionMismatch {
+            expected: n_primes,
+            found: v.len(),
+        }),
+        None => Ok(()),
+    }
+}
+
+/// The order of torsion in a list of unison vectors,
+/// or 1 if there isn't any.
+/// Torsion means some interval that isn't tempered out
+/// becomes a unison when it's multiplied by a whole number.
+pub fn torsion(uvs: &[ETMap]) -> Result<i64, Error> {
+    saturation_index(uvs)
+}
+
+pub fn has_torsion(uvs: &[ETMap]) -> Result<bool, Error> {
+    Ok(torsion(uvs)? > 1)
 }
 
-pub fn saturated_kernel_basis(vectors: &[ETMap]) -> Mapping {
-    saturate(&kernel_basis(vectors))
-        .expect("calculated basis not of full rank")
+pub fn saturated_kernel_basis(vectors: &[ETMap]) -> Result<Mapping, Error> {
+    Ok(saturate(&kernel_basis(vectors)?)?
+        .expect("calculated basis not of full rank"))
 }
 
 /// Get unison vectors from a mapping, or vice versa.
 /// Results aren't simple and might introduce torsion.
-fn kernel_basis(vectors: &[ETMap]) -> Mapping {
+fn kernel_basis(vectors: &[ETMap]) -> Result<Mapping, Error> {
     // The algorithm originally came from
     // http://en.wikipedia.org/wiki/Null_space#Basis
     // but they kept taking it away because it isn't efficient.
     // But it is easy to implement.
 
     if vectors.is_empty() {
-        return vec![];
+        return Ok(vec![]);
     }
     let n_rows = vectors.len();
     let mut prepared = transpose(vectors);
@@ -110,7 +208,7 @@ fn kernel_basis(vectors: &[ETMap]) -> Mapping {
             v.push(if i == j { 1 } else { 0 });
         }
     }
-    echelon_form(&prepared)
+    Ok(checked_echelon_form(&prepared)?
         .into_iter()
         .filter_map(|v| {
             debug_assert_eq!(n_rows + n_cols, v.len());
@@ -122,26 +220,27 @@ fn kernel_basis(vectors: &[ETMap]) -> Mapping {
                 None
             }
         })
-        .collect()
+        .collect())
 }
 
 /// Remove torsion from a basis.
-/// Returns None when the vectors are linearly dependent.
-fn saturate(vectors: &[ETMap]) -> Option<Mapping> {
+/// Returns None when the vectors are linearly dependent
+/// and Error::Overflow if the numbers get too big.
+fn saturate(vectors: &[ETMap]) -> Result<Option<Mapping>, Error> {
     // c.f. http://www.wstein.org/papers/hnf/
     // pernet-stein-fast_computation_of_hnf_of_random_integer_matrices.pdf
     if vectors.is_empty() {
-        return Some(vec![]);
+        return Ok(Some(vec![]));
     }
     debug_assert!(vectors.iter().all(|row| row.len() == vectors[0].len()));
-    debug_assert_ne!(vectors[0], vec![]);
+    debug_assert!(!vectors[0].is_empty());
 
     let n_vecs = vectors.len();
-    let hermite = hermite_normal_form(vectors);
+    let hermite = checked_hermite_normal_form(vectors)?;
     debug_assert!(hermite.iter().all(|row| row.len() == vectors[0].len()));
     debug_assert_eq!(hermite.len(), n_vecs);
 
-    let double_hermite = hermite_normal_form(&transpose(&hermite));
+    let double_hermite = checked_hermite_normal_form(&transpose(&hermite))?;
     debug_assert!(
         double_hermite
             .iter()
@@ -153,17 +252,30 @@ fn saturate(vectors: &[ETMap]) -> Option<Mapping> {
         let gcd = double_hermite[0][0];
         if gcd == 0 {
             debug_assert!(vector.iter().all(|&x| x == 0));
-            return Some(vectors.to_vec());
+            return Ok(Some(vectors.to_vec()));
         }
-        return Some(vec![vector.iter().map(|x| x / gcd).collect()]);
+        return Ok(Some(vec![vector.iter().map(|x| x / gcd).collect()]));
     }
-    let double_hermite = float_matrix_from_mapping(&double_hermite[..n_vecs]);
-    debug_assert_eq!(double_hermite.shape(), (n_vecs, n_vecs));
-
-    let transformation = double_hermite.try_inverse()?;
-    let hermite_matrix = float_matrix_from_mapping(&hermite);
-    let result = transformation.transpose() * hermite_matrix;
-    Some(mapping_from_float_matrix(result))
+    let double_hermite = lattice::from_mapping(&double_hermite[..n_vecs]);
+    debug_assert!(double_hermite.iter().all(|row| row.len() == n_vecs));
+
+    // The transformation is the inverse of double_hermite,
+    // which is the adjugate divided by the determinant
+    let det = lattice::determinant(&double_hermite)?;
+    if det == 0 {
+        return Ok(None);
+    }
+    let adjugate = lattice::adjugate(&double_hermite)?;
+    let result = lattice::multiply(
+        &lattice::transpose(&adjugate, n_vecs),
+        &lattice::from_mapping(&hermite),
+    )?;
+    debug_assert!(result.iter().flatten().all(|x| x % det == 0));
+    let result: IntMatrix = result
+        .iter()
+        .map(|row| row.iter().map(|x| x / det).collect())
+        .collect();
+    lattice::to_mapping(&result).map(Some)
 }
 
 fn transpose<T: Clone>(m: &[Vec<T>]) -> Vec<Vec<T>> {
@@ -177,22 +289,6 @@ fn transpose<T: Clone>(m: &[Vec<T>]) -> Vec<Vec<T>> {
     }
 }
 
-fn float_matrix_from_mapping(m: &[ETMap]) -> DMatrix<f64> {
-    let n_cols = m.first().map_or(0, Vec::len);
-    debug_assert!(m.iter().all(|row| row.len() == n_cols));
-    DMatrix::from_row_iterator(
-        m.len(),
-        n_cols,
-        m.iter().flat_map(|m| m.iter()).map(|&x| x as f64),
-    )
-}
-
-fn mapping_from_float_matrix(m: DMatrix<f64>) -> Mapping {
-    m.row_iter()
-        .map(|row| row.iter().map(|&x| x.round() as Exponent).collect())
-        .collect()
-}
-
 /// Recursive Tenney-weighted LLL
 pub fn rtlll(plimit: &[Cents], vectors: &[ETMap]) -> Mapping {
     if vectors.len() < 2 {
@@ -486,22 +582,28 @@ fn inherent_errors() {
 fn meantone5_kernel() {
     let mapping = vec![vec![12, 19, 28], vec![19, 30, 44]];
     let expected = vec![vec![4, -4, 1]];
-    let kernel = kernel_basis(&mapping);
+    let kernel = kernel_basis(&mapping).unwrap();
     assert_eq!(kernel, expected);
-    assert_eq!(Some(expected), saturate(&kernel));
-    assert_eq!(kernel, saturated_kernel_basis(&mapping));
+    assert_eq!(Some(expected), saturate(&kernel).unwrap());
+    assert_eq!(kernel, saturated_kernel_basis(&mapping).unwrap());
     let reduced = super::hermite_normal_form(&mapping);
-    assert_eq!(reduced, super::hermite_normal_form(&kernel_basis(&kernel)));
+    assert_eq!(
+        reduced,
+        super::hermite_normal_form(&kernel_basis(&kernel).unwrap())
+    );
 }
 
 #[test]
 fn meantone5_redundant_kernel() {
     let mapping = vec![vec![12, 19, 28], vec![19, 30, 44], vec![31, 49, 72]];
-    assert_eq!(kernel_basis(&mapping), vec![vec![4, -4, 1]]);
+    assert_eq!(kernel_basis(&mapping).unwrap(), vec![vec![4, -4, 1]]);
     // The redundant mapping can't be saturated
-    assert_eq!(saturate(&mapping), None);
+    assert_eq!(saturate(&mapping).unwrap(), None);
     // But the basis is fine
-    assert_eq!(saturated_kernel_basis(&mapping), vec![vec![4, -4, 1]]);
+    assert_eq!(
+        saturated_kernel_basis(&mapping).unwrap(),
+        vec![vec![4, -4, 1]]
+    );
 }
 
 #[test]
@@ -509,11 +611,14 @@ fn meantone7_kernel() {
     let mapping = vec![vec![12, 19, 28, 34], vec![19, 30, 44, 53]];
     // This is implementation-specific
     let expected = vec![vec![1, 2, -3, 1], vec![0, 12, -13, 4]];
-    let kernel = kernel_basis(&mapping);
+    let kernel = kernel_basis(&mapping).unwrap();
     assert_eq!(kernel, expected);
-    assert_eq!(kernel, saturated_kernel_basis(&mapping));
+    assert_eq!(kernel, saturated_kernel_basis(&mapping).unwrap());
     let reduced = super::hermite_normal_form(&mapping);
-    assert_eq!(reduced, super::hermite_normal_form(&kernel_basis(&kernel)));
+    assert_eq!(
+        reduced,
+        super::hermite_normal_form(&kernel_basis(&kernel).unwrap())
+    );
 }
 
 #[test]
@@ -525,23 +630,28 @@ fn meantone7_redundant_kernel() {
     ];
     // This is implementation-specific
     let expected = vec![vec![1, 2, -3, 1], vec![0, 12, -13, 4]];
-    assert_eq!(kernel_basis(&mapping), expected);
-    assert_eq!(saturated_kernel_basis(&mapping), expected);
+    assert_eq!(kernel_basis(&mapping).unwrap(), expected);
+    assert_eq!(saturated_kernel_basis(&mapping).unwrap(), expected);
 }
 
 #[test]
 fn magic11_kernel() {
     let mapping = vec![vec![19, 30, 44, 53, 66], vec![22, 35, 51, 62, 76]];
-    let kernel = kernel_basis(&mapping);
+    let kernel = kernel_basis(&mapping).unwrap();
     let reduced = super::hermite_normal_form(&mapping);
     assert_eq!(
         super::hermite_normal_form(&kernel),
-        super::hermite_normal_form(&saturated_kernel_basis(&mapping)),
+        super::hermite_normal_form(
+            &saturated_kernel_basis(&mapping).unwrap()
+        ),
+    );
+    assert_eq!(
+        reduced,
+        super::hermite_normal_form(&kernel_basis(&kernel).unwrap())
     );
-    assert_eq!(reduced, super::hermite_normal_form(&kernel_basis(&kernel)));
     assert_eq!(
         reduced,
-        super::hermite_normal_form(&saturated_kernel_basis(&kernel)),
+        super::hermite_normal_form(&saturated_kernel_basis(&kernel).unwrap()),
     );
 }
 
@@ -552,16 +662,21 @@ fn marvel11_kernel() {
         vec![22, 35, 51, 62, 76],
         vec![31, 49, 72, 87, 107],
     ];
-    let kernel = kernel_basis(&mapping);
+    let kernel = kernel_basis(&mapping).unwrap();
     let reduced = super::hermite_normal_form(&mapping);
     assert_eq!(
         super::hermite_normal_form(&kernel),
-        super::hermite_normal_form(&saturated_kernel_basis(&mapping)),
+        super::hermite_normal_form(
+            &saturated_kernel_basis(&mapping).unwrap()
+        ),
     );
-    assert_eq!(reduced, super::hermite_normal_form(&kernel_basis(&kernel)));
     assert_eq!(
         reduced,
-        super::hermite_normal_form(&saturated_kernel_basis(&kernel)),
+        super::hermite_normal_form(&kernel_basis(&kernel).unwrap())
+    );
+    assert_eq!(
+        reduced,
+        super::hermite_normal_form(&saturated_kernel_basis(&kernel).unwrap()),
     );
 }
 
@@ -572,7 +687,7 @@ fn mystery17_uvs() {
         vec![58, 92, 135, 163, 201, 215, 237],
     ];
     let plimit = super::PrimeLimit::new(17);
-    let uvs = unison_vector_basis(&plimit.pitches, &mapping);
+    let uvs = unison_vector_basis(&plimit.pitches, &mapping).unwrap();
     let ratios: Vec<_> = uvs
         .iter()
         .map(|uv| super::ratio::get_ratio_or_ket_string(&plimit, uv))
@@ -598,16 +713,21 @@ fn mystery17_kernel() {
         vec![29, 46, 67, 81, 100, 107, 119],
         vec![58, 92, 135, 163, 201, 215, 237],
     ];
-    let kernel = kernel_basis(&mapping);
+    let kernel = kernel_basis(&mapping).unwrap();
     let reduced = super::hermite_normal_form(&mapping);
     assert_eq!(
         super::hermite_normal_form(&kernel),
-        super::hermite_normal_form(&saturated_kernel_basis(&mapping)),
+        super::hermite_normal_form(
+            &saturated_kernel_basis(&mapping).unwrap()
+        ),
+    );
+    assert_eq!(
+        reduced,
+        super::hermite_normal_form(&kernel_basis(&kernel).unwrap())
     );
-    assert_eq!(reduced, super::hermite_normal_form(&kernel_basis(&kernel)));
     assert_eq!(
         reduced,
-        super::hermite_normal_form(&saturated_kernel_basis(&kernel)),
+        super::hermite_normal_form(&saturated_kernel_basis(&kernel).unwrap()),
     );
 }
 
@@ -615,51 +735,64 @@ fn mystery17_kernel() {
 fn saturate_vector() {
     let mapping = vec![vec![2, 4, 6, 8]];
     let expected = vec![vec![1, 2, 3, 4]];
-    assert_eq!(saturate(&mapping), Some(expected));
+    assert_eq!(saturate(&mapping).unwrap(), Some(expected));
 }
 
 #[test]
 fn saturate_negative() {
     let mapping = vec![vec![-2, -4, -6, -8]];
     let expected = vec![vec![-1, -2, -3, -4]];
-    assert_eq!(saturate(&mapping), Some(expected));
+    assert_eq!(saturate(&mapping).unwrap(), Some(expected));
 }
 
 #[test]
 fn saturate_vector10() {
     let mapping = vec![vec![20, 40, 60, 80]];
     let expected = vec![vec![1, 2, 3, 4]];
-    assert_eq!(saturate(&mapping), Some(expected));
+    assert_eq!(saturate(&mapping).unwrap(), Some(expected));
 }
 
 #[test]
 fn saturate_matrix() {
     let mapping = vec![vec![2, 4, 6], vec![3, 4, 5]];
     let expected = vec![vec![1, 0, -1], vec![0, 1, 2]];
-    assert_eq!(saturate(&mapping), Some(expected));
+    assert_eq!(saturate(&mapping).unwrap(), Some(expected));
 }
 
 #[test]
 fn saturate_empty() {
-    assert_eq!(saturate(&vec![]), Some(vec![]));
+    assert_eq!(saturate(&vec![]).unwrap(), Some(vec![]));
 }
 
 #[test]
 fn saturate_zero() {
     // A single zero vector going in is valid
-    assert_eq!(saturate(&vec![vec![0]]), Some(vec![vec![0]]));
+    assert_eq!(saturate(&vec![vec![0]]).unwrap(), Some(vec![vec![0]]));
     assert_eq!(
-        saturate(&vec![vec![0, 0, 0, 0]]),
+        saturate(&vec![vec![0, 0, 0, 0]]).unwrap(),
         Some(vec![vec![0, 0, 0, 0]]),
     );
     // Two zero vectors count as linearly dependent
-    assert_eq!(saturate(&vec![vec![0, 0, 0], vec![0, 0, 0]]), None);
+    assert_eq!(saturate(&vec![vec![0, 0, 0], vec![0, 0, 0]]).unwrap(), None);
+}
+
+#[test]
+fn saturate_big() {
+    // Checked against the saturation from the Smith normal form
+    let vectors =
+        vec![vec![2 * 1009, 2 * 997, 0, 4], vec![3 * 991, 0, 3 * 1013, 6]];
+    let saturated = saturate(&vectors).unwrap().expect("dependent vectors");
+    assert_eq!(
+        super::hermite_normal_form(&saturated),
+        lattice::saturation(&vectors).unwrap()
+    );
+    assert_eq!(saturation_index(&saturated).unwrap(), 1);
 }
 
 #[test]
 fn saturate_redundant() {
     let mapping = vec![vec![1, 2, 3, 4], vec![2, 4, 6, 8]];
-    assert_eq!(saturate(&mapping), None);
+    assert_eq!(saturate(&mapping).unwrap(), None);
 }
 
 #[test]
@@ -693,51 +826,6 @@ fn uneven_transpose() {
     transpose(&vec![vec![1, 2, 3], vec![4, 5]]);
 }
 
-#[test]
-fn matrix_from_mapping() {
-    assert_eq!(
-        float_matrix_from_mapping(&vec![vec![1, 2, 3], vec![4, 5, 6]]),
-        nalgebra::dmatrix![1.0, 2.0, 3.0; 4.0, 5.0, 6.0],
-    )
-}
-
-#[test]
-fn mapping_from_matrix() {
-    assert_eq!(
-        mapping_from_float_matrix(
-            nalgebra::dmatrix![1.0, 2.0, 3.0; 4.0, 5.0, 6.0]
-        ),
-        vec![vec![1, 2, 3], vec![4, 5, 6]],
-    )
-}
-
-#[test]
-fn matrix_from_vector() {
-    assert_eq!(
-        float_matrix_from_mapping(&vec![vec![1, 2, 3]]),
-        nalgebra::dmatrix![1.0, 2.0, 3.0],
-    )
-}
-
-#[test]
-fn vector_from_matrix() {
-    assert_eq!(
-        mapping_from_float_matrix(nalgebra::dmatrix![1.0, 2.0, 3.0]),
-        vec![vec![1, 2, 3]],
-    )
-}
-
-#[test]
-fn matrix_from_empty() {
-    assert_eq!(float_matrix_from_mapping(&vec![]), nalgebra::dmatrix![])
-}
-
-#[test]
-fn mapping_from_empty() {
-    let empty: Mapping = vec![];
-    assert_eq!(mapping_from_float_matrix(nalgebra::dmatrix![]), empty)
-}
-
 #[test]
 fn tlll_limit11() {
     // Compared to Python implementation
@@ -818,3 +906,30 @@ fn round_lll_checks() {
     assert_eq!(round_lll(-0.5), -1);
     assert_eq!(round_lll(-0.5000001), -1);
 }
+
+#[test]
+fn filter_uvs() {
+    let limit = super::PrimeLimit::new(5).pitches;
+    let uvs = vec![vec![4, -4, 1], vec![-1, 1, 0], vec![-2, 0, 1]];
+    // The fifth and major third are too big to be unison vectors
+    assert_eq!(filter_unison_vectors(&limit, uvs), vec![vec![-4, 4, -1]]);
+}
+
+#[test]
+fn torsional_commas() {
+    let limit5 = super::PrimeLimit::new(5);
+    let meantone = vec![vec![-4, 4, -1]];
+    assert!(!has_torsion(&meantone).unwrap());
+    let squared = vec![vec![-8, 8, -2], vec![7, 0, -3]];
+    assert_eq!(torsion(&squared).unwrap(), 2);
+    // The torsion disappears from the mapping
+    let mapping =
+        mapping_from_unison_vectors(3, &squared).expect("no mapping");
+    assert_eq!(mapping, vec![vec![12, 19, 28]]);
+    assert!(
+        !has_torsion(
+            &unison_vector_basis(&limit5.pitches, &mapping).unwrap()
+        )
+        .unwrap()
+    );
+}
@@ -5,25 +5,28 @@ use wasm_bindgen::prelude::{JsValue, wasm_bindgen};
 use web_sys::{Element, Event, HtmlInputElement};
 
 use super::accordion::show_accordion;
-use super::cangwu::{
-    CangwuTemperament, ambiguous_et, get_equal_temperaments,
-    higher_rank_search,
-};
+use super::cangwu::{CangwuTemperament, et_name, higher_rank_search};
+use super::constrained::ConstrainedTemperament;
+use super::mts::bulk_dump;
 use super::ratio::{
     get_ratio_or_ket_string, parse_as_vector, parse_in_simplest_limit,
 };
+use super::search::Search;
 use super::te::TETemperament;
 use super::temperament_class::TemperamentClass;
 use super::top::TOPTemperament;
-use super::tuned_temperament::TunedTemperament;
+use super::tp::TpTemperament;
+use super::tuned_temperament::{TunedTemperament, kbm_file};
+use super::tuning_file::TuningFormat;
 use super::uv::{
-    ek_for_search, get_ets_tempering_out, only_unison_vector,
-    unison_vector_basis,
+    ek_for_search, filter_unison_vectors, get_ets_tempering_out,
+    only_unison_vector, unison_vector_basis,
 };
 use super::web_context::{Exceptionable, WebContext};
+use super::weighting::Weighting;
 use super::{
-    Cents, ETMap, Exponent, Mapping, PrimeLimit, hermite_normal_form, map,
-    normalize_positive, warted_et_name,
+    Cents, ETMap, Error, Exponent, Mapping, PrimeLimit, map,
+    normalize_positive,
 };
 
 #[wasm_bindgen]
@@ -94,7 +97,7 @@ fn pregular_action(
 ) -> Result<(), String> {
     let limit = params.get("limit").ok_or("No prime limit")?;
     web.set_input_value("prime-limit", limit);
-    let limit = limit.parse().or(Err("Unable to parse prime limit"))?;
+    let limit = limit.parse().map_err(|e: Error| e.to_string())?;
     let eka = params.get("error").ok_or("No target error")?;
     web.set_input_value("prime-eka", eka);
     let eka = eka.parse().or(Err("Unable to parse target error"))?;
@@ -126,23 +129,18 @@ fn uv_action(
         .collect();
     web.set_input_value("uv-uvs", &uv_strings.join(" "));
     let (limit, uvs) = if let Some(limit) = params.get("limit") {
-        let limit = limit.parse().or(Err("Unable to parse prime limit"))?;
+        let limit = limit.parse().map_err(|e: Error| e.to_string())?;
         let uvs = uv_strings
             .iter()
-            .filter_map(|uv| parse_as_vector(&limit, uv))
-            .collect();
+            .map(|uv| parse_as_vector(&limit, uv))
+            .collect::<Result<_, _>>()
+            .map_err(|e| e.to_string())?;
         (limit, uvs)
     } else {
         parse_in_simplest_limit(&uv_strings)
             .ok_or("Unable to determine prime limit from ratios")?
     };
-    let uvs: Mapping = uvs
-        .into_iter()
-        // Ensure everything's positive before the size check
-        .map(|uv| normalize_positive(&limit.pitches, uv))
-        // Filter out anything larger than a whole tone as not a unison vector
-        .filter(|uv| limit.interval_size(uv) < 200.0)
-        .collect();
+    let uvs = filter_unison_vectors(&limit.pitches, uvs);
     if uvs.is_empty() {
         return Err("No valid unison vectors in the limit".to_string());
     }
@@ -174,13 +172,12 @@ fn lowrank_action(
     let (ets, limit, key) =
         parse_rt_params(params).ok_or("Missing parameter")?;
     web.set_input_value("prime-limit", &limit);
-    let limit =
-        limit.parse::<PrimeLimit>().or(Err("Unable to parse limit"))?;
+    let limit = limit.parse::<PrimeLimit>().map_err(|e| e.to_string())?;
     let rt = match key {
-        Some(key) => rt_from_ets_and_key(&limit, &ets, &key),
-        None => CangwuTemperament::from_name(&limit, &ets),
-    }
-    .ok_or("Couldn't generate the regular temperament!")?;
+        Some(key) => rt_from_ets_and_key(&limit, &ets, &key)?,
+        None => CangwuTemperament::from_name(&limit, &ets)
+            .map_err(|e| e.to_string())?,
+    };
     let ekm = if let Some(multiplier) = params.get("error") {
         multiplier
             .parse()
@@ -210,6 +207,23 @@ fn net_action(
     rt_action(web, &params)
 }
 
+/// Show the tunings for another Lp norm
+#[wasm_bindgen]
+pub fn norm_change(evt: Event) {
+    let web = WebContext::init();
+    let Some(input) = evt
+        .target()
+        .and_then(|target| target.dyn_into::<HtmlInputElement>().ok())
+    else {
+        return;
+    };
+    let params = web.get_url_params();
+    let mut params: HashMap<&str, String> =
+        params.iter().map(|(k, v)| (k.as_str(), v.clone())).collect();
+    params.insert("norm", input.value().trim().to_string());
+    web.resubmit_with_params(&params);
+}
+
 #[wasm_bindgen(start)]
 fn wasm_main() -> Result<(), JsValue> {
     clear_noscript();
@@ -264,19 +278,31 @@ fn rt_action(
 ) -> Result<(), String> {
     let (ets, limit, key) =
         parse_rt_params(params).ok_or("Missing parameter")?;
+    // A bad norm only loses the Tp tunings
+    let norm = match params.get("norm") {
+        Some(norm) => norm.parse().ok(),
+        None => Some(2.0),
+    };
+    match norm {
+        Some(norm) => {
+            for id in ["et-tp-norm", "rt-tp-norm"] {
+                web.set_input_value(id, &norm.to_string());
+            }
+        }
+        None => web.log_error("Unable to parse norm"),
+    }
     web.set_input_value("prime-limit", &limit);
-    let limit =
-        limit.parse::<PrimeLimit>().or(Err("Unable to parse limit"))?;
+    let limit = limit.parse::<PrimeLimit>().map_err(|e| e.to_string())?;
     let rt = match key {
-        Some(key) => rt_from_ets_and_key(&limit, &ets, &key),
-        None => CangwuTemperament::from_name(&limit, &ets),
-    }
-    .ok_or("Couldn't generate the regular temperament!")?;
+        Some(key) => rt_from_ets_and_key(&limit, &ets, &key)?,
+        None => CangwuTemperament::from_name(&limit, &ets)
+            .map_err(|e| e.to_string())?,
+    };
     if rt.melody.len() == 1 {
-        show_et(web, &limit, rt.melody)
+        show_et(web, &limit, rt.melody, norm)
             .or(Err("Failed to show the regular temperament"))?;
     } else {
-        show_rt(web, &limit, rt.melody)
+        show_rt(web, &limit, rt.melody, norm)
             .or(Err("Failed to show the regular temperament"))?;
     }
     Ok(())
@@ -286,18 +312,19 @@ fn rt_from_ets_and_key<'a>(
     limit: &'a PrimeLimit,
     ets: &str,
     key: &str,
-) -> Option<CangwuTemperament<'a>> {
+) -> Result<CangwuTemperament<'a>, String> {
     let ets = ets
         .split('_')
         .map(Exponent::from_str)
         .collect::<Result<Vec<_>, _>>()
-        .ok()?;
+        .or(Err("Unable to parse ETs"))?;
     let key = key
         .split('_')
         .map(Exponent::from_str)
         .collect::<Result<Vec<_>, _>>()
-        .ok()?;
+        .or(Err("Unable to parse key"))?;
     CangwuTemperament::from_ets_and_key(&limit.pitches, &ets, &key)
+        .map_err(|e| e.to_string())
 }
 
 fn regular_temperament_search(
@@ -306,16 +333,10 @@ fn regular_temperament_search(
     ek_adjusted: Cents,
     n_results: usize,
 ) -> Result<(), String> {
-    let dimension = limit.pitches.len();
     let ek =
         ek_adjusted * 12e2 / limit.pitches.last().ok_or("no harmonics")?;
-    let safety = if dimension < 100 {
-        40
-    } else {
-        4 * (dimension as f64).sqrt().floor() as usize
-    };
-    let mappings =
-        get_equal_temperaments(&limit.pitches, ek, n_results + safety);
+    let mut search = Search::new(&limit.pitches, ek, n_results);
+    let ets = search.next().ok_or("Search stopped early")?;
     let list = web
         .emptied_element("temperament-list")
         .ok_or("Couldn't find list for results")?;
@@ -324,22 +345,13 @@ fn regular_temperament_search(
         web,
         &list,
         &limit,
-        mappings.iter().take(n_results),
+        ets.iter().map(|mapping| &mapping[0]),
     )
     .or(Err("Failed to display equal temperaments"))?;
 
-    let mut rts = map(|mapping| vec![mapping.clone()], &mappings);
-    for rank in 2..dimension {
-        rts = higher_rank_search(
-            &limit.pitches,
-            &mappings,
-            &rts,
-            ek,
-            n_results + if rank == dimension - 1 { 0 } else { safety },
-        );
+    for (rts, rank) in search.zip(2..) {
         if !rts.is_empty() {
-            let visible_rts = rts.iter().take(n_results);
-            show_regular_temperaments(web, &list, &limit, visible_rts, rank)
+            show_regular_temperaments(web, &list, &limit, rts.iter(), rank)
                 .or(Err("Failed to display regular temperaments"))?
         }
     }
@@ -429,14 +441,11 @@ fn unison_vector_search(
         return Err("No unison vectors supplied".to_string());
     }
     let ek = ek_for_search(&limit.pitches, &uvs) * ek_multiplier;
-    let dimension = limit.pitches.len();
-    let corank = hermite_normal_form(&uvs).len();
-    if corank == dimension {
-        return Err(
-            "Too many unison vectors: whole space matches".to_string()
-        );
-    }
-    let highest_rank = dimension - corank;
+    // The exact temperament class doesn't need a search,
+    // only equal temperaments to name it by
+    let exact = CangwuTemperament::from_unison_vectors(&limit.pitches, &uvs)
+        .map_err(|e| e.to_string())?;
+    let highest_rank = exact.rank().map_err(|e| e.to_string())?;
     let mappings = get_ets_tempering_out(
         &limit.pitches,
         ek,
@@ -455,6 +464,14 @@ fn unison_vector_search(
     }
     let mut rts = map(|mapping| vec![mapping.clone()], &mappings);
     for rank in 2..(highest_rank + 1) {
+        // Show the exact class if the ETs found so far can span it
+        if rank == highest_rank
+            && let Some(ets) = exact.spanning_ets(&mappings)
+        {
+            show_regular_temperaments(web, &list, &limit, [ets].iter(), rank)
+                .or(Err("Failed to display regular temperaments"))?;
+            break;
+        }
         rts = higher_rank_search(
             &limit.pitches,
             &mappings,
@@ -612,6 +629,48 @@ fn write_float_row(
     Ok(())
 }
 
+/// Compare the optimal tunings with each weighting
+fn show_weightings(
+    web: &WebContext,
+    table: &Element,
+    limit: &PrimeLimit,
+    mapping: &Mapping,
+) -> Exceptionable {
+    let head = web.new_or_emptied_element(table, "thead")?;
+    let row = web.document.create_element("tr")?;
+    let headings = ["Weighting", "Complexity", "Error"];
+    let prime_headings = limit.headings.iter().map(String::as_str);
+    for heading in headings.into_iter().chain(prime_headings) {
+        let cell = web.document.create_element("th")?;
+        cell.set_text_content(Some(heading));
+        row.append_child(&cell)?;
+    }
+    head.append_child(&row)?;
+    table.append_child(&head)?;
+
+    let body = web.new_or_emptied_element(table, "tbody")?;
+    for weighting in Weighting::NAMED {
+        let rt = TETemperament::weighted(&limit.pitches, mapping, weighting)
+            .map_err(|e| e.to_string())?;
+        let row = web.document.create_element("tr")?;
+        let cell = web.document.create_element("td")?;
+        cell.set_text_content(Some(rt.weighting.name()));
+        row.append_child(&cell)?;
+        let stats =
+            [format!("{:.6}", rt.complexity()), format!("{:.6}", rt.error())];
+        let tuning_map = rt.tuning_map();
+        let pitches = tuning_map.iter().map(|pitch| format!("{:.3}", pitch));
+        for text in stats.into_iter().chain(pitches) {
+            let cell = web.document.create_element("td")?;
+            cell.set_text_content(Some(&text));
+            row.append_child(&cell)?;
+        }
+        body.append_child(&row)?;
+    }
+    table.append_child(&body)?;
+    Ok(())
+}
+
 fn show_regular_temperaments<'a>(
     web: &WebContext,
     list: &Element,
@@ -724,6 +783,7 @@ fn show_et(
     web: &WebContext,
     limit: &PrimeLimit,
     mapping: Mapping,
+    norm: Option<f64>,
 ) -> Exceptionable {
     let rt = TETemperament::new(&limit.pitches, &mapping);
 
@@ -804,6 +864,23 @@ fn show_et(
         }
     }
 
+    // and the Tp fields for whatever norm was asked for
+    if let Some(rt) = tp_temperament(web, limit, &mapping, norm) {
+        if let Some(table) = web.element("et-tp-tuning-map") {
+            write_headings(web, &table, limit)?;
+            write_float_row(web, &table, &rt.tuning_map(), 3)?;
+        }
+
+        if let Some(table) = web.element("et-tp-mistunings") {
+            write_headings(web, &table, limit)?;
+            write_float_row(web, &table, &rt.mistunings(), 4)?;
+        }
+
+        if let Some(field) = web.element("et-tp-error") {
+            field.set_text_content(Some(&format!("{:.6}", rt.error())));
+        }
+    }
+
     web.set_body_class("show-et");
     if let Some(result) = web.element("equal-temperament") {
         result.scroll_into_view();
@@ -816,6 +893,7 @@ fn show_rt(
     web: &WebContext,
     limit: &PrimeLimit,
     mapping: Mapping,
+    norm: Option<f64>,
 ) -> Exceptionable {
     let rt = TETemperament::new(&limit.pitches, &mapping);
 
@@ -827,7 +905,7 @@ fn show_rt(
         write_mapping_matrix(web, &table, limit, mapping.iter())?;
     }
 
-    let redmap = rt.reduced_mapping();
+    let redmap = rt.reduced_mapping().map_err(|e| e.to_string())?;
     if let Some(table) = web.element("rt-redmap") {
         write_mapping_matrix(web, &table, limit, redmap.iter())?;
     }
@@ -873,11 +951,18 @@ fn show_rt(
         list_unison_vectors(web, limit, &rt, &field)?;
     }
 
+    if let Some(table) = web.element("rt-weightings") {
+        show_weightings(web, &table, limit, &mapping)?;
+    }
+
     if let Some(field) = web.element("error") {
         field.set_text_content(Some(&format!("{:.6}", rt.adjusted_error())));
     }
 
     let potential_top_rt = TOPTemperament::new(&limit.pitches, &mapping);
+    let potential_cte_rt =
+        ConstrainedTemperament::cte(&limit.pitches, &mapping);
+    let tp_rt = tp_temperament(web, limit, &mapping, norm);
     if let Some(field) = web.emptied_element("rt-scala-files") {
         let steps: ETMap = rt.mapping().iter().map(|row| row[0]).collect();
         let temperament_name =
@@ -890,11 +975,7 @@ fn show_rt(
         ));
         field.append_child(&headers)?;
         show_scala_files(web, &field, &rt, &steps, &temperament_name, "TE")?;
-        let pote_rt = TETemperament {
-            plimit: rt.plimit(),
-            melody: rt.melody.clone(),
-            tuning: rt.unstretched_tuning(),
-        };
+        let pote_rt = rt.with_tuning(rt.unstretched_tuning());
         show_scala_files(
             web,
             &field,
@@ -903,6 +984,16 @@ fn show_rt(
             &temperament_name,
             "POTE",
         )?;
+        if let Ok(ref cte_rt) = potential_cte_rt {
+            show_scala_files(
+                web,
+                &field,
+                cte_rt,
+                &steps,
+                &temperament_name,
+                "CTE",
+            )?;
+        }
         if let Ok(ref top_rt) = potential_top_rt {
             show_scala_files(
                 web,
@@ -912,11 +1003,8 @@ fn show_rt(
                 &temperament_name,
                 "TOP",
             )?;
-            let unstretched_rt = TETemperament {
-                plimit: top_rt.plimit(),
-                melody: top_rt.melody.clone(),
-                tuning: top_rt.unstretched_tuning(),
-            };
+            let unstretched_rt =
+                top_rt.with_tuning(top_rt.unstretched_tuning());
             show_scala_files(
                 web,
                 &field,
@@ -926,6 +1014,16 @@ fn show_rt(
                 "Unstretched",
             )?;
         }
+        if let Some(ref tp_rt) = tp_rt {
+            show_scala_files(
+                web,
+                &field,
+                tp_rt,
+                &steps,
+                &temperament_name,
+                &format!("T{}", tp_rt.norm),
+            )?;
+        }
     }
 
     let ets = map(|et| et_name(limit, et), &rt.melody);
@@ -949,7 +1047,9 @@ fn show_rt(
     }
 
     // Make another RT object to get the generator tunings
-    let rt = TETemperament::new(&limit.pitches, &redmap);
+    // with musically meaningful generators
+    let mingen = rt.mingen().map_err(|e| e.to_string())?;
+    let rt = TETemperament::new(&limit.pitches, &mingen.mapping);
     if let Some(table) = web.element("rt-generators") {
         write_float_row(web, &table, &rt.tuning, 4)?;
     }
@@ -963,6 +1063,37 @@ fn show_rt(
         result.scroll_into_view();
     }
 
+    // CTE has pure octaves without stretching
+    match potential_cte_rt {
+        Ok(rt) => {
+            if let Some(table) = web.element("rt-cte-steps") {
+                write_float_row(web, &table, &rt.tuning, 4)?;
+            }
+
+            if let Some(table) = web.element("rt-cte-tuning-map") {
+                write_headings(web, &table, limit)?;
+                write_float_row(web, &table, &rt.tuning_map(), 3)?;
+            }
+
+            if let Some(table) = web.element("rt-cte-mistunings") {
+                write_headings(web, &table, limit)?;
+                write_float_row(web, &table, &rt.mistunings(), 4)?;
+            }
+
+            if let Some(field) = web.element("rt-cte-error") {
+                field.set_text_content(Some(&format!("{:.6}", rt.error())));
+            }
+
+            // The same generators as the TE tuning
+            if let Some(sizes) = mingen.generator_sizes(&rt.tuning_map())
+                && let Some(table) = web.element("rt-cte-generators")
+            {
+                write_float_row(web, &table, &sizes, 4)?;
+            }
+        }
+        Err(_) => web.log_error("Failed to calculate CTE tuning"),
+    }
+
     // Now do it all again with TOP
     if let Ok(ref rt) = potential_top_rt {
         if let Some(table) = web.element("rt-top-steps") {
@@ -1001,7 +1132,7 @@ fn show_rt(
     }
 
     // Now another RT object for TOP family generator tunings
-    if let Ok(rt) = TOPTemperament::new(&limit.pitches, &redmap) {
+    if let Ok(rt) = TOPTemperament::new(&limit.pitches, &mingen.mapping) {
         if let Some(table) = web.element("rt-top-generators") {
             write_float_row(web, &table, &rt.tuning, 4)?;
         }
@@ -1012,16 +1143,66 @@ fn show_rt(
     } else {
         web.log_error("Failed to calculate TOP generator tuning");
     }
+
+    // Tp for the norm that was asked for
+    if let Some(ref rt) = tp_rt {
+        if let Some(table) = web.element("rt-tp-steps") {
+            write_float_row(web, &table, &rt.tuning, 4)?;
+        }
+
+        if let Some(table) = web.element("rt-tp-tuning-map") {
+            write_headings(web, &table, limit)?;
+            write_float_row(web, &table, &rt.tuning_map(), 3)?;
+        }
+
+        if let Some(table) = web.element("rt-tp-mistunings") {
+            write_headings(web, &table, limit)?;
+            write_float_row(web, &table, &rt.mistunings(), 4)?;
+        }
+
+        if let Some(field) = web.element("rt-tp-complexity") {
+            let text = format!("{:.6}", rt.complexity());
+            field.set_text_content(Some(&text));
+        }
+
+        if let Some(field) = web.element("rt-tp-error") {
+            field.set_text_content(Some(&format!("{:.6}", rt.error())));
+        }
+
+        // The same generators as the TE tuning
+        if let Some(sizes) = mingen.generator_sizes(&rt.tuning_map())
+            && let Some(table) = web.element("rt-tp-generators")
+        {
+            write_float_row(web, &table, &sizes, 4)?;
+        }
+    }
     Ok(())
 }
 
+/// The Tp tuning for the norm, if there is one,
+/// with an error logged if it fails
+fn tp_temperament<'a>(
+    web: &WebContext,
+    limit: &'a PrimeLimit,
+    mapping: &[ETMap],
+    norm: Option<f64>,
+) -> Option<TpTemperament<'a>> {
+    match TpTemperament::new(&limit.pitches, mapping, norm?) {
+        Ok(rt) => Some(rt),
+        Err(e) => {
+            web.log_error(&e.to_string());
+            None
+        }
+    }
+}
+
 fn list_unison_vectors(
     web: &WebContext,
     limit: &PrimeLimit,
     rt: &TETemperament,
     field: &Element,
 ) -> Exceptionable {
-    let rank = rt.rank();
+    let rank = rt.rank().map_err(|e| e.to_string())?;
     let dimension = limit.pitches.len();
     let list = web.document.create_element("ul")?;
     let uvs = if dimension < 12 {
@@ -1030,6 +1211,7 @@ fn list_unison_vectors(
     } else {
         // The full search is to inefficient in these reaches
         unison_vector_basis(&limit.pitches, &rt.melody)
+            .map_err(|e| e.to_string())?
     };
     for uv in uvs {
         let item = web.document.create_element("li")?;
@@ -1065,12 +1247,36 @@ fn show_scala_files(
     line.append_child(&entry)?;
     for &n_notes in steps {
         let entry = web.document.create_element("td")?;
+        let scale = rt.fokker_block_scale(n_notes, temperament_name);
         let new_link = web.make_download_link(
             &n_notes.to_string(),
-            &format!("{}_{}.scl", &temperament_name, n_notes),
-            &rt.scala_file(n_notes, temperament_name),
+            &TuningFormat::Scala.filename(&scale),
+            TuningFormat::Scala.contents(&scale).as_bytes(),
         )?;
         entry.append_child(&new_link)?;
+        for format in [TuningFormat::AnaMark, TuningFormat::Ableton] {
+            entry.append_with_str_1(" ")?;
+            let format_link = web.make_download_link(
+                format.extension(),
+                &format.filename(&scale),
+                format.contents(&scale).as_bytes(),
+            )?;
+            entry.append_child(&format_link)?;
+        }
+        entry.append_with_str_1(" ")?;
+        let kbm_link = web.make_download_link(
+            "kbm",
+            &format!("{}_{}.kbm", &temperament_name, n_notes),
+            kbm_file(n_notes, &scale.keyboard).as_bytes(),
+        )?;
+        entry.append_child(&kbm_link)?;
+        entry.append_with_str_1(" ")?;
+        let mts_link = web.make_download_link(
+            "syx",
+            &format!("{}_{}.syx", &temperament_name, n_notes),
+            &bulk_dump(&scale.pitches, &scale.name, &scale.keyboard),
+        )?;
+        entry.append_child(&mts_link)?;
         line.append_child(&entry)?;
     }
     table.append_child(&line)?;
@@ -1085,12 +1291,3 @@ fn rt_name(limit: &PrimeLimit, rt: &TETemperament) -> String {
         octaves.join(" & ")
     }
 }
-
-fn et_name(limit: &PrimeLimit, et: &ETMap) -> String {
-    assert!(!et.is_empty());
-    if ambiguous_et(&limit.pitches, et) {
-        warted_et_name(limit, et)
-    } else {
-        et[0].to_string()
-    }
-}
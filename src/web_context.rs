@@ -137,9 +137,9 @@ impl WebContext {
         &self,
         label: &str,
         filename: &str,
-        contents: &str,
+        contents: &[u8],
     ) -> Result<Element, JsValue> {
-        let bytes = Uint8Array::from(contents.as_bytes());
+        let bytes = Uint8Array::from(contents);
         let parts = Array::new();
         parts.push(&bytes.buffer());
 
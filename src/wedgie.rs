@@ -0,0 +1,279 @@
+//! Exterior algebra for temperament classes
+//!
+//! The wedgie of a rank r mapping is its multival:
+//! the r×r minors taken from the columns (primes)
+//! in lexicographic order.  For 5-limit meantone that's ⟨⟨1 4 4]].
+//! Commas wedge together to make a multimonzo in the same way,
+//! and the two are duals.
+//! Normalized wedgies have no contorsion and a positive first entry,
+//! so they identify temperament classes.
+
+use super::lattice::{IntMatrix, determinant, smith_normal_form};
+use super::uv::{mapping_from_unison_vectors, saturated_kernel_basis};
+use super::{ETMap, Error, Exponent, Mapping};
+use num_integer::Integer;
+
+/// Coefficients of a multivector in lexicographic order
+/// of the combinations of basis vectors
+pub type Wedgie = Vec<i64>;
+
+/// Wedge product of the vectors, either vals or monzos.
+/// A multival from vals, or a multimonzo from monzos.
+/// Error::Overflow if an element doesn't fit in the Wedgie.
+pub fn wedge(vectors: &[ETMap]) -> Result<Wedgie, Error> {
+    let grade = vectors.len();
+    let dimension = vectors.first().map_or(0, Vec::len);
+    debug_assert!(vectors.iter().all(|v| v.len() == dimension));
+    combinations(dimension, grade)
+        .iter()
+        .map(|columns| {
+            let minor: IntMatrix = vectors
+                .iter()
+                .map(|v| columns.iter().map(|&i| i128::from(v[i])).collect())
+                .collect();
+            i64::try_from(determinant(&minor)?).map_err(|_| Error::Overflow)
+        })
+        .collect()
+}
+
+/// The multimonzo of a comma basis
+pub fn multimonzo(commas: &[ETMap]) -> Result<Wedgie, Error> {
+    wedge(commas)
+}
+
+/// Convert between a multival and a multimonzo.
+/// The grade is the rank for a multival
+/// or the number of commas for a multimonzo.
+/// Signs follow the Hodge star so that the dual of a normalized
+/// wedgie only needs the sign normalizing.
+pub fn dual(
+    dimension: usize,
+    grade: usize,
+    multivector: &[i64],
+) -> Result<Wedgie, Error> {
+    let combos = combinations(dimension, grade);
+    if grade > dimension || combos.len() != multivector.len() {
+        return Err(Error::BadWedgie {
+            dimension,
+            rank: grade,
+            length: multivector.len(),
+        });
+    }
+    let complements = combinations(dimension, dimension - grade);
+    Ok(complements
+        .iter()
+        .map(|complement| {
+            let indices: Vec<usize> =
+                (0..dimension).filter(|i| !complement.contains(i)).collect();
+            let position = combos
+                .iter()
+                .position(|combo| *combo == indices)
+                .expect("Complement not found");
+            // The sign of the permutation indices ++ complement
+            let inversions: usize = indices
+                .iter()
+                .map(|i| complement.iter().filter(|&j| j < i).count())
+                .sum();
+            if inversions.is_multiple_of(2) {
+                multivector[position]
+            } else {
+                -multivector[position]
+            }
+        })
+        .collect())
+}
+
+/// Remove contorsion (a common factor) and make the first
+/// non-zero entry positive
+pub fn normalize(multivector: &[i64]) -> Wedgie {
+    let gcd = multivector.iter().fold(0, |a, b| a.gcd(b));
+    if gcd == 0 {
+        return multivector.to_vec();
+    }
+    let sign = match multivector.iter().find(|&&x| x != 0) {
+        Some(&first) if first < 0 => -1,
+        _ => 1,
+    };
+    multivector.iter().map(|x| sign * x / gcd).collect()
+}
+
+/// The common factor of a multivector.
+/// More than 1 means the mapping is contorted
+/// (or the commas have torsion).
+pub fn contorsion(multivector: &[i64]) -> i64 {
+    multivector.iter().fold(0, |a, b| a.gcd(b))
+}
+
+/// How many times bigger the lattice of the saturated vectors is
+/// than the lattice of the vectors: the contorsion of a mapping
+/// or the torsion of a list of unison vectors.
+/// Dependent vectors are fine and 1 means no contorsion or torsion.
+pub fn saturation_index(vectors: &[ETMap]) -> Result<i64, Error> {
+    // The product of the invariant factors is the greatest common
+    // divisor of the maximal minors, so the contorsion of the wedgie
+    smith_normal_form(vectors)?.invariant_factors().into_iter().try_fold(
+        1,
+        |index: i64, factor| {
+            i64::try_from(factor)
+                .ok()
+                .and_then(|factor| index.checked_mul(factor))
+                .ok_or(Error::Overflow)
+        },
+    )
+}
+
+/// Reconstruct a reduced mapping (without contorsion)
+/// from a multival of the given rank
+pub fn mapping_from_wedgie(
+    dimension: usize,
+    rank: usize,
+    wedgie: &[i64],
+) -> Result<Mapping, Error> {
+    let bad_wedgie =
+        || Error::BadWedgie { dimension, rank, length: wedgie.len() };
+    if rank == 0
+        || rank > dimension
+        || combinations(dimension, rank).len() != wedgie.len()
+        || wedgie.iter().all(|&x| x == 0)
+    {
+        return Err(bad_wedgie());
+    }
+    // Contracting with each (rank-1)-combination gives a vector
+    // in the span of the vals
+    let combos = combinations(dimension, rank);
+    let mut vectors = Vec::new();
+    for fixed in combinations(dimension, rank - 1) {
+        let mut vector = vec![0; dimension];
+        for (j, element) in vector.iter_mut().enumerate() {
+            if fixed.contains(&j) {
+                continue;
+            }
+            let mut indices = fixed.clone();
+            indices.push(j);
+            indices.sort_unstable();
+            let position = combos
+                .iter()
+                .position(|combo| *combo == indices)
+                .expect("Combination not found");
+            let before = fixed.iter().filter(|&&i| i < j).count();
+            let value = if before.is_multiple_of(2) {
+                wedgie[position]
+            } else {
+                -wedgie[position]
+            };
+            *element = Exponent::try_from(value).or(Err(bad_wedgie()))?;
+        }
+        if vector.iter().any(|&x| x != 0) {
+            vectors.push(vector);
+        }
+    }
+    // The vals that are orthogonal to everything
+    // orthogonal to these vectors
+    let commas = saturated_kernel_basis(&vectors)?;
+    let mapping = mapping_from_unison_vectors(dimension, &commas)?;
+    // The contractions only span the right space
+    // if the wedgie came from a mapping
+    if mapping.len() != rank
+        || normalize(&wedge(&mapping)?) != normalize(wedgie)
+    {
+        return Err(Error::NotDecomposable);
+    }
+    Ok(mapping)
+}
+
+/// All ways of choosing k indices from 0..n, in lexicographic order
+pub fn combinations(n: usize, k: usize) -> Vec<Vec<usize>> {
+    if k > n {
+        return vec![];
+    }
+    let mut result = Vec::new();
+    let mut combo: Vec<usize> = (0..k).collect();
+    loop {
+        result.push(combo.clone());
+        // Find the rightmost index that can move right
+        let Some(i) = (0..k).rev().find(|&i| combo[i] < n - k + i) else {
+            return result;
+        };
+        combo[i] += 1;
+        for j in (i + 1)..k {
+            combo[j] = combo[j - 1] + 1;
+        }
+    }
+}
+
+#[test]
+fn meantone_wedgie() {
+    let meantone = vec![vec![12, 19, 28], vec![19, 30, 44]];
+    assert_eq!(wedge(&meantone).unwrap(), vec![-1, -4, -4]);
+    assert_eq!(normalize(&wedge(&meantone).unwrap()), vec![1, 4, 4]);
+    assert_eq!(multimonzo(&[vec![-4, 4, -1]]).unwrap(), vec![-4, 4, -1]);
+    assert_eq!(dual(3, 2, &[1, 4, 4]), Ok(vec![4, -4, 1]));
+    assert_eq!(dual(3, 1, &[4, -4, 1]), Ok(vec![1, 4, 4]));
+}
+
+#[test]
+fn wedgie_overflow() {
+    let big = Exponent::MAX;
+    let scaled = vec![vec![big, 0, 0], vec![0, big, 0], vec![0, 0, big]];
+    assert_eq!(wedge(&scaled), Err(Error::Overflow));
+    assert_eq!(wedge(&scaled[1..]), Ok(vec![0, 0, i64::from(big).pow(2)]));
+}
+
+#[test]
+fn marvel_wedgie() {
+    let marvel = vec![
+        vec![22, 35, 51, 62, 76],
+        vec![31, 49, 72, 87, 107],
+        vec![41, 65, 95, 115, 142],
+    ];
+    let wedgie = normalize(&wedge(&marvel).unwrap());
+    assert_eq!(wedgie, vec![1, 2, -3, -2, 1, -4, -5, 12, 9, -19]);
+    let commas = saturated_kernel_basis(&marvel).unwrap();
+    assert_eq!(
+        normalize(&multimonzo(&commas).unwrap()),
+        normalize(&dual(5, 3, &wedgie).unwrap())
+    );
+    let mapping = mapping_from_wedgie(5, 3, &wedgie).expect("no mapping");
+    assert_eq!(mapping, super::hermite_normal_form(&marvel));
+}
+
+#[test]
+fn contorted_wedgie() {
+    let contorted = vec![vec![24, 38, 56], vec![38, 60, 88]];
+    assert_eq!(contorsion(&wedge(&contorted).unwrap()), 4);
+    assert_eq!(normalize(&wedge(&contorted).unwrap()), vec![1, 4, 4]);
+    assert_eq!(
+        mapping_from_wedgie(3, 2, &wedge(&contorted).unwrap()),
+        Ok(super::hermite_normal_form(&[vec![12, 19, 28], vec![19, 30, 44]]))
+    );
+}
+
+#[test]
+fn bad_wedgies() {
+    assert_eq!(
+        mapping_from_wedgie(5, 2, &[1, 2, 3]),
+        Err(Error::BadWedgie { dimension: 5, rank: 2, length: 3 })
+    );
+    // Not the wedge product of anything
+    assert_eq!(
+        mapping_from_wedgie(4, 2, &[1, 0, 0, 0, 0, 1]),
+        Err(Error::NotDecomposable)
+    );
+}
+
+#[test]
+fn combinations_in_order() {
+    assert_eq!(combinations(4, 2).len(), 6);
+    assert_eq!(combinations(4, 2)[..3], [vec![0, 1], vec![0, 2], vec![0, 3]]);
+    assert_eq!(combinations(3, 0), vec![Vec::<usize>::new()]);
+    assert_eq!(combinations(2, 3), Vec::<Vec<usize>>::new());
+}
+
+#[test]
+fn saturation_indices() {
+    let doubled = vec![vec![24, 38, 56], vec![19, 30, 44]];
+    assert_eq!(saturation_index(&doubled).unwrap(), 2);
+    let dependent = vec![vec![12, 19, 28], vec![24, 38, 56]];
+    assert_eq!(saturation_index(&dependent).unwrap(), 1);
+    assert_eq!(saturation_index(&[vec![0, 0, 0]]).unwrap(), 1);
+}
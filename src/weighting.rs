@@ -0,0 +1,170 @@
+//! How much each prime counts for tunings and complexity
+//!
+//! Weighted mappings have a column for each equal temperament
+//! (or generator) and a row for each prime, like nalgebra
+//! wants them.  The weighted just intonation point goes with them
+//! so that tunings and badness work with any weighting.
+
+extern crate nalgebra as na;
+use na::{DMatrix, DVector};
+
+use super::{Cents, ETMap, Error, map};
+
+#[derive(Clone, Debug, Default, PartialEq)]
+#[cfg_attr(feature = "serde", derive(serde::Serialize, serde::Deserialize))]
+#[cfg_attr(feature = "serde", serde(rename_all = "lowercase"))]
+pub enum Weighting {
+    /// Divide by the size of the prime (1200/p for p in cents)
+    #[default]
+    Tenney,
+    /// Tenney weighting with part of the mean taken out,
+    /// the Euclidean form of Weil (log-max) height
+    Weil,
+    /// No weighting, so all primes count the same
+    Frobenius,
+    /// Divide by the prime itself (not its logarithm)
+    /// like Benedetti height, scaled to agree with Tenney on the octave
+    Benedetti,
+    /// A weight for each prime, where 1200/p is Tenney
+    Custom(Vec<f64>),
+}
+
+impl Weighting {
+    /// The weightings that don't need any more information
+    pub const NAMED: [Weighting; 4] = [
+        Weighting::Tenney,
+        Weighting::Weil,
+        Weighting::Frobenius,
+        Weighting::Benedetti,
+    ];
+
+    pub fn from_name(name: &str) -> Option<Self> {
+        Weighting::NAMED
+            .into_iter()
+            .find(|weighting| weighting.name() == name.to_lowercase())
+    }
+
+    pub fn name(&self) -> &'static str {
+        match self {
+            Weighting::Tenney => "tenney",
+            Weighting::Weil => "weil",
+            Weighting::Frobenius => "frobenius",
+            Weighting::Benedetti => "benedetti",
+            Weighting::Custom(_) => "custom",
+        }
+    }
+
+    /// Make sure there's a weight for each prime
+    pub fn check(&self, plimit: &[Cents]) -> Result<(), Error> {
+        match self {
+            Weighting::Custom(weights) if weights.len() != plimit.len() => {
+                Err(Error::DimensionMismatch {
+                    expected: plimit.len(),
+                    found: weights.len(),
+                })
+            }
+            _ => Ok(()),
+        }
+    }
+
+    /// What each prime's mapping gets multiplied by
+    pub fn weights(&self, plimit: &[Cents]) -> Vec<f64> {
+        match self {
+            Weighting::Tenney | Weighting::Weil => {
+                map(|p| 1200.0 / p, plimit)
+            }
+            Weighting::Frobenius => vec![1.0; plimit.len()],
+            Weighting::Benedetti => {
+                map(|p| 2.0 / (p / 1200.0).exp2(), plimit)
+            }
+            Weighting::Custom(weights) => {
+                debug_assert_eq!(weights.len(), plimit.len());
+                weights.clone()
+            }
+        }
+    }
+
+    /// Mapping with a column for each ET and a row for each prime
+    pub fn weight_mapping(
+        &self,
+        mapping: &[ETMap],
+        plimit: &[Cents],
+    ) -> DMatrix<f64> {
+        let rank = mapping.len();
+        let dimension = plimit.len();
+        debug_assert!(rank > 0);
+        let flattened = mapping.iter().flat_map(|m| m.iter()).cloned();
+        let mapping = DMatrix::from_iterator(dimension, rank, flattened);
+        let weights = DVector::from_vec(self.weights(plimit));
+        let mut weighted = mapping.map(f64::from);
+        for mut column in weighted.column_iter_mut() {
+            column.component_mul_assign(&weights);
+        }
+        self.transform(weighted)
+    }
+
+    /// Weighted sizes of the primes in cents
+    pub fn weighted_ji(&self, plimit: &[Cents]) -> DVector<f64> {
+        let weights = DVector::from_vec(self.weights(plimit));
+        let ji = DVector::from_column_slice(plimit).component_mul(&weights);
+        self.transform(DMatrix::from_columns(&[ji])).column(0).into()
+    }
+
+    /// Anything that happens after the weights are applied
+    fn transform(&self, mut weighted: DMatrix<f64>) -> DMatrix<f64> {
+        if *self == Weighting::Weil {
+            // Taking this much of the mean out of each column
+            // gives the dual of the norm with an extra dimension
+            // for the sum of the weighted exponents
+            let dimension = weighted.nrows() as f64;
+            let scaling = 1.0 - 1.0 / (dimension + 1.0).sqrt();
+            for mut column in weighted.column_iter_mut() {
+                let offset = scaling * column.mean();
+                column.add_scalar_mut(-offset);
+            }
+        }
+        weighted
+    }
+}
+
+#[test]
+fn tenney_weights() {
+    let limit5 = super::PrimeLimit::new(5);
+    let weighted = Weighting::Tenney
+        .weight_mapping(&[vec![12, 19, 28]], &limit5.pitches);
+    assert_eq!(weighted.shape(), (3, 1));
+    assert_eq!(weighted[0], 12.0);
+    super::assert_between!(11.98, weighted[1], 11.99);
+    super::assert_between!(12.05, weighted[2], 12.06);
+    let ji = Weighting::Tenney.weighted_ji(&limit5.pitches);
+    for &size in ji.iter() {
+        super::assert_between!(1199.999, size, 1200.001);
+    }
+}
+
+#[test]
+fn other_weights() {
+    let limit5 = super::PrimeLimit::new(5);
+    let benedetti = Weighting::Benedetti.weights(&limit5.pitches);
+    super::assert_between!(0.9999, benedetti[0], 1.0001);
+    super::assert_between!(0.6666, benedetti[1], 0.6667);
+    super::assert_between!(0.3999, benedetti[2], 0.4001);
+    assert_eq!(Weighting::Frobenius.weights(&limit5.pitches), vec![1.0; 3]);
+    let custom = Weighting::Custom(vec![1.0, 2.0, 3.0]);
+    let weighted =
+        custom.weight_mapping(&[vec![12, 19, 28]], &limit5.pitches);
+    assert_eq!(weighted.as_slice(), &[12.0, 38.0, 84.0]);
+    assert!(custom.check(&limit5.pitches).is_ok());
+    assert!(matches!(
+        Weighting::Custom(vec![1.0]).check(&limit5.pitches),
+        Err(super::Error::DimensionMismatch { expected: 3, found: 1 }),
+    ));
+    // Weil takes out part of the mean
+    let weil =
+        Weighting::Weil.weight_mapping(&[vec![1, 1, 1]], &limit5.pitches);
+    let tenney =
+        Weighting::Tenney.weight_mapping(&[vec![1, 1, 1]], &limit5.pitches);
+    assert!(weil.norm() < tenney.norm());
+    assert_eq!(Weighting::from_name("Weil"), Some(Weighting::Weil));
+    assert_eq!(Weighting::from_name("custom"), None);
+}